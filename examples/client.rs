@@ -14,7 +14,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // If you are going to create many `SkySparkClient`s,
     // reuse the same `reqwest::Client` in each `SkySparkClient`
     // by using the `SkySparkClient::new_with_client` function instead.
-    let mut client = SkySparkClient::new(url, "username", "p4ssw0rd").await?;
+    let client = SkySparkClient::new(url, "username", "p4ssw0rd").await?;
 
     let sites_grid = client.eval("readAll(site)").await?;
 
@@ -7,9 +7,35 @@ pub(crate) trait HaystackUrl {
     fn filetypes_url(&self) -> Url;
     fn his_read_url(&self) -> Url;
     fn his_write_url(&self) -> Url;
+    fn invoke_action_url(&self) -> Url;
     fn nav_url(&self) -> Url;
     fn ops_url(&self) -> Url;
+    fn point_write_url(&self) -> Url;
     fn read_url(&self) -> Url;
+    fn watch_poll_url(&self) -> Url;
+    fn watch_sub_url(&self) -> Url;
+    fn watch_unsub_url(&self) -> Url;
+}
+
+/// The encodings which can be used for grids sent to and received from
+/// a Haystack server.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum GridFormat {
+    /// Hayson, the JSON encoding for Haystack grids (`application/json`).
+    #[default]
+    Json,
+    /// The Zinc encoding for Haystack grids (`text/zinc`).
+    Zinc,
+}
+
+impl GridFormat {
+    /// Return the MIME type for this grid format.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Zinc => "text/zinc",
+        }
+    }
 }
 
 /// Represents the different time range queries that can be sent
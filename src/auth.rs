@@ -1,13 +1,33 @@
+//! Authentication with Haystack servers.
+//!
+//! The client sends a `HELLO` request, and the server responds with the
+//! authentication scheme it wants the client to use in its
+//! `WWW-Authenticate` header. The client then uses the matching
+//! `AuthScheme` to obtain an auth token. The SCRAM, HMAC, PLAINTEXT and
+//! Basic schemes are built in, and other schemes can be added by
+//! implementing `AuthScheme`.
+
+use crate::{
+    Credentials, HttpRequest, HttpResponse, Transport, TransportError,
+};
+use futures::future::BoxFuture;
 use hmac::{Hmac, Mac, NewMac};
 use pbkdf2::pbkdf2;
-use reqwest::{Client, Response};
+use reqwest::Method;
+use sha1::Sha1;
 use sha2::{Digest, Sha256, Sha512};
 use std::convert::From;
+use std::fmt::Debug;
 use std::num::NonZeroU32;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
 use thiserror::Error;
+use url::Url;
+use zeroize::Zeroizing;
 
+#[derive(Clone, Copy, Eq, PartialEq)]
 enum HashFunction {
+    Sha1,
     Sha256,
     Sha512,
 }
@@ -16,6 +36,7 @@ impl FromStr for HashFunction {
     type Err = ParseHashFunctionError;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s {
+            "SHA-1" => Ok(HashFunction::Sha1),
             "SHA-256" => Ok(HashFunction::Sha256),
             "SHA-512" => Ok(HashFunction::Sha512),
             _ => Err(ParseHashFunctionError {
@@ -25,6 +46,7 @@ impl FromStr for HashFunction {
     }
 }
 
+type HmacSha1 = Hmac<Sha1>;
 type HmacSha256 = Hmac<Sha256>;
 type HmacSha512 = Hmac<Sha512>;
 
@@ -38,6 +60,9 @@ impl HashFunction {
         let mut dk = vec![0u8; self.dk_len()];
 
         match self {
+            Self::Sha1 => {
+                pbkdf2::<HmacSha1>(key, salt, iterations.into(), &mut dk);
+            }
             Self::Sha256 => {
                 pbkdf2::<HmacSha256>(key, salt, iterations.into(), &mut dk);
             }
@@ -51,6 +76,7 @@ impl HashFunction {
     /// Return the dk length, in bytes.
     fn dk_len(&self) -> usize {
         match self {
+            Self::Sha1 => 20,
             Self::Sha256 => 32,
             Self::Sha512 => 64,
         }
@@ -60,6 +86,14 @@ impl HashFunction {
     /// `key_value`.
     fn hmac_sign(&self, key_value: &[u8], data: &[u8]) -> Vec<u8> {
         match self {
+            Self::Sha1 => {
+                let mut mac = HmacSha1::new_from_slice(key_value)
+                    .expect("expected key length to be valid");
+                mac.update(data);
+                let result = mac.finalize();
+                let bytes = result.into_bytes();
+                bytes.into_iter().collect()
+            }
             Self::Sha256 => {
                 let mut mac = HmacSha256::new_from_slice(key_value)
                     .expect("expected key length to be valid");
@@ -81,6 +115,10 @@ impl HashFunction {
 
     fn digest(&self, input: &[u8]) -> Vec<u8> {
         match self {
+            Self::Sha1 => {
+                let bytes = Sha1::digest(input);
+                bytes.into_iter().collect()
+            }
             Self::Sha256 => {
                 let bytes = Sha256::digest(input);
                 bytes.into_iter().collect()
@@ -93,26 +131,634 @@ impl HashFunction {
     }
 }
 
+/// The maximum number of salted passwords kept in a client's cache.
+pub(crate) const SALTED_PASSWORD_CACHE_CAPACITY: usize = 32;
+
+/// The inputs which determine a salted password, other than the password.
+#[derive(Clone, Eq, PartialEq)]
+struct SaltedPasswordKey {
+    username: String,
+    salt: Vec<u8>,
+    iterations: NonZeroU32,
+    hash_fn: HashFunction,
+}
+
+impl SaltedPasswordKey {
+    fn new(
+        username: &str,
+        salt: &[u8],
+        iterations: NonZeroU32,
+        hash_fn: HashFunction,
+    ) -> Self {
+        Self {
+            username: username.to_owned(),
+            salt: salt.to_vec(),
+            iterations,
+            hash_fn,
+        }
+    }
+}
+
+/// A bounded cache of salted passwords, so that re-authenticating with the
+/// same server does not repeat the expensive PBKDF2 key derivation, as
+/// suggested by RFC 5802. Each client has its own cache.
+///
+/// The password is not part of the cache key. Instead, the cache is cleared
+/// whenever it is used with credentials which differ from the credentials
+/// it was last used with. The least recently used salted password is
+/// evicted when the cache is full, and salted passwords are zeroed in
+/// memory when they are evicted.
+pub(crate) struct SaltedPasswordCache {
+    capacity: usize,
+    credentials: Option<Credentials>,
+    /// Ordered from the least recently used to the most recently used.
+    entries: Vec<(SaltedPasswordKey, Zeroizing<Vec<u8>>)>,
+}
+
+impl SaltedPasswordCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            credentials: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Clear the cache if it was last used with different credentials.
+    fn use_credentials(&mut self, username: &str, password: &str) {
+        let is_same_credentials = match &self.credentials {
+            Some(credentials) => {
+                credentials.username() == username
+                    && constant_time_eq(
+                        credentials.password().as_bytes(),
+                        password.as_bytes(),
+                    )
+            }
+            None => false,
+        };
+
+        if !is_same_credentials {
+            self.entries.clear();
+            self.credentials = Some(Credentials::new(username, password));
+        }
+    }
+
+    fn get(&mut self, key: &SaltedPasswordKey) -> Option<Zeroizing<Vec<u8>>> {
+        let index = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(index);
+        let salted_password = entry.1.clone();
+        self.entries.push(entry);
+        Some(salted_password)
+    }
+
+    fn insert(
+        &mut self,
+        key: SaltedPasswordKey,
+        salted_password: Zeroizing<Vec<u8>>,
+    ) {
+        self.remove(&key);
+        if self.entries.len() >= self.capacity && !self.entries.is_empty() {
+            self.entries.remove(0);
+        }
+        if self.capacity > 0 {
+            self.entries.push((key, salted_password));
+        }
+    }
+
+    fn remove(&mut self, key: &SaltedPasswordKey) {
+        self.entries.retain(|(k, _)| k != key);
+    }
+}
+
+/// Return true if the byte strings are equal, taking an amount of time
+/// which does not depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn lock_cache(
+    cache: &Mutex<SaltedPasswordCache>,
+) -> MutexGuard<'_, SaltedPasswordCache> {
+    // The cache is always left in a valid state, so it can still be used
+    // if another thread panicked while holding the lock:
+    cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Return the salted password for the given key, using the cached salted
+/// password if there is a cache which contains it.
+fn salted_password(
+    cache: Option<&Mutex<SaltedPasswordCache>>,
+    key: &SaltedPasswordKey,
+    password: &str,
+) -> Zeroizing<Vec<u8>> {
+    if let Some(salted_password) = cache.and_then(|c| lock_cache(c).get(key)) {
+        return salted_password;
+    }
+
+    let salted_password = Zeroizing::new(key.hash_fn.pbkdf2(
+        password.as_bytes(),
+        &key.salt,
+        key.iterations,
+    ));
+    if let Some(cache) = cache {
+        lock_cache(cache).insert(key.clone(), salted_password.clone());
+    }
+    salted_password
+}
+
+/// Remove a salted password which may have been rejected by the server
+/// from the cache, so it is not used again.
+fn forget_salted_password(
+    cache: Option<&Mutex<SaltedPasswordCache>>,
+    key: &SaltedPasswordKey,
+) {
+    if let Some(cache) = cache {
+        lock_cache(cache).remove(key);
+    }
+}
+
 type AuthResult<T> = std::result::Result<T, InternalAuthError>;
 
+/// Obtain a new auth token from the server, using the authentication scheme
+/// requested by the server. Salted passwords are cached in
+/// `salted_password_cache`, if there is one. The arguments are skipped by
+/// the tracing span, so the password is never logged.
+#[tracing::instrument(
+    name = "haystack_auth",
+    skip_all,
+    fields(auth_url = %url, scheme = tracing::field::Empty)
+)]
 pub(crate) async fn new_auth_token(
-    client: &Client,
-    url: &str,
+    transport: &dyn Transport,
+    url: &Url,
     username: &str,
     password: &str,
+    auth_schemes: &AuthSchemes,
+    salted_password_cache: Option<&Mutex<SaltedPasswordCache>>,
+) -> Result<String, AuthError> {
+    if let Some(cache) = salted_password_cache {
+        lock_cache(cache).use_credentials(username, password);
+    }
+
+    let challenge = hello(transport, url, username).await?;
+    let auth_scheme = auth_schemes.negotiate(&challenge)?;
+    tracing::Span::current().record("scheme", auth_scheme.name());
+
+    let request = AuthRequest {
+        transport,
+        url,
+        username,
+        password,
+        challenge: &challenge,
+        salted_password_cache,
+    };
+    auth_scheme.authenticate(request).await
+}
+
+/// An authentication scheme, which obtains an auth token from a server.
+pub trait AuthScheme: Debug + Send + Sync {
+    /// Return the name of the scheme, as it appears in the server's
+    /// `WWW-Authenticate` header, for example `SCRAM`. Names are compared
+    /// case-insensitively.
+    fn name(&self) -> &str;
+
+    /// Return true if the scheme exposes the password to anyone who can
+    /// read the requests, such as the PLAINTEXT and Basic schemes. Insecure
+    /// schemes are only used if `AuthSchemes::allow_insecure` is enabled.
+    fn is_insecure(&self) -> bool {
+        false
+    }
+
+    /// Obtain an auth token from the server.
+    fn authenticate<'a>(
+        &'a self,
+        request: AuthRequest<'a>,
+    ) -> BoxFuture<'a, Result<String, AuthError>>;
+}
+
+/// The information available to an `AuthScheme` when it authenticates.
+#[derive(Clone, Copy)]
+pub struct AuthRequest<'a> {
+    transport: &'a dyn Transport,
+    url: &'a Url,
+    username: &'a str,
+    password: &'a str,
+    challenge: &'a AuthChallenge,
+    salted_password_cache: Option<&'a Mutex<SaltedPasswordCache>>,
+}
+
+impl<'a> AuthRequest<'a> {
+    /// Return the transport used to send requests.
+    pub fn transport(&self) -> &'a dyn Transport {
+        self.transport
+    }
+
+    /// Return the URL which authentication requests are sent to.
+    pub fn url(&self) -> &'a Url {
+        self.url
+    }
+
+    /// Return the username.
+    pub fn username(&self) -> &'a str {
+        self.username
+    }
+
+    /// Return the password.
+    pub fn password(&self) -> &'a str {
+        self.password
+    }
+
+    /// Return the server's response to the `HELLO` request.
+    pub fn challenge(&self) -> &'a AuthChallenge {
+        self.challenge
+    }
+}
+
+impl Debug for AuthRequest<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthRequest")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("challenge", &self.challenge)
+            .finish()
+    }
+}
+
+/// The scheme and parameters from the `WWW-Authenticate` header that the
+/// server returned in response to the `HELLO` request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuthChallenge {
+    scheme: String,
+    params: Vec<(String, String)>,
+}
+
+impl AuthChallenge {
+    /// Parse a `WWW-Authenticate` header value, such as
+    /// `SCRAM handshakeToken=abc, hash=SHA-256`.
+    fn parse(header_value: &str) -> Self {
+        let header_value = header_value.trim();
+        let (scheme, params) = match header_value.find(' ') {
+            Some(index) => header_value.split_at(index),
+            None => (header_value, ""),
+        };
+
+        let params = split_challenge_params(params)
+            .into_iter()
+            .filter_map(|param| {
+                let param = param.trim();
+                let (name, value) = param.split_at(param.find('=')?);
+                Some((name.trim().to_owned(), value[1..].trim().to_owned()))
+            })
+            .collect();
+
+        Self {
+            scheme: scheme.to_owned(),
+            params,
+        }
+    }
+
+    /// Return the name of the scheme requested by the server.
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Return the value of the parameter with the given name.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param_name, _)| param_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn required_param(
+        &self,
+        name: &str,
+    ) -> Result<String, KeyValuePairParseError> {
+        self.param(name)
+            .map(|value| value.to_owned())
+            .ok_or_else(|| {
+                let msg = format!("missing key {} in key-value pairs", name);
+                KeyValuePairParseError { msg }
+            })
+    }
+}
+
+/// Split the parameters of a `WWW-Authenticate` header value on the commas
+/// which are not inside quoted strings, removing the quotes and escape
+/// characters of any quoted strings.
+fn split_challenge_params(params: &str) -> Vec<String> {
+    let mut split_params = Vec::new();
+    let mut param = String::new();
+    let mut is_quoted = false;
+    let mut chars = params.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => is_quoted = !is_quoted,
+            '\\' if is_quoted => param.extend(chars.next()),
+            ',' if !is_quoted => split_params.push(std::mem::take(&mut param)),
+            c => param.push(c),
+        }
+    }
+
+    split_params.push(param);
+    split_params
+}
+
+/// The authentication schemes a client may use. The server chooses which
+/// scheme is used.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use raystack::auth::AuthSchemes;
+/// use raystack::SkySparkClientBuilder;
+/// use url::Url;
+///
+/// // Allow the PLAINTEXT and Basic schemes, for a development server:
+/// let auth_schemes = AuthSchemes::default().allow_insecure(true);
+/// let url = Url::parse("http://localhost:8080/api/demo/").unwrap();
+/// let client = SkySparkClientBuilder::new(url, "username", "p4ssw0rd")
+///     .auth_schemes(auth_schemes)
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct AuthSchemes {
+    schemes: Vec<Arc<dyn AuthScheme>>,
+    allow_insecure: bool,
+}
+
+impl AuthSchemes {
+    /// Create a new `AuthSchemes` with no schemes.
+    pub fn new() -> Self {
+        Self {
+            schemes: Vec::new(),
+            allow_insecure: false,
+        }
+    }
+
+    /// Add a scheme, replacing any existing scheme with the same name.
+    pub fn with_scheme(mut self, scheme: Arc<dyn AuthScheme>) -> Self {
+        self.schemes.retain(|existing| {
+            !existing.name().eq_ignore_ascii_case(scheme.name())
+        });
+        self.schemes.push(scheme);
+        self
+    }
+
+    /// Set whether insecure schemes, which expose the password, may be
+    /// used. This is disabled by default.
+    pub fn allow_insecure(mut self, allow_insecure: bool) -> Self {
+        self.allow_insecure = allow_insecure;
+        self
+    }
+
+    /// Return true if insecure schemes may be used.
+    pub fn allows_insecure(&self) -> bool {
+        self.allow_insecure
+    }
+
+    /// Return the scheme requested by the server.
+    fn negotiate(
+        &self,
+        challenge: &AuthChallenge,
+    ) -> Result<&dyn AuthScheme, AuthError> {
+        let scheme_name = challenge.scheme();
+        let scheme = self
+            .schemes
+            .iter()
+            .find(|scheme| scheme.name().eq_ignore_ascii_case(scheme_name))
+            .ok_or_else(|| AuthError::UnsupportedScheme {
+                scheme: challenge.scheme().to_owned(),
+            })?;
+
+        if scheme.is_insecure() && !self.allow_insecure {
+            Err(AuthError::InsecureScheme {
+                scheme: challenge.scheme().to_owned(),
+            })
+        } else {
+            Ok(scheme.as_ref())
+        }
+    }
+}
+
+impl Default for AuthSchemes {
+    /// The SCRAM, HMAC, PLAINTEXT and Basic schemes, where the insecure
+    /// PLAINTEXT and Basic schemes are not allowed.
+    fn default() -> Self {
+        Self::new()
+            .with_scheme(Arc::new(ScramAuth))
+            .with_scheme(Arc::new(HmacAuth))
+            .with_scheme(Arc::new(PlaintextAuth))
+            .with_scheme(Arc::new(BasicAuth))
+    }
+}
+
+/// The SCRAM scheme (RFC 5802), used by SkySpark 3 and Haystack 4 servers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ScramAuth;
+
+impl AuthScheme for ScramAuth {
+    fn name(&self) -> &str {
+        "SCRAM"
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        request: AuthRequest<'a>,
+    ) -> BoxFuture<'a, Result<String, AuthError>> {
+        Box::pin(async move { Ok(scram_auth_token(request).await?) })
+    }
+}
+
+/// The HMAC scheme, used by older SkySpark servers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HmacAuth;
+
+impl AuthScheme for HmacAuth {
+    fn name(&self) -> &str {
+        "HMAC"
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        request: AuthRequest<'a>,
+    ) -> BoxFuture<'a, Result<String, AuthError>> {
+        Box::pin(async move { Ok(hmac_auth_token(request).await?) })
+    }
+}
+
+/// The PLAINTEXT scheme, which sends the password to the server without
+/// protecting it. This scheme is insecure.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlaintextAuth;
+
+impl AuthScheme for PlaintextAuth {
+    fn name(&self) -> &str {
+        "PLAINTEXT"
+    }
+
+    fn is_insecure(&self) -> bool {
+        true
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        request: AuthRequest<'a>,
+    ) -> BoxFuture<'a, Result<String, AuthError>> {
+        Box::pin(async move {
+            let auth_header_value = format!(
+                "PLAINTEXT username={}, password={}",
+                base64_encode_no_padding(request.username()),
+                base64_encode_no_padding(request.password())
+            );
+            Ok(final_auth_token(request, auth_header_value).await?)
+        })
+    }
+}
+
+/// The HTTP Basic scheme (RFC 7617), which sends the password to the
+/// server without protecting it. This scheme is insecure.
+///
+/// Servers which use this scheme do not issue auth tokens, and expect the
+/// credentials in every request. Instead of an auth token, this scheme
+/// returns the `Authorization` header value containing the encoded
+/// credentials, which the client sends in place of a bearer token. Like
+/// any other auth token, it is saved by the client's `TokenStore`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BasicAuth;
+
+impl AuthScheme for BasicAuth {
+    fn name(&self) -> &str {
+        "Basic"
+    }
+
+    fn is_insecure(&self) -> bool {
+        true
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        request: AuthRequest<'a>,
+    ) -> BoxFuture<'a, Result<String, AuthError>> {
+        Box::pin(async move { Ok(basic_auth_header_value(request).await?) })
+    }
+}
+
+/// Return the `Authorization` header value for the Basic scheme, after
+/// checking that the server accepts the credentials.
+async fn basic_auth_header_value(
+    request: AuthRequest<'_>,
+) -> AuthResult<String> {
+    let credentials = format!("{}:{}", request.username(), request.password());
+    let auth_header_value = format!("Basic {}", base64::encode(credentials));
+    let res = send_auth_request(
+        request.transport(),
+        request.url(),
+        &auth_header_value,
+    )
+    .await?;
+
+    if res.status().is_success() {
+        Ok(auth_header_value)
+    } else {
+        Err(HandshakeError::UnexpectedStatus(res.status()).into())
+    }
+}
+
+/// Return the `Authorization` header value which authorizes a request with
+/// the given auth token. The auth tokens returned by `BasicAuth` are
+/// already header values, and are sent unchanged.
+pub(crate) fn authorization_header_value(auth_token: &str) -> String {
+    if is_basic_auth_header_value(auth_token) {
+        auth_token.to_owned()
+    } else {
+        format!("BEARER authToken={}", auth_token)
+    }
+}
+
+/// Return true if the value is an `Authorization` header value for the
+/// Basic scheme.
+fn is_basic_auth_header_value(value: &str) -> bool {
+    let prefix = "Basic ";
+    match value.get(..prefix.len()) {
+        Some(value_prefix) => value_prefix.eq_ignore_ascii_case(prefix),
+        None => false,
+    }
+}
+
+/// Send the final request of a scheme, and return the auth token from the
+/// server's `Authentication-Info` header.
+async fn final_auth_token(
+    request: AuthRequest<'_>,
+    auth_header_value: String,
 ) -> AuthResult<String> {
-    let auth_session_cfg = auth_session_config(client, url, username).await?;
+    let res = send_auth_request(
+        request.transport(),
+        request.url(),
+        &auth_header_value,
+    )
+    .await?;
 
-    let AuthSessionConfig {
-        handshake_token,
-        hash_fn,
-    } = auth_session_cfg;
+    let auth_info =
+        parse_key_value_pairs_from_header("authentication-info", res)?;
+    Ok(auth_info.get("authToken")?)
+}
+
+async fn hmac_auth_token(request: AuthRequest<'_>) -> AuthResult<String> {
+    let challenge = request.challenge();
+    let hash_fn = challenge
+        .required_param("hash")?
+        .parse::<HashFunction>()
+        .map_err(HandshakeError::from)?;
+    let salt = challenge.required_param("salt")?;
+    let nonce = challenge.required_param("nonce")?;
+
+    let hmac = hash_fn.hmac_sign(
+        request.password().as_bytes(),
+        format!("{}:{}", request.username(), salt).as_bytes(),
+    );
+    let digest_input = format!("{}:{}", base64::encode(hmac), nonce);
+    let digest = base64::encode(hash_fn.digest(digest_input.as_bytes()));
+
+    let mut auth_header_value =
+        format!("HMAC digest={}, nonce={}", digest, nonce);
+    if let Some(handshake_token) = challenge.param("handshakeToken") {
+        auth_header_value
+            .push_str(&format!(", handshakeToken={}", handshake_token));
+    }
+
+    final_auth_token(request, auth_header_value).await
+}
+
+async fn scram_auth_token(request: AuthRequest<'_>) -> AuthResult<String> {
+    let AuthRequest {
+        transport,
+        url,
+        username,
+        password,
+        challenge,
+        salted_password_cache,
+    } = request;
+
+    let handshake_token = challenge.required_param("handshakeToken")?;
+    let hash_fn = challenge
+        .required_param("hash")?
+        .parse::<HashFunction>()
+        .map_err(HandshakeError::from)?;
 
     let nonce = generate_nonce().map_err(HandshakeError::from)?;
     let client_first_msg = format!("n={},r={}", username, nonce);
 
     let server_first_res = server_first_response(
-        client,
+        transport,
         url,
         &handshake_token,
         &client_first_msg,
@@ -135,11 +781,14 @@ pub(crate) async fn new_auth_token(
         })
     })?;
 
-    let salted_password = hash_fn.pbkdf2(
-        password.as_bytes(),
+    let salted_password_key = SaltedPasswordKey::new(
+        username,
         &decoded_server_salt,
         server_iterations,
+        hash_fn,
     );
+    let salted_password =
+        salted_password(salted_password_cache, &salted_password_key, password);
 
     let client_final_no_proof = format!("c=biws,r={}", server_nonce);
     let auth_msg = format!(
@@ -147,8 +796,8 @@ pub(crate) async fn new_auth_token(
         client_first_msg, server_first_msg, client_final_no_proof
     );
 
-    let server_second_res = server_second_response(
-        client,
+    let server_second_res = match server_second_response(
+        transport,
         url,
         &handshake_token,
         &auth_msg,
@@ -156,7 +805,14 @@ pub(crate) async fn new_auth_token(
         &client_final_no_proof,
         &hash_fn,
     )
-    .await?;
+    .await
+    {
+        Ok(server_second_res) => server_second_res,
+        Err(err) => {
+            forget_salted_password(salted_password_cache, &salted_password_key);
+            return Err(err);
+        }
+    };
 
     let ServerSecondResponse {
         auth_token,
@@ -167,8 +823,9 @@ pub(crate) async fn new_auth_token(
     {
         Ok(auth_token)
     } else {
+        forget_salted_password(salted_password_cache, &salted_password_key);
         Err(InternalAuthError::ServerValidation {
-            server_id: url.into(),
+            server_id: url.to_string(),
         })
     }
 }
@@ -191,35 +848,36 @@ fn generate_nonce() -> Result<String, GenerateNonceError> {
     Ok(nonce)
 }
 
-struct AuthSessionConfig {
-    handshake_token: String,
-    hash_fn: HashFunction,
+/// Send a request with the given `Authorization` header value to the
+/// authentication URL.
+async fn send_auth_request(
+    transport: &dyn Transport,
+    url: &Url,
+    auth_header_value: &str,
+) -> AuthResult<HttpResponse> {
+    let request = HttpRequest::new(Method::GET, url.clone())
+        .try_with_header("Authorization", auth_header_value)?;
+    Ok(transport.send(request).await?)
 }
 
-async fn auth_session_config(
-    client: &Client,
-    url: &str,
+/// Send the `HELLO` request, and return the authentication scheme and
+/// parameters requested by the server.
+async fn hello(
+    transport: &dyn Transport,
+    url: &Url,
     username: &str,
-) -> AuthResult<AuthSessionConfig> {
+) -> AuthResult<AuthChallenge> {
     let base64_username = base64_encode_no_padding(username);
     let auth_header_value = format!("HELLO username={}", base64_username);
-    let res = client
-        .get(url)
-        .header("Authorization", auth_header_value)
-        .send()
-        .await?;
+    let res = send_auth_request(transport, url, &auth_header_value).await?;
 
-    let kvps = parse_key_value_pairs_from_header("www-authenticate", res)?;
-    let handshake_token = kvps.get("handshakeToken")?;
-    let hash_fn = kvps
-        .get("hash")?
-        .parse::<HashFunction>()
-        .map_err(HandshakeError::from)?;
-
-    Ok(AuthSessionConfig {
-        handshake_token,
-        hash_fn,
-    })
+    let header = "www-authenticate";
+    let header_value = res.headers().get(header).ok_or_else(|| {
+        let msg = format!("missing HTTP header {}", header);
+        KeyValuePairParseError { msg }
+    })?;
+    let header_value = header_value.to_str().map_err(HandshakeError::from)?;
+    Ok(AuthChallenge::parse(header_value))
 }
 
 struct ServerFirstResponse {
@@ -230,8 +888,8 @@ struct ServerFirstResponse {
 }
 
 async fn server_first_response(
-    client: &Client,
-    url: &str,
+    transport: &dyn Transport,
+    url: &Url,
     handshake_token: &str,
     client_first_msg: &str,
 ) -> AuthResult<ServerFirstResponse> {
@@ -240,11 +898,7 @@ async fn server_first_response(
         handshake_token,
         base64_encode_no_padding(&client_first_msg)
     );
-    let res = client
-        .get(url)
-        .header("Authorization", auth_header_value)
-        .send()
-        .await?;
+    let res = send_auth_request(transport, url, &auth_header_value).await?;
 
     let kvps = parse_key_value_pairs_from_header("www-authenticate", res)?;
     let data_base64 = kvps.get("data")?;
@@ -274,8 +928,8 @@ struct ServerSecondResponse {
 }
 
 async fn server_second_response(
-    client: &Client,
-    url: &str,
+    transport: &dyn Transport,
+    url: &Url,
     handshake_token: &str,
     auth_msg: &str,
     salted_password: &[u8],
@@ -306,11 +960,7 @@ async fn server_second_response(
         handshake_token,
         base64_encode_no_padding(&client_final)
     );
-    let res = client
-        .get(url)
-        .header("Authorization", auth_header_value)
-        .send()
-        .await?;
+    let res = send_auth_request(transport, url, &auth_header_value).await?;
 
     let auth_info =
         parse_key_value_pairs_from_header("authentication-info", res)?;
@@ -345,7 +995,7 @@ fn is_server_valid(
 
 fn parse_key_value_pairs_from_header(
     header: &str,
-    res: Response,
+    res: HttpResponse,
 ) -> Result<KeyValuePairs, KeyValuePairParseError> {
     let header_value = res.headers().get(header).ok_or_else(|| {
         let msg = format!("missing HTTP header {}", header);
@@ -424,15 +1074,26 @@ fn base64_decode_no_padding(s: &str) -> Result<String, Base64DecodeError> {
 /// An error which occurred during the authentication process.
 #[derive(Debug, Error)]
 pub enum AuthError {
-    /// An error which occurred in the underlying HTTP client.
-    #[error("A HTTP client error occurred while authenticating")]
-    Http(#[source] reqwest::Error),
+    /// An error which occurred in the client's `Transport`.
+    #[error("A HTTP transport error occurred while authenticating")]
+    Http(#[source] TransportError),
     /// An error occurred in `raystack` during authentication.
     #[error("An internal error occurred while authenticating")]
     Internal(#[source] Box<dyn std::error::Error + Send + Sync + 'static>),
     /// Error denoting that the server's identity was not valid.
     #[error("Could not validate the identity of the server {server_id}")]
     ServerValidation { server_id: String },
+    /// The credential provider could not provide credentials.
+    #[error("Could not obtain credentials to authenticate with")]
+    Credentials(#[from] crate::CredentialsError),
+    /// The server requested an authentication scheme which the client
+    /// does not support.
+    #[error("The server requested an unsupported auth scheme {scheme}")]
+    UnsupportedScheme { scheme: String },
+    /// The server requested an insecure authentication scheme, which the
+    /// client has not been allowed to use.
+    #[error("The server requested the insecure auth scheme {scheme}")]
+    InsecureScheme { scheme: String },
 }
 
 impl From<InternalAuthError> for AuthError {
@@ -454,8 +1115,8 @@ impl From<InternalAuthError> for AuthError {
 pub(crate) enum InternalAuthError {
     #[error("Error occured while authenticating with the server")]
     Handshake(#[from] HandshakeError),
-    #[error("HTTP client error")]
-    Http(#[from] reqwest::Error),
+    #[error("HTTP transport error")]
+    Http(#[from] TransportError),
     #[error("Could not validate the identity of the server {server_id}")]
     ServerValidation { server_id: String },
 }
@@ -477,6 +1138,8 @@ pub(crate) enum HandshakeError {
     ParseIterations(#[from] ParseIterationsError),
     #[error("Could not decode a string as UTF8")]
     Utf8Decode(#[from] std::string::FromUtf8Error),
+    #[error("The server responded with the unexpected status {0}")]
+    UnexpectedStatus(reqwest::StatusCode),
 }
 
 #[derive(Debug, Error)]
@@ -518,3 +1181,199 @@ pub(crate) struct Base64DecodeError {
 pub(crate) struct GenerateNonceError {
     msg: String,
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        authorization_header_value, constant_time_eq, AuthChallenge, AuthError,
+        AuthRequest, AuthScheme, AuthSchemes, HashFunction,
+        SaltedPasswordCache, SaltedPasswordKey,
+    };
+    use crate::ReqwestTransport;
+    use futures::future::BoxFuture;
+    use std::num::NonZeroU32;
+    use std::sync::Arc;
+    use url::Url;
+    use zeroize::Zeroizing;
+
+    #[test]
+    fn parse_challenge() {
+        let challenge =
+            AuthChallenge::parse("SCRAM handshakeToken=abc=, hash=SHA-256");
+        assert_eq!(challenge.scheme(), "SCRAM");
+        assert_eq!(challenge.param("handshakeToken"), Some("abc="));
+        assert_eq!(challenge.param("HASH"), Some("SHA-256"));
+        assert_eq!(challenge.param("salt"), None);
+
+        let challenge = AuthChallenge::parse(r#"Basic realm="SkySpark""#);
+        assert_eq!(challenge.scheme(), "Basic");
+        assert_eq!(challenge.param("realm"), Some("SkySpark"));
+
+        let challenge = AuthChallenge::parse(
+            r#"Custom realm="a,b", msg="say \"hi\"", hash=SHA-256"#,
+        );
+        assert_eq!(challenge.scheme(), "Custom");
+        assert_eq!(challenge.param("realm"), Some("a,b"));
+        assert_eq!(challenge.param("msg"), Some(r#"say "hi""#));
+        assert_eq!(challenge.param("hash"), Some("SHA-256"));
+
+        let challenge = AuthChallenge::parse("PLAINTEXT");
+        assert_eq!(challenge.scheme(), "PLAINTEXT");
+        assert_eq!(challenge.param("username"), None);
+    }
+
+    #[test]
+    fn negotiate_secure_schemes() {
+        let auth_schemes = AuthSchemes::default();
+        let scram = AuthChallenge::parse("scram hash=SHA-256");
+        assert_eq!(auth_schemes.negotiate(&scram).unwrap().name(), "SCRAM");
+        let hmac = AuthChallenge::parse("HMAC hash=SHA-1, salt=a, nonce=b");
+        assert_eq!(auth_schemes.negotiate(&hmac).unwrap().name(), "HMAC");
+    }
+
+    #[test]
+    fn insecure_schemes_are_opt_in() {
+        let plaintext = AuthChallenge::parse("PLAINTEXT");
+        let basic = AuthChallenge::parse(r#"Basic realm="SkySpark""#);
+
+        let auth_schemes = AuthSchemes::default();
+        assert!(!auth_schemes.allows_insecure());
+        assert!(matches!(
+            auth_schemes.negotiate(&plaintext),
+            Err(AuthError::InsecureScheme { .. })
+        ));
+        assert!(matches!(
+            auth_schemes.negotiate(&basic),
+            Err(AuthError::InsecureScheme { .. })
+        ));
+
+        let auth_schemes = auth_schemes.allow_insecure(true);
+        assert!(auth_schemes.negotiate(&plaintext).is_ok());
+        assert!(auth_schemes.negotiate(&basic).is_ok());
+    }
+
+    #[test]
+    fn unsupported_scheme_fails() {
+        let challenge = AuthChallenge::parse("Negotiate");
+        let auth_schemes = AuthSchemes::default();
+        let result = auth_schemes.negotiate(&challenge);
+        assert!(matches!(
+            result,
+            Err(AuthError::UnsupportedScheme { scheme }) if scheme == "Negotiate"
+        ));
+
+        let scram = AuthChallenge::parse("SCRAM hash=SHA-256");
+        assert!(AuthSchemes::new().negotiate(&scram).is_err());
+    }
+
+    #[test]
+    fn authorization_header_values() {
+        assert_eq!(
+            authorization_header_value("token"),
+            "BEARER authToken=token"
+        );
+        assert_eq!(
+            authorization_header_value("Basic bmFtZTpwYXNzd29yZA=="),
+            "Basic bmFtZTpwYXNzd29yZA=="
+        );
+    }
+
+    #[derive(Debug)]
+    struct FixedTokenAuth;
+
+    impl AuthScheme for FixedTokenAuth {
+        fn name(&self) -> &str {
+            "SCRAM"
+        }
+
+        fn authenticate<'a>(
+            &'a self,
+            _request: AuthRequest<'a>,
+        ) -> BoxFuture<'a, Result<String, AuthError>> {
+            Box::pin(async { Ok("fixedtoken".to_owned()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_scheme_replaces_built_in_scheme() {
+        let auth_schemes =
+            AuthSchemes::default().with_scheme(Arc::new(FixedTokenAuth));
+        let challenge = AuthChallenge::parse("SCRAM hash=SHA-256");
+        let scheme = auth_schemes.negotiate(&challenge).unwrap();
+        let transport = ReqwestTransport::default();
+        let url = Url::parse("http://localhost:1/ui").unwrap();
+        let request = AuthRequest {
+            transport: &transport,
+            url: &url,
+            username: "name",
+            password: "s3cr3t",
+            challenge: &challenge,
+            salted_password_cache: None,
+        };
+        assert!(!format!("{:?}", request).contains("s3cr3t"));
+        assert_eq!(scheme.authenticate(request).await.unwrap(), "fixedtoken");
+    }
+
+    fn key(username: &str) -> SaltedPasswordKey {
+        let iterations = NonZeroU32::new(1000).unwrap();
+        let hash_fn = HashFunction::Sha256;
+        SaltedPasswordKey::new(username, b"salt", iterations, hash_fn)
+    }
+
+    fn value(byte: u8) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(vec![byte; 4])
+    }
+
+    #[test]
+    fn salted_password_cache_is_cleared_when_credentials_change() {
+        let mut cache = SaltedPasswordCache::new(4);
+        cache.use_credentials("name", "password1");
+        cache.insert(key("name"), value(1));
+
+        cache.use_credentials("name", "password1");
+        assert_eq!(cache.get(&key("name")), Some(value(1)));
+        assert_eq!(cache.get(&key("other")), None);
+
+        cache.use_credentials("name", "password2");
+        assert_eq!(cache.get(&key("name")), None);
+
+        cache.insert(key("name"), value(2));
+        cache.use_credentials("other", "password2");
+        assert_eq!(cache.get(&key("name")), None);
+
+        cache.insert(key("other"), value(3));
+        cache.remove(&key("other"));
+        assert_eq!(cache.get(&key("other")), None);
+    }
+
+    #[test]
+    fn constant_time_eq_compares_bytes() {
+        assert!(constant_time_eq(b"password", b"password"));
+        assert!(!constant_time_eq(b"password", b"passw0rd"));
+        assert!(!constant_time_eq(b"password", b"password1"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn salted_password_cache_evicts_least_recently_used() {
+        let mut cache = SaltedPasswordCache::new(2);
+        cache.insert(key("a"), value(1));
+        cache.insert(key("b"), value(2));
+
+        // Use "a", so "b" becomes the least recently used:
+        assert!(cache.get(&key("a")).is_some());
+        cache.insert(key("c"), value(3));
+
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("c")).is_some());
+    }
+
+    #[test]
+    fn salted_password_cache_with_no_capacity() {
+        let mut cache = SaltedPasswordCache::new(0);
+        cache.insert(key("a"), value(1));
+        assert!(cache.get(&key("a")).is_none());
+    }
+}
@@ -0,0 +1,790 @@
+use crate::auth::{AuthError, AuthSchemes};
+use crate::limiter::RequestLimiter;
+use crate::{
+    skyspark_auth_url, validate_base_url, validate_project_api_url, ClientAuth,
+    CredentialProvider, GridFormat, HaystackClient, NewSkySparkClientError,
+    ReqwestTransport, RetryPolicy, SkySparkClient, StaticCredentials,
+    TokenStore, Transport,
+};
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, Proxy};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Define the given setters on `HaystackClientBuilder`, and setters with the
+/// same names and documentation on `SkySparkClientBuilder` which call them,
+/// so that both builders always have the same options.
+macro_rules! builder_setters {
+    ($(
+        $(#[$attr:meta])*
+        pub fn $name:ident(
+            mut $self_:ident,
+            $arg:ident: $arg_type:ty $(,)?
+        ) -> Self $body:block
+    )*) => {
+        impl HaystackClientBuilder {
+            $(
+                $(#[$attr])*
+                pub fn $name(mut $self_, $arg: $arg_type) -> Self $body
+            )*
+        }
+
+        impl SkySparkClientBuilder {
+            $(
+                $(#[$attr])*
+                pub fn $name(mut self, $arg: $arg_type) -> Self {
+                    self.builder = self.builder.$name($arg);
+                    self
+                }
+            )*
+        }
+    };
+}
+
+/// A builder for creating a `HaystackClient` with a customized underlying
+/// HTTP client.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use raystack::HaystackClientBuilder;
+/// use std::time::Duration;
+/// use url::Url;
+///
+/// let base_url = Url::parse("https://haystack.company.com/haystack/").unwrap();
+/// let auth_url = Url::parse("https://haystack.company.com/auth").unwrap();
+/// let client = HaystackClientBuilder::new(base_url, "username", "p4ssw0rd")
+///     .auth_url(auth_url)
+///     .request_timeout(Duration::from_secs(30))
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct HaystackClientBuilder {
+    base_url: Url,
+    auth_url: Option<Url>,
+    credential_provider: Arc<dyn CredentialProvider>,
+    auth_token: Option<String>,
+    token_store: Option<Arc<dyn TokenStore>>,
+    auth_schemes: AuthSchemes,
+    grid_format: GridFormat,
+    retry_policy: RetryPolicy,
+    request_timeout: Option<Duration>,
+    total_timeout: Option<Duration>,
+    max_in_flight: Option<usize>,
+    max_requests_per_second: Option<u32>,
+    user_agent: Option<String>,
+    default_headers: HeaderMap,
+    accept_invalid_certs: bool,
+    root_certificates: Vec<Certificate>,
+    proxy: Option<Proxy>,
+    transport: Option<Arc<dyn Transport>>,
+}
+
+impl HaystackClientBuilder {
+    /// Create a new `HaystackClientBuilder`, which will create a client
+    /// that sends Haystack ops to URLs relative to the given base URL,
+    /// using the given username and password.
+    pub fn new(base_url: Url, username: &str, password: &str) -> Self {
+        let credential_provider =
+            Arc::new(StaticCredentials::new(username, password));
+        Self::with_credential_provider(base_url, credential_provider)
+    }
+
+    /// Create a new `HaystackClientBuilder`, which will create a client
+    /// that sends Haystack ops to URLs relative to the given base URL,
+    /// using the credentials from the given credential provider.
+    pub fn with_credential_provider(
+        base_url: Url,
+        credential_provider: Arc<dyn CredentialProvider>,
+    ) -> Self {
+        Self {
+            base_url,
+            auth_url: None,
+            credential_provider,
+            auth_token: None,
+            token_store: None,
+            auth_schemes: AuthSchemes::default(),
+            grid_format: GridFormat::default(),
+            retry_policy: RetryPolicy::default(),
+            request_timeout: None,
+            total_timeout: None,
+            max_in_flight: None,
+            max_requests_per_second: None,
+            user_agent: None,
+            default_headers: HeaderMap::new(),
+            accept_invalid_certs: false,
+            root_certificates: Vec::new(),
+            proxy: None,
+            transport: None,
+        }
+    }
+
+    /// Set the URL which authentication requests are sent to. By default,
+    /// this is the URL of the `about` op.
+    pub fn auth_url(mut self, auth_url: Url) -> Self {
+        self.auth_url = Some(auth_url);
+        self
+    }
+
+    /// Create the `HaystackClient`. Unless an existing auth token was
+    /// given or found in the token store, this authenticates with
+    /// the server.
+    pub async fn build(
+        self,
+    ) -> std::result::Result<HaystackClient, NewSkySparkClientError> {
+        let has_auth_token = self.auth_token.is_some();
+        let total_timeout = self.total_timeout;
+        let client = self.build_unauthenticated()?;
+        if has_auth_token {
+            return Ok(client);
+        }
+
+        let auth = &client.auth;
+        let auth_token =
+            match auth.load_stored_auth_token(&client.base_url).await {
+                Some(auth_token) => auth_token,
+                None => {
+                    let limited_transport = client.limited_transport();
+                    let new_auth_token = auth
+                        .new_auth_token(&client.auth_url, &limited_transport);
+                    let auth_token =
+                        within_timeout(new_auth_token, total_timeout).await?;
+                    auth.store_auth_token(&client.base_url, &auth_token).await;
+                    auth_token
+                }
+            };
+        *auth.auth_token.write().await = auth_token;
+        Ok(client)
+    }
+
+    /// Create the `HaystackClient` without sending any requests or using
+    /// the token store, so this can be called outside of an async context.
+    /// The client uses the auth token given to `auth_token`, if there is
+    /// one, and otherwise authenticates when the server rejects its first
+    /// request.
+    pub(crate) fn build_unauthenticated(
+        self,
+    ) -> std::result::Result<HaystackClient, NewSkySparkClientError> {
+        let base_url = validate_base_url(self.base_url)?;
+        let auth_url = match self.auth_url {
+            Some(auth_url) => auth_url,
+            None => base_url
+                .join("about")
+                .expect("since url ends with '/' this should never fail"),
+        };
+
+        let transport: Arc<dyn Transport> = match self.transport {
+            Some(transport) => transport,
+            None => {
+                let mut reqwest_builder = reqwest::Client::builder()
+                    .default_headers(self.default_headers)
+                    .danger_accept_invalid_certs(self.accept_invalid_certs);
+                if let Some(timeout) = self.request_timeout {
+                    reqwest_builder = reqwest_builder.timeout(timeout);
+                }
+                if let Some(user_agent) = self.user_agent {
+                    reqwest_builder = reqwest_builder.user_agent(user_agent);
+                }
+                for certificate in self.root_certificates {
+                    reqwest_builder =
+                        reqwest_builder.add_root_certificate(certificate);
+                }
+                if let Some(proxy) = self.proxy {
+                    reqwest_builder = reqwest_builder.proxy(proxy);
+                }
+                Arc::new(ReqwestTransport::new(reqwest_builder.build()?))
+            }
+        };
+
+        let auth = ClientAuth::new(
+            self.auth_token.unwrap_or_default(),
+            self.credential_provider,
+            self.token_store,
+            self.auth_schemes,
+        );
+
+        let limiter = RequestLimiter::new(
+            self.max_in_flight,
+            self.max_requests_per_second,
+        );
+
+        Ok(HaystackClient {
+            auth: Arc::new(auth),
+            transport,
+            grid_format: self.grid_format,
+            base_url,
+            auth_url,
+            retry_policy: self.retry_policy,
+            total_timeout: self.total_timeout,
+            metrics: Arc::default(),
+            limiter: Arc::new(limiter),
+        })
+    }
+}
+
+/// Wait for the initial authentication of a new client, failing if it does
+/// not complete within the client's total timeout.
+async fn within_timeout<F>(
+    new_auth_token: F,
+    total_timeout: Option<Duration>,
+) -> Result<String, NewSkySparkClientError>
+where
+    F: Future<Output = Result<String, AuthError>>,
+{
+    match total_timeout {
+        Some(timeout) => Ok(tokio::time::timeout(timeout, new_auth_token)
+            .await
+            .map_err(|_| NewSkySparkClientError::timeout(timeout))??),
+        None => Ok(new_auth_token.await?),
+    }
+}
+
+builder_setters! {
+    /// Use an existing auth token, instead of authenticating with the
+    /// server when the client is built. If the server rejects the auth
+    /// token, the client obtains a new auth token using its credentials.
+    pub fn auth_token(mut self, auth_token: &str) -> Self {
+        self.auth_token = Some(auth_token.to_owned());
+        self
+    }
+
+    /// Use a token store to load and save auth tokens. When the client is
+    /// built, an auth token saved in the token store is used instead of
+    /// authenticating with the server. Each new auth token obtained by the
+    /// client is saved to the token store.
+    pub fn token_store(mut self, token_store: Arc<dyn TokenStore>) -> Self {
+        self.token_store = Some(token_store);
+        self
+    }
+
+    /// Set the authentication schemes the client may use when the server
+    /// requests them. By default, the SCRAM and HMAC schemes may be used.
+    pub fn auth_schemes(mut self, auth_schemes: AuthSchemes) -> Self {
+        self.auth_schemes = auth_schemes;
+        self
+    }
+
+    /// Set the format used to encode grids sent to and received from
+    /// the server.
+    pub fn grid_format(mut self, grid_format: GridFormat) -> Self {
+        self.grid_format = grid_format;
+        self
+    }
+
+    /// Set the policy used to retry requests which fail due to transient
+    /// errors.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the maximum amount of time each individual HTTP request may
+    /// take, from when the connection is started until the response body
+    /// has been read.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the maximum amount of time each operation may take, including
+    /// all retries of its requests, any delays between them, and any
+    /// re-authentication. The timeout also applies to the authentication
+    /// performed when the client is built, and to logging out.
+    pub fn total_timeout(mut self, timeout: Duration) -> Self {
+        self.total_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the maximum number of HTTP requests which the client and all of
+    /// its clones may have in progress at once, including retries and
+    /// authentication requests. Further requests wait until an earlier
+    /// request finishes. Time spent waiting counts towards the total
+    /// timeout.
+    ///
+    /// # Panics
+    /// Panics if `max_in_flight` is zero.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        assert_ne!(max_in_flight, 0, "max_in_flight must not be zero");
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    /// Set the maximum number of HTTP requests which the client and all of
+    /// its clones may send each second, including retries and
+    /// authentication requests. Requests are spaced evenly, and further
+    /// requests wait until they can be sent. Time spent waiting counts
+    /// towards the total timeout.
+    ///
+    /// # Panics
+    /// Panics if `max_requests_per_second` is zero.
+    pub fn max_requests_per_second(
+        mut self,
+        max_requests_per_second: u32,
+    ) -> Self {
+        assert_ne!(
+            max_requests_per_second, 0,
+            "max_requests_per_second must not be zero"
+        );
+        self.max_requests_per_second = Some(max_requests_per_second);
+        self
+    }
+
+    /// Set the value of the User-Agent header sent with each request.
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_owned());
+        self
+    }
+
+    /// Set headers which are sent with each request. Headers which are set
+    /// by the client for each request, such as Authorization, take
+    /// precedence over these headers.
+    pub fn default_headers(mut self, headers: HeaderMap) -> Self {
+        self.default_headers = headers;
+        self
+    }
+
+    /// Set whether invalid TLS certificates, such as self-signed
+    /// certificates, are accepted.
+    ///
+    /// # Warning
+    /// Accepting invalid certificates allows any server to impersonate the
+    /// SkySpark server. This should only be used for servers in isolated
+    /// lab environments.
+    pub fn accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Trust the given root certificate, in addition to the system's root
+    /// certificates. This can be called multiple times to add multiple
+    /// certificates.
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Send all requests through the given proxy.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Send requests using the given transport, instead of a
+    /// `ReqwestTransport`. The request timeout, user agent, default
+    /// headers, TLS and proxy settings are only used by the default
+    /// transport, and are ignored if a transport is given.
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+}
+
+/// A builder for creating a `SkySparkClient` with a customized underlying
+/// HTTP client.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use raystack::SkySparkClientBuilder;
+/// use reqwest::Proxy;
+/// use std::time::Duration;
+/// use url::Url;
+///
+/// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
+/// let client = SkySparkClientBuilder::new(url, "username", "p4ssw0rd")
+///     .request_timeout(Duration::from_secs(30))
+///     .total_timeout(Duration::from_secs(120))
+///     .user_agent("plant-monitor/1.0")
+///     .proxy(Proxy::all("http://proxy.company.com:8080").unwrap())
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SkySparkClientBuilder {
+    builder: HaystackClientBuilder,
+}
+
+impl SkySparkClientBuilder {
+    /// Create a new `SkySparkClientBuilder`, which will create a client
+    /// that connects to the given project API URL using the given
+    /// username and password.
+    pub fn new(project_api_url: Url, username: &str, password: &str) -> Self {
+        Self {
+            builder: HaystackClientBuilder::new(
+                project_api_url,
+                username,
+                password,
+            ),
+        }
+    }
+
+    /// Create a new `SkySparkClientBuilder`, which will create a client
+    /// that connects to the given project API URL using the credentials
+    /// from the given credential provider.
+    pub fn with_credential_provider(
+        project_api_url: Url,
+        credential_provider: Arc<dyn CredentialProvider>,
+    ) -> Self {
+        Self {
+            builder: HaystackClientBuilder::with_credential_provider(
+                project_api_url,
+                credential_provider,
+            ),
+        }
+    }
+
+    /// Create the `SkySparkClient`. Unless an existing auth token was
+    /// given or found in the token store, this authenticates with
+    /// the server.
+    pub async fn build(
+        self,
+    ) -> std::result::Result<SkySparkClient, NewSkySparkClientError> {
+        let haystack_client = self.into_haystack_builder()?.build().await?;
+        Ok(SkySparkClient { haystack_client })
+    }
+
+    /// Create the `SkySparkClient` without sending any requests or using
+    /// the token store. See `HaystackClientBuilder::build_unauthenticated`.
+    pub(crate) fn build_unauthenticated(
+        self,
+    ) -> std::result::Result<SkySparkClient, NewSkySparkClientError> {
+        let haystack_client =
+            self.into_haystack_builder()?.build_unauthenticated()?;
+        Ok(SkySparkClient { haystack_client })
+    }
+
+    /// Return the `HaystackClientBuilder`, with the URLs used by a
+    /// SkySpark project.
+    fn into_haystack_builder(
+        mut self,
+    ) -> std::result::Result<HaystackClientBuilder, NewSkySparkClientError>
+    {
+        let project_api_url = validate_project_api_url(self.builder.base_url)?;
+        self.builder.auth_url = Some(skyspark_auth_url(&project_api_url));
+        self.builder.base_url = project_api_url;
+        Ok(self.builder)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HaystackClientBuilder, SkySparkClientBuilder};
+    use crate::auth::AuthError;
+    use crate::{CallbackCredentials, CredentialsError};
+    use crate::{GridFormat, NewSkySparkClientError, RetryPolicy};
+    use crate::{MemoryTokenStore, TokenStore};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use url::Url;
+
+    #[tokio::test]
+    async fn build_with_auth_token_does_not_authenticate() {
+        let url = Url::parse("http://localhost:1/api/proj").unwrap();
+        let client = SkySparkClientBuilder::new(url, "name", "password")
+            .auth_token("existingtoken")
+            .grid_format(GridFormat::Zinc)
+            .retry_policy(RetryPolicy::none())
+            .request_timeout(Duration::from_secs(5))
+            .total_timeout(Duration::from_secs(10))
+            .user_agent("raystack-test")
+            .accept_invalid_certs(true)
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(client.auth_token().await, "existingtoken");
+        assert_eq!(client.project_name(), "proj");
+
+        let client = client.into_haystack_client();
+        assert_eq!(client.grid_format(), GridFormat::Zinc);
+        assert_eq!(client.retry_policy(), &RetryPolicy::none());
+        assert_eq!(client.total_timeout(), Some(Duration::from_secs(10)));
+    }
+
+    #[tokio::test]
+    async fn build_with_stored_auth_token_does_not_authenticate() {
+        let url = Url::parse("http://localhost:1/api/proj/").unwrap();
+        let token_store = Arc::new(MemoryTokenStore::new());
+        token_store.save(&url, "name", "storedtoken").unwrap();
+
+        let client = SkySparkClientBuilder::new(url, "name", "password")
+            .token_store(token_store)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(client.auth_token().await, "storedtoken");
+    }
+
+    /// Records the threads a `MemoryTokenStore` is used on.
+    #[derive(Debug, Default)]
+    struct ThreadRecordingTokenStore {
+        store: MemoryTokenStore,
+        threads: std::sync::Mutex<Vec<std::thread::ThreadId>>,
+    }
+
+    impl ThreadRecordingTokenStore {
+        fn record_thread(&self) {
+            let thread_id = std::thread::current().id();
+            self.threads.lock().unwrap().push(thread_id);
+        }
+    }
+
+    impl TokenStore for ThreadRecordingTokenStore {
+        fn load(
+            &self,
+            project_api_url: &Url,
+            username: &str,
+        ) -> std::io::Result<Option<String>> {
+            self.record_thread();
+            self.store.load(project_api_url, username)
+        }
+
+        fn save(
+            &self,
+            project_api_url: &Url,
+            username: &str,
+            auth_token: &str,
+        ) -> std::io::Result<()> {
+            self.record_thread();
+            self.store.save(project_api_url, username, auth_token)
+        }
+
+        fn remove(
+            &self,
+            project_api_url: &Url,
+            username: &str,
+        ) -> std::io::Result<()> {
+            self.record_thread();
+            self.store.remove(project_api_url, username)
+        }
+    }
+
+    #[tokio::test]
+    async fn token_store_is_not_used_on_the_runtime_thread() {
+        use crate::transport::TestTransport;
+
+        let url = Url::parse("http://localhost:1/api/proj/").unwrap();
+        let token_store = Arc::new(ThreadRecordingTokenStore::default());
+        token_store.store.save(&url, "name", "storedtoken").unwrap();
+
+        let client =
+            SkySparkClientBuilder::new(url.clone(), "name", "password")
+                .token_store(token_store.clone())
+                .transport(Arc::new(TestTransport::empty_grid()))
+                .build()
+                .await
+                .unwrap();
+        client.logout().await.unwrap();
+        assert_eq!(token_store.store.load(&url, "name").unwrap(), None);
+
+        // The test runtime only has one thread, which runs this test:
+        let runtime_thread_id = std::thread::current().id();
+        let threads = token_store.threads.lock().unwrap();
+        assert!(!threads.is_empty());
+        assert!(threads.iter().all(|thread| *thread != runtime_thread_id));
+    }
+
+    #[tokio::test]
+    async fn build_with_failing_credential_provider_fails() {
+        let url = Url::parse("http://localhost:1/api/proj").unwrap();
+        let credential_provider = Arc::new(CallbackCredentials::new(|| {
+            Err(CredentialsError::new("vault is unavailable"))
+        }));
+        let result = SkySparkClientBuilder::with_credential_provider(
+            url,
+            credential_provider,
+        )
+        .build()
+        .await;
+        assert!(matches!(
+            result,
+            Err(NewSkySparkClientError::Auth(AuthError::Credentials(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn build_with_slow_authentication_times_out() {
+        use crate::transport::TestTransport;
+
+        let url = Url::parse("http://localhost:1/api/proj/").unwrap();
+        let transport =
+            TestTransport::empty_grid().with_delay(Duration::from_secs(5));
+        let result = SkySparkClientBuilder::new(url, "name", "password")
+            .total_timeout(Duration::from_millis(50))
+            .transport(Arc::new(transport))
+            .build()
+            .await;
+        assert!(matches!(
+            result,
+            Err(NewSkySparkClientError::Timeout { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn slow_logout_times_out() {
+        use crate::transport::TestTransport;
+        use crate::Error;
+
+        let url = Url::parse("http://localhost:1/api/proj/").unwrap();
+        let transport =
+            TestTransport::empty_grid().with_delay(Duration::from_secs(5));
+        let client = SkySparkClientBuilder::new(url, "name", "password")
+            .auth_token("existingtoken")
+            .total_timeout(Duration::from_millis(50))
+            .transport(Arc::new(transport))
+            .build()
+            .await
+            .unwrap();
+        assert!(matches!(client.logout().await, Err(Error::Timeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn basic_auth_sends_credentials_in_each_request() {
+        use crate::auth::AuthSchemes;
+        use crate::transport::TestTransport;
+        use crate::HttpResponse;
+        use reqwest::header::{HeaderMap, HeaderValue};
+        use reqwest::StatusCode;
+
+        // "name:password" encoded as base64:
+        let basic = "Basic bmFtZTpwYXNzd29yZA==";
+        let transport = Arc::new(TestTransport::new(move |request| {
+            let auth = request.headers()["authorization"].to_str().unwrap();
+            if auth == basic {
+                let grid = serde_json::json!({
+                    "_kind": "grid",
+                    "meta": {"ver": "3.0"},
+                    "cols": [{"name": "empty"}],
+                    "rows": [],
+                });
+                TestTransport::json_response(StatusCode::OK, &grid)
+            } else {
+                let mut headers = HeaderMap::new();
+                let challenge = HeaderValue::from_static("Basic realm=\"a\"");
+                headers.insert("www-authenticate", challenge);
+                HttpResponse::new(StatusCode::UNAUTHORIZED, headers, Vec::new())
+            }
+        }));
+
+        let url = Url::parse("http://localhost:1/api/proj/").unwrap();
+        let result =
+            SkySparkClientBuilder::new(url.clone(), "name", "password")
+                .transport(transport.clone())
+                .build()
+                .await;
+        assert!(matches!(
+            result,
+            Err(NewSkySparkClientError::Auth(
+                AuthError::InsecureScheme { .. }
+            ))
+        ));
+
+        let auth_schemes = AuthSchemes::default().allow_insecure(true);
+        let client = SkySparkClientBuilder::new(url, "name", "password")
+            .auth_schemes(auth_schemes)
+            .transport(transport.clone())
+            .build()
+            .await
+            .unwrap();
+        client.about().await.unwrap();
+
+        let requests = transport.requests();
+        let last_request = requests.last().unwrap();
+        assert_eq!(last_request.url().path(), "/api/proj/about");
+        assert_eq!(last_request.headers()["authorization"], basic);
+    }
+
+    #[tokio::test]
+    async fn build_with_invalid_url_fails() {
+        let url = Url::parse("http://localhost:1/notapi").unwrap();
+        let result = SkySparkClientBuilder::new(url, "name", "password")
+            .auth_token("existingtoken")
+            .build()
+            .await;
+        assert!(matches!(result, Err(NewSkySparkClientError::Url { .. })));
+    }
+
+    #[tokio::test]
+    async fn build_with_invalid_user_agent_fails() {
+        let url = Url::parse("http://localhost:1/api/proj").unwrap();
+        let result = SkySparkClientBuilder::new(url, "name", "password")
+            .auth_token("existingtoken")
+            .user_agent("bad\nagent")
+            .build()
+            .await;
+        assert!(matches!(result, Err(NewSkySparkClientError::Http(_))));
+    }
+
+    #[tokio::test]
+    async fn build_haystack_client_with_any_base_url() {
+        let base_url = Url::parse("http://localhost:1/haystack/v4").unwrap();
+        let client = HaystackClientBuilder::new(base_url, "name", "password")
+            .auth_token("existingtoken")
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.base_url().as_str(),
+            "http://localhost:1/haystack/v4/"
+        );
+        assert_eq!(
+            client.auth_url().as_str(),
+            "http://localhost:1/haystack/v4/about"
+        );
+        assert_eq!(client.auth_token().await, "existingtoken");
+    }
+
+    #[tokio::test]
+    async fn build_haystack_client_with_auth_url() {
+        let base_url = Url::parse("http://localhost:1/").unwrap();
+        let auth_url = Url::parse("http://localhost:1/auth/login").unwrap();
+        let client = HaystackClientBuilder::new(base_url, "name", "password")
+            .auth_url(auth_url.clone())
+            .auth_token("existingtoken")
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(client.auth_url(), &auth_url);
+    }
+
+    #[tokio::test]
+    async fn build_haystack_client_with_invalid_url_fails() {
+        let base_url = Url::parse("mailto:name@example.com").unwrap();
+        let result = HaystackClientBuilder::new(base_url, "name", "password")
+            .auth_token("existingtoken")
+            .build()
+            .await;
+        assert!(matches!(result, Err(NewSkySparkClientError::Url { .. })));
+    }
+
+    #[tokio::test]
+    async fn skyspark_client_uses_skyspark_urls() {
+        let url = Url::parse("http://localhost:1/api/proj").unwrap();
+        let client = SkySparkClientBuilder::new(url, "name", "password")
+            .auth_token("existingtoken")
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.project_api_url().as_str(),
+            "http://localhost:1/api/proj/"
+        );
+        assert_eq!(
+            client.as_haystack_client().base_url(),
+            client.project_api_url()
+        );
+        assert_eq!(
+            client.as_haystack_client().auth_url().as_str(),
+            "http://localhost:1/ui"
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Providers of the username and password used to authenticate with a
+//! SkySpark server.
+
+use std::fmt::{self, Debug};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+/// A username and password used to authenticate with a SkySpark server.
+/// The password is zeroed in memory when the `Credentials` are dropped,
+/// and is not included in the `Debug` output.
+#[derive(Clone)]
+pub struct Credentials {
+    username: String,
+    password: Zeroizing<String>,
+}
+
+impl Credentials {
+    /// Create new `Credentials`.
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_owned(),
+            password: Zeroizing::new(password.to_owned()),
+        }
+    }
+
+    /// Return the username.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Return the password.
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Provides the credentials used by a `SkySparkClient` to authenticate
+/// with the server. The provider is called each time the client
+/// authenticates, including when the server rejects the current auth token,
+/// so it can return credentials which have changed since the client was
+/// created.
+pub trait CredentialProvider: Debug + Send + Sync {
+    /// Return the credentials used to authenticate with the server.
+    fn credentials(&self) -> Result<Credentials, CredentialsError>;
+
+    /// Return the username which will be used to authenticate with the
+    /// server. This is used to find saved auth tokens in a token store,
+    /// without needing to obtain the password.
+    fn username(&self) -> Result<String, CredentialsError> {
+        self.credentials()
+            .map(|credentials| credentials.username().to_owned())
+    }
+}
+
+/// A `CredentialProvider` which always returns the same credentials.
+#[derive(Clone, Debug)]
+pub struct StaticCredentials {
+    credentials: Credentials,
+}
+
+impl StaticCredentials {
+    /// Create a new `StaticCredentials` provider.
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            credentials: Credentials::new(username, password),
+        }
+    }
+}
+
+impl CredentialProvider for StaticCredentials {
+    fn credentials(&self) -> Result<Credentials, CredentialsError> {
+        Ok(self.credentials.clone())
+    }
+
+    fn username(&self) -> Result<String, CredentialsError> {
+        Ok(self.credentials.username().to_owned())
+    }
+}
+
+/// A `CredentialProvider` which reads the username and password from
+/// environment variables each time it is called.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use raystack::{EnvCredentials, SkySparkClientBuilder};
+/// use std::sync::Arc;
+/// use url::Url;
+///
+/// let credentials = EnvCredentials::new("SKYSPARK_USERNAME", "SKYSPARK_PASSWORD");
+/// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
+/// let client = SkySparkClientBuilder::with_credential_provider(url, Arc::new(credentials))
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct EnvCredentials {
+    username_var: String,
+    password_var: String,
+}
+
+impl EnvCredentials {
+    /// Create a new `EnvCredentials` provider, which reads the username and
+    /// password from the environment variables with the given names.
+    pub fn new(username_var: &str, password_var: &str) -> Self {
+        Self {
+            username_var: username_var.to_owned(),
+            password_var: password_var.to_owned(),
+        }
+    }
+}
+
+impl CredentialProvider for EnvCredentials {
+    fn credentials(&self) -> Result<Credentials, CredentialsError> {
+        let username = self.username()?;
+        let password = Zeroizing::new(env_var(&self.password_var)?);
+        Ok(Credentials::new(&username, &password))
+    }
+
+    fn username(&self) -> Result<String, CredentialsError> {
+        env_var(&self.username_var)
+    }
+}
+
+fn env_var(name: &str) -> Result<String, CredentialsError> {
+    std::env::var(name).map_err(|_| {
+        CredentialsError::new(format!(
+            "environment variable {} is not set or is not valid Unicode",
+            name
+        ))
+    })
+}
+
+/// A `CredentialProvider` which calls a function to obtain the credentials,
+/// for example to read them from a secrets manager.
+pub struct CallbackCredentials<F> {
+    callback: F,
+}
+
+impl<F> CallbackCredentials<F>
+where
+    F: Fn() -> Result<Credentials, CredentialsError> + Send + Sync,
+{
+    /// Create a new `CallbackCredentials` provider, which calls the given
+    /// function each time credentials are required.
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+impl<F> Debug for CallbackCredentials<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackCredentials")
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F> CredentialProvider for CallbackCredentials<F>
+where
+    F: Fn() -> Result<Credentials, CredentialsError> + Send + Sync,
+{
+    fn credentials(&self) -> Result<Credentials, CredentialsError> {
+        (self.callback)()
+    }
+}
+
+/// Error denoting that a `CredentialProvider` could not provide
+/// credentials.
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("Could not obtain credentials: {msg}")]
+pub struct CredentialsError {
+    msg: String,
+}
+
+impl CredentialsError {
+    /// Create a new `CredentialsError` with the given description.
+    pub fn new<S: Into<String>>(msg: S) -> Self {
+        Self { msg: msg.into() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CallbackCredentials, CredentialProvider, Credentials, CredentialsError,
+        EnvCredentials, StaticCredentials,
+    };
+
+    #[test]
+    fn debug_output_is_redacted() {
+        let provider = StaticCredentials::new("name", "s3cr3t");
+        let debug = format!("{:?}", provider);
+        assert!(debug.contains("name"));
+        assert!(!debug.contains("s3cr3t"));
+
+        let credentials = provider.credentials().unwrap();
+        assert_eq!(credentials.password(), "s3cr3t");
+        assert!(!format!("{:?}", credentials).contains("s3cr3t"));
+    }
+
+    #[test]
+    fn env_credentials() {
+        let username_var = "RAYSTACK_TEST_ENV_CREDENTIALS_USERNAME";
+        let password_var = "RAYSTACK_TEST_ENV_CREDENTIALS_PASSWORD";
+        let provider = EnvCredentials::new(username_var, password_var);
+        assert!(provider.credentials().is_err());
+
+        std::env::set_var(username_var, "name");
+        std::env::set_var(password_var, "s3cr3t");
+        let credentials = provider.credentials().unwrap();
+        assert_eq!(credentials.username(), "name");
+        assert_eq!(credentials.password(), "s3cr3t");
+        assert_eq!(provider.username().unwrap(), "name");
+
+        std::env::remove_var(username_var);
+        std::env::remove_var(password_var);
+    }
+
+    #[test]
+    fn callback_credentials() {
+        let provider =
+            CallbackCredentials::new(|| Ok(Credentials::new("name", "s3cr3t")));
+        assert_eq!(provider.username().unwrap(), "name");
+        assert_eq!(provider.credentials().unwrap().password(), "s3cr3t");
+
+        let failing_provider = CallbackCredentials::new(|| {
+            Err(CredentialsError::new("vault is unavailable"))
+        });
+        assert!(failing_provider.credentials().is_err());
+        assert!(failing_provider.username().is_err());
+    }
+}
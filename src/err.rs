@@ -1,11 +1,13 @@
 use crate::auth::AuthError;
 use crate::grid::{Grid, ParseJsonGridError};
+use crate::zinc::ParseZincError;
+use crate::TransportError;
 use thiserror::Error;
 
 impl Error {
     /// Return true if this error encapsulates a Haystack error grid.
     pub fn is_grid(&self) -> bool {
-        matches!(self, Self::Grid { .. })
+        self.grid().is_some()
     }
 
     /// Return a reference to the Haystack error grid encapsulated by this
@@ -13,6 +15,7 @@ impl Error {
     pub fn grid(&self) -> Option<&Grid> {
         match self {
             Self::Grid { err_grid } => Some(err_grid),
+            Self::Retried { err, .. } => err.grid(),
             _ => None,
         }
     }
@@ -22,9 +25,44 @@ impl Error {
     pub fn into_grid(self) -> Option<Grid> {
         match self {
             Self::Grid { err_grid } => Some(err_grid),
+            Self::Retried { err, .. } => err.into_grid(),
             _ => None,
         }
     }
+
+    /// Return the number of times the failed request was attempted.
+    pub fn attempts(&self) -> u32 {
+        match self {
+            Self::Retried { attempts, .. } => *attempts,
+            _ => 1,
+        }
+    }
+
+    /// Return the error which caused the final attempt of a request to
+    /// fail, ignoring how many times the request was attempted.
+    pub fn last_error(&self) -> &Error {
+        match self {
+            Self::Retried { err, .. } => err,
+            _ => self,
+        }
+    }
+
+    /// Wrap the error in `Error::Retried` if the request was attempted
+    /// more than once.
+    pub(crate) fn with_attempts(err: Error, attempts: u32) -> Self {
+        if attempts > 1 {
+            Error::Retried {
+                attempts,
+                err: Box::new(err),
+            }
+        } else {
+            err
+        }
+    }
+
+    pub(crate) fn unexpected_grid(msg: &str) -> Self {
+        Error::UnexpectedGrid { msg: msg.into() }
+    }
 }
 
 /// Describes the kinds of errors that can occur in this crate.
@@ -36,33 +74,104 @@ pub enum Error {
         /// The grid which caused this error.
         err_grid: Grid,
     },
-    /// An error which originated from the underlying HTTP library.
-    #[error("Error occurred in the underlying HTTP library")]
+    /// An error which occurred when sending a request or reading its
+    /// response, in the client's `Transport`.
+    #[error("Error occurred in the HTTP transport")]
     Http {
         #[from]
-        err: reqwest::Error,
+        err: TransportError,
     },
     /// An error related to parsing a `Grid` from a JSON value.
     #[error("Could not parse JSON as a Haystack grid")]
     ParseJsonGrid(#[from] ParseJsonGridError),
+    /// An error related to parsing a `Grid` from a Zinc string.
+    #[error("Could not parse Zinc as a Haystack grid")]
+    ParseZincGrid(#[from] ParseZincError),
+    /// A request failed after being attempted more than once, as allowed
+    /// by the client's `RetryPolicy`.
+    #[error("Request failed after {attempts} attempts")]
+    Retried {
+        /// The number of times the request was attempted.
+        attempts: u32,
+        /// The error which caused the final attempt to fail.
+        #[source]
+        err: Box<Error>,
+    },
+    /// An operation did not complete within the client's total timeout.
+    #[error("Operation did not complete within {timeout:?}")]
+    Timeout {
+        /// The total timeout of the client.
+        timeout: std::time::Duration,
+    },
     /// An error caused by an invalid time zone.
     #[error("Not a valid time zone: {err_time_zone}")]
     TimeZone {
         /// The time zone which caused the error.
         err_time_zone: String,
     },
+    /// The server returned a grid which did not have the structure
+    /// expected for the operation.
+    #[error("Server returned an unexpected grid: {msg}")]
+    UnexpectedGrid {
+        /// A description of what was unexpected about the grid.
+        msg: String,
+    },
     /// An error occurred when trying to obtain a new auth token from
     /// the server.
     #[error("Could not obtain a new auth token from the server")]
     UpdateAuthToken(#[from] crate::auth::AuthError),
 }
 
-/// Errors that can occur when creating a new `SkySparkClient`.
+/// Error denoting that a multi-point hisWrite failed part way through.
+/// The server has already written the values sent by the requests before
+/// the failed request, and the grids returned by those requests are kept
+/// in this error.
+#[derive(Debug, Error)]
+#[error("hisWrite failed after {} requests succeeded", .written.len())]
+pub struct HisWriteManyError {
+    written: Vec<Grid>,
+    #[source]
+    err: Error,
+}
+
+impl HisWriteManyError {
+    pub(crate) fn new(written: Vec<Grid>, err: Error) -> Self {
+        Self { written, err }
+    }
+
+    /// Return the grids returned by the requests which succeeded before
+    /// the failed request.
+    pub fn written(&self) -> &[Grid] {
+        &self.written
+    }
+
+    /// Return the error which caused the failed request to fail.
+    pub fn error(&self) -> &Error {
+        &self.err
+    }
+
+    /// Return the error which caused the failed request to fail.
+    pub fn into_error(self) -> Error {
+        self.err
+    }
+}
+
+/// Errors that can occur when creating a new `SkySparkClient` or
+/// `HaystackClient`.
 #[derive(Debug, Error)]
 pub enum NewSkySparkClientError {
     /// An error which occurred during the authentication process.
     #[error("Error occurred during authentication")]
     Auth(#[from] AuthError),
+    /// An error which occurred when creating the underlying HTTP client.
+    #[error("Could not create the underlying HTTP client")]
+    Http(#[from] reqwest::Error),
+    /// Authentication did not complete within the client's total timeout.
+    #[error("Authentication did not complete within {timeout:?}")]
+    Timeout {
+        /// The total timeout of the client.
+        timeout: std::time::Duration,
+    },
     /// An error caused by an invalid SkySpark project url.
     #[error("The SkySpark URL is invalid: {msg}")]
     Url { msg: String },
@@ -72,4 +181,8 @@ impl NewSkySparkClientError {
     pub(crate) fn url(msg: &str) -> Self {
         NewSkySparkClientError::Url { msg: msg.into() }
     }
+
+    pub(crate) fn timeout(timeout: std::time::Duration) -> Self {
+        NewSkySparkClientError::Timeout { timeout }
+    }
 }
\ No newline at end of file
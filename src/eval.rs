@@ -1,8 +1,11 @@
+use crate::auth::{authorization_header_value, AuthSchemes};
 use crate::Grid;
 use crate::{
     add_backslash_if_necessary, has_valid_path_segments, http_response_to_grid,
-    new_auth_token,
+    new_auth_token, skyspark_auth_url,
 };
+use crate::{HttpRequest, ReqwestTransport, Transport, TransportError};
+use reqwest::Method;
 use serde_json::json;
 use thiserror::Error;
 use url::Url;
@@ -47,13 +50,24 @@ pub async fn eval(
         .join("eval")
         .expect("since url ends with '/' this should never fail");
 
+    let transport = ReqwestTransport::new(client.clone());
+    let auth_url = skyspark_auth_url(&project_api_url);
+    let auth_schemes = AuthSchemes::default();
     let mut was_new_token_obtained = false;
 
     let auth_token = match auth_token {
         Some(token) => token.to_owned(),
         None => {
             was_new_token_obtained = true;
-            new_auth_token(&project_api_url, client, username, password).await?
+            new_auth_token(
+                &auth_url,
+                &transport,
+                username,
+                password,
+                &auth_schemes,
+                None,
+            )
+            .await?
         }
     };
 
@@ -61,28 +75,36 @@ pub async fn eval(
     let req_grid = Grid::new_internal(vec![row]);
 
     let req_with_token = |token: &str| {
-        client
-            .post(eval_url.clone())
-            .header("Accept", "application/json")
-            .header("Authorization", format!("BEARER authToken={}", token))
-            .header("Content-Type", "application/json")
-            .body(req_grid.to_json_string())
+        let request = HttpRequest::new(Method::POST, eval_url.clone())
+            .with_header("Accept", "application/json")
+            .with_header("Content-Type", "application/json")
+            .try_with_header(
+                "Authorization",
+                &authorization_header_value(token),
+            )?
+            .with_body(req_grid.to_json_string());
+        Ok::<_, TransportError>(request)
     };
 
-    let res = req_with_token(&auth_token).send().await?;
+    let res = transport.send(req_with_token(&auth_token)?).await?;
 
     if res.status() == reqwest::StatusCode::FORBIDDEN {
-        let auth_token =
-            new_auth_token(&project_api_url, client, username, password)
-                .await?;
-        let retry_res = req_with_token(&auth_token).send().await?;
-        let grid: Result<Grid, EvalError> = http_response_to_grid(retry_res)
-            .await
-            .map_err(|err| err.into());
+        let auth_token = new_auth_token(
+            &auth_url,
+            &transport,
+            username,
+            password,
+            &auth_schemes,
+            None,
+        )
+        .await?;
+        let retry_res = transport.send(req_with_token(&auth_token)?).await?;
+        let grid: Result<Grid, EvalError> =
+            http_response_to_grid(retry_res).map_err(|err| err.into());
         Ok(EvalOutput::new(grid?, Some(auth_token)))
     } else {
         let grid: Result<Grid, EvalError> =
-            http_response_to_grid(res).await.map_err(|err| err.into());
+            http_response_to_grid(res).map_err(|err| err.into());
         if was_new_token_obtained {
             Ok(EvalOutput::new(grid?, Some(auth_token)))
         } else {
@@ -148,16 +170,32 @@ pub enum EvalError {
         err_grid: Grid,
     },
     #[error("HTTP error: {0}")]
-    Http(#[from] reqwest::Error),
+    Http(#[from] crate::TransportError),
     /// An error related to parsing a `Grid` from a JSON value.
     #[error("Could not parse JSON as a Haystack grid")]
     ParseJsonGrid(#[from] crate::grid::ParseJsonGridError),
+    /// An error related to parsing a `Grid` from a Zinc string.
+    #[error("Could not parse Zinc as a Haystack grid")]
+    ParseZincGrid(#[from] crate::zinc::ParseZincError),
+    /// An operation did not complete within the client's total timeout.
+    #[error("Operation did not complete within {timeout:?}")]
+    Timeout {
+        /// The total timeout of the client.
+        timeout: std::time::Duration,
+    },
     /// An error caused by an invalid time zone.
     #[error("Not a valid time zone: {err_time_zone}")]
     TimeZone {
         /// The time zone which caused this error.
         err_time_zone: String,
     },
+    /// The server returned a grid which did not have the expected
+    /// structure.
+    #[error("Server returned an unexpected grid: {msg}")]
+    UnexpectedGrid {
+        /// A description of what was unexpected about the grid.
+        msg: String,
+    },
 }
 
 impl std::convert::From<crate::Error> for EvalError {
@@ -166,10 +204,17 @@ impl std::convert::From<crate::Error> for EvalError {
             crate::Error::Grid { err_grid } => Self::Grid { err_grid },
             crate::Error::Http { err } => Self::Http(err),
             crate::Error::ParseJsonGrid(err) => Self::ParseJsonGrid(err),
+            crate::Error::ParseZincGrid(err) => Self::ParseZincGrid(err),
+            // The error which caused the final attempt to fail is kept:
+            crate::Error::Retried { err, .. } => Self::from(*err),
+            crate::Error::Timeout { timeout } => Self::Timeout { timeout },
             crate::Error::TimeZone { err_time_zone } => {
                 Self::TimeZone { err_time_zone }
             }
-            crate::Error::UpdateAuthToken(_) => unreachable!(), // The standalone eval function will not update auth tokens.
+            crate::Error::UnexpectedGrid { msg } => {
+                Self::UnexpectedGrid { msg }
+            }
+            crate::Error::UpdateAuthToken(err) => Self::Auth(err),
         }
     }
 }
@@ -235,6 +280,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn eval_works_with_no_token() {
         let output = eval_expr("readAll(site)", None).await.unwrap();
         assert!(output.has_new_auth_token());
@@ -244,6 +290,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn eval_works_with_bad_token() {
         let output = eval_expr("readAll(site)", Some("thistokenisnotvalid"))
             .await
@@ -255,6 +302,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn eval_works_with_good_token() {
         let output_for_token = eval_expr("readAll(site)", None).await.unwrap();
         let valid_token = output_for_token.new_auth_token().unwrap();
@@ -268,4 +316,23 @@ mod test {
         assert!(grid.size() > 1);
         assert!(grid.rows()[0]["site"].is_hs_marker());
     }
+
+    #[test]
+    fn client_errors_convert_without_panicking() {
+        let timeout = std::time::Duration::from_secs(1);
+        let err = EvalError::from(crate::Error::Retried {
+            attempts: 3,
+            err: Box::new(crate::Error::Timeout { timeout }),
+        });
+        assert!(matches!(err, EvalError::Timeout { .. }));
+
+        let msg = "missing id".to_owned();
+        let err = EvalError::from(crate::Error::UnexpectedGrid { msg });
+        assert!(matches!(err, EvalError::UnexpectedGrid { .. }));
+
+        let scheme = "Negotiate".to_owned();
+        let auth_err = crate::auth::AuthError::UnsupportedScheme { scheme };
+        let err = EvalError::from(crate::Error::UpdateAuthToken(auth_err));
+        assert!(matches!(err, EvalError::Auth(_)));
+    }
 }
@@ -0,0 +1,676 @@
+//! Transports which record requests and responses to a fixture file, and
+//! replay them later without a server.
+//!
+//! Fixture files are JSON files containing each request's method, path and
+//! body, and the status, content type and body of its response. Auth
+//! tokens are removed before anything is written to a fixture file, and
+//! the requests made while authenticating are never recorded. Responses
+//! which reject an auth token are not recorded either, because the client
+//! authenticates again before resending the request.
+
+use crate::{
+    HttpRequest, HttpResponse, Transport, TransportError, TransportErrorKind,
+};
+use futures::future::BoxFuture;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::fmt::{self, Debug};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Replaces auth tokens in recorded requests and responses.
+const SCRUBBED: &str = "<scrubbed>";
+
+/// A request and the response the server sent for it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Interaction {
+    method: String,
+    path: String,
+    request_body: Option<String>,
+    status: u16,
+    content_type: Option<String>,
+    response_body: String,
+}
+
+impl Interaction {
+    fn to_json(&self) -> Value {
+        json!({
+            "request": {
+                "method": self.method,
+                "path": self.path,
+                "body": self.request_body,
+            },
+            "response": {
+                "status": self.status,
+                "contentType": self.content_type,
+                "body": self.response_body,
+            },
+        })
+    }
+
+    fn from_json(json: &Value) -> Option<Self> {
+        let request = &json["request"];
+        let response = &json["response"];
+        let optional_str = |value: &Value| match value {
+            Value::Null => Some(None),
+            Value::String(s) => Some(Some(s.clone())),
+            _ => None,
+        };
+
+        Some(Self {
+            method: request["method"].as_str()?.to_owned(),
+            path: request["path"].as_str()?.to_owned(),
+            request_body: optional_str(&request["body"])?,
+            status: response["status"].as_u64()? as u16,
+            content_type: optional_str(&response["contentType"])?,
+            response_body: response["body"].as_str()?.to_owned(),
+        })
+    }
+
+    /// Return a copy of this interaction with each of the given auth tokens
+    /// replaced.
+    fn scrubbed(&self, auth_tokens: &[String]) -> Self {
+        let scrub = |s: &str| scrub(s, auth_tokens);
+        Self {
+            method: self.method.clone(),
+            path: scrub(&self.path),
+            request_body: self.request_body.as_deref().map(scrub),
+            status: self.status,
+            content_type: self.content_type.clone(),
+            response_body: scrub(&self.response_body),
+        }
+    }
+
+    fn matches(&self, method: &str, path: &str, body: Option<&str>) -> bool {
+        self.method == method
+            && self.path == path
+            && self.request_body.as_deref() == body
+    }
+
+    fn to_response(&self) -> Result<HttpResponse, TransportError> {
+        let status = StatusCode::from_u16(self.status).map_err(|err| {
+            TransportError::new(TransportErrorKind::Other, err)
+        })?;
+
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = &self.content_type {
+            let content_type =
+                HeaderValue::from_str(content_type).map_err(|err| {
+                    TransportError::new(TransportErrorKind::Other, err)
+                })?;
+            headers.insert("content-type", content_type);
+        }
+
+        let body = self.response_body.clone().into_bytes();
+        Ok(HttpResponse::new(status, headers, body))
+    }
+}
+
+/// A `Transport` which sends requests using another transport, and records
+/// each request and its response so they can be saved to a fixture file.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use raystack::{RecordingTransport, ReqwestTransport, SkySparkClientBuilder};
+/// use std::sync::Arc;
+/// use url::Url;
+///
+/// let recorder = Arc::new(RecordingTransport::new(Arc::new(ReqwestTransport::default())));
+/// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
+/// let client = SkySparkClientBuilder::new(url, "username", "p4ssw0rd")
+///     .transport(recorder.clone())
+///     .build()
+///     .await
+///     .unwrap();
+///
+/// client.read("site", None).await.unwrap();
+/// recorder.save("tests/fixtures/read_sites.json").unwrap();
+/// # }
+/// ```
+pub struct RecordingTransport {
+    transport: Arc<dyn Transport>,
+    interactions: Mutex<Vec<Interaction>>,
+    /// Every auth token which has been sent through this transport, so
+    /// each of them can be removed from every recorded interaction.
+    auth_tokens: Mutex<Vec<String>>,
+}
+
+impl RecordingTransport {
+    /// Create a new `RecordingTransport`, which sends requests using the
+    /// given transport.
+    pub fn new(transport: Arc<dyn Transport>) -> Self {
+        Self {
+            transport,
+            interactions: Mutex::new(Vec::new()),
+            auth_tokens: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Return the number of requests which have been recorded.
+    pub fn len(&self) -> usize {
+        self.interactions.lock().expect("lock not poisoned").len()
+    }
+
+    /// Return true if no requests have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Save the recorded requests and responses to a fixture file,
+    /// replacing the file if it already exists.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        // Auth tokens first seen after an interaction was recorded are
+        // removed from it here:
+        let auth_tokens = self.auth_tokens();
+        let interactions = self.interactions.lock().expect("lock not poisoned");
+        let interactions = interactions
+            .iter()
+            .map(|interaction| interaction.scrubbed(&auth_tokens).to_json())
+            .collect::<Vec<_>>();
+        let json = json!({ "interactions": interactions });
+        let contents = serde_json::to_string_pretty(&json)?;
+        std::fs::write(path, contents)
+    }
+
+    async fn send_request(
+        &self,
+        request: HttpRequest,
+    ) -> Result<HttpResponse, TransportError> {
+        match bearer_auth_token(&request) {
+            Some(auth_token) => self.add_auth_token(auth_token),
+            None => return self.transport.send(request).await,
+        }
+
+        let method = request.method().to_string();
+        let path = path_and_query(&request);
+        let request_body = body_string(request.body());
+
+        let response = self.transport.send(request).await?;
+
+        // The client obtains a new auth token and sends the request again,
+        // and that request is recorded instead:
+        if response.status() == StatusCode::FORBIDDEN {
+            return Ok(response);
+        }
+
+        let interaction = Interaction {
+            method,
+            path,
+            request_body,
+            status: response.status().as_u16(),
+            content_type: content_type(response.headers()),
+            response_body: body_string(Some(response.body()))
+                .unwrap_or_default(),
+        };
+        let interaction = interaction.scrubbed(&self.auth_tokens());
+        self.interactions
+            .lock()
+            .expect("lock not poisoned")
+            .push(interaction);
+
+        Ok(response)
+    }
+
+    fn add_auth_token(&self, auth_token: &str) {
+        let mut auth_tokens =
+            self.auth_tokens.lock().expect("lock not poisoned");
+        if !auth_tokens.iter().any(|existing| existing == auth_token) {
+            auth_tokens.push(auth_token.to_owned());
+        }
+    }
+
+    fn auth_tokens(&self) -> Vec<String> {
+        self.auth_tokens.lock().expect("lock not poisoned").clone()
+    }
+}
+
+impl Debug for RecordingTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordingTransport")
+            .field("transport", &self.transport)
+            .field("recorded", &self.len())
+            .finish()
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn send(
+        &self,
+        request: HttpRequest,
+    ) -> BoxFuture<'_, Result<HttpResponse, TransportError>> {
+        Box::pin(self.send_request(request))
+    }
+}
+
+/// A `Transport` which responds to requests using the responses saved in a
+/// fixture file by a `RecordingTransport`, without sending any requests.
+///
+/// Each request is matched to a recorded request with the same method,
+/// path and body. Recorded requests are matched in the order they were
+/// recorded, and each recorded response is only used once, so repeated
+/// requests receive the same sequence of responses as when they were
+/// recorded.
+///
+/// Fixture files do not contain the requests made while authenticating, so
+/// a client which uses a `ReplayTransport` must be created with an auth
+/// token, which can be any string.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use raystack::{ReplayTransport, SkySparkClientBuilder};
+/// use std::sync::Arc;
+/// use url::Url;
+///
+/// let replayer = ReplayTransport::load("tests/fixtures/read_sites.json").unwrap();
+/// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
+/// let client = SkySparkClientBuilder::new(url, "username", "p4ssw0rd")
+///     .auth_token("replayed")
+///     .transport(Arc::new(replayer))
+///     .build()
+///     .await
+///     .unwrap();
+///
+/// let sites = client.read("site", None).await.unwrap();
+/// # }
+/// ```
+pub struct ReplayTransport {
+    /// Each recorded interaction, and whether it has been replayed yet.
+    interactions: Mutex<Vec<(Interaction, bool)>>,
+}
+
+impl ReplayTransport {
+    /// Load the requests and responses saved in a fixture file.
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let json: Value = serde_json::from_str(&contents)?;
+
+        let interactions = json["interactions"]
+            .as_array()
+            .and_then(|interactions| {
+                interactions
+                    .iter()
+                    .map(|interaction| {
+                        Interaction::from_json(interaction)
+                            .map(|interaction| (interaction, false))
+                    })
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "fixture file does not contain valid interactions",
+                )
+            })?;
+
+        Ok(Self {
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    /// Return the number of recorded responses which have not been
+    /// replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.interactions
+            .lock()
+            .expect("lock not poisoned")
+            .iter()
+            .filter(|(_, is_replayed)| !is_replayed)
+            .count()
+    }
+
+    fn replay(
+        &self,
+        request: &HttpRequest,
+    ) -> Result<HttpResponse, TransportError> {
+        if bearer_auth_token(request).is_none()
+            && request.headers().contains_key("authorization")
+        {
+            let msg = "fixture files do not contain authentication requests, \
+                       so the client must be created with an auth token";
+            return Err(TransportError::new(TransportErrorKind::Other, msg));
+        }
+
+        let auth_tokens = bearer_auth_token(request)
+            .map(|auth_token| vec![auth_token.to_owned()])
+            .unwrap_or_default();
+        let scrub = |s: &str| scrub(s, &auth_tokens);
+        let method = request.method().to_string();
+        let path = scrub(&path_and_query(request));
+        let body = body_string(request.body()).map(|body| scrub(&body));
+
+        let mut interactions =
+            self.interactions.lock().expect("lock not poisoned");
+        let interaction =
+            interactions.iter_mut().find(|(interaction, is_replayed)| {
+                !is_replayed
+                    && interaction.matches(&method, &path, body.as_deref())
+            });
+
+        match interaction {
+            Some((interaction, is_replayed)) => {
+                *is_replayed = true;
+                interaction.to_response()
+            }
+            None => {
+                let msg =
+                    format!("no recorded response for {} {}", method, path);
+                Err(TransportError::new(TransportErrorKind::Other, msg))
+            }
+        }
+    }
+}
+
+impl Debug for ReplayTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayTransport")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send(
+        &self,
+        request: HttpRequest,
+    ) -> BoxFuture<'_, Result<HttpResponse, TransportError>> {
+        let response = self.replay(&request);
+        Box::pin(async move { response })
+    }
+}
+
+/// Return the auth token in the request's `Authorization` header, if the
+/// request is authorized with a bearer token.
+fn bearer_auth_token(request: &HttpRequest) -> Option<&str> {
+    let value = request.headers().get("authorization")?.to_str().ok()?;
+    let (scheme, params) = value.split_at(value.find(' ')?);
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    params
+        .split(',')
+        .filter_map(|param| {
+            let param = param.trim();
+            param.strip_prefix("authToken=")
+        })
+        .next()
+}
+
+/// Replace each of the given auth tokens in the string. Longer auth tokens
+/// are replaced first, in case one auth token contains another.
+fn scrub(s: &str, auth_tokens: &[String]) -> String {
+    let mut auth_tokens = auth_tokens.iter().collect::<Vec<_>>();
+    auth_tokens.sort_by_key(|auth_token| std::cmp::Reverse(auth_token.len()));
+    auth_tokens
+        .into_iter()
+        .filter(|auth_token| !auth_token.is_empty())
+        .fold(s.to_owned(), |s, auth_token| {
+            s.replace(auth_token.as_str(), SCRUBBED)
+        })
+}
+
+fn path_and_query(request: &HttpRequest) -> String {
+    let url = request.url();
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    }
+}
+
+fn body_string(body: Option<&[u8]>) -> Option<String> {
+    body.map(|body| String::from_utf8_lossy(body).into_owned())
+}
+
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RecordingTransport, ReplayTransport};
+    use crate::transport::TestTransport;
+    use crate::{
+        HaystackClient, HaystackClientBuilder, HttpRequest, Transport,
+        TransportError,
+    };
+    use reqwest::{Method, StatusCode};
+    use serde_json::json;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use url::Url;
+
+    /// Return a transport which responds with a grid containing the
+    /// request's path, `Authorization` header and a count of the requests
+    /// it has received.
+    fn echo_transport() -> TestTransport {
+        let count = AtomicUsize::new(0);
+        TestTransport::new(move |request| {
+            let count = count.fetch_add(1, Ordering::SeqCst);
+            let authorization = request.headers()["authorization"]
+                .to_str()
+                .unwrap()
+                .to_owned();
+            let grid = json!({
+                "_kind": "grid",
+                "meta": {"ver": "3.0"},
+                "cols": [{"name": "path"}, {"name": "auth"}, {"name": "count"}],
+                "rows": [{
+                    "path": request.url().path(),
+                    "auth": authorization,
+                    "count": count,
+                }],
+            });
+            TestTransport::json_response(StatusCode::OK, &grid)
+        })
+    }
+
+    fn fixture_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "raystack_fixture_{}_{}.json",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn source_msg(err: &TransportError) -> String {
+        std::error::Error::source(err).unwrap().to_string()
+    }
+
+    async fn new_client(
+        transport: Arc<dyn Transport>,
+        auth_token: &str,
+    ) -> HaystackClient {
+        let base_url = Url::parse("http://localhost:1/haystack/").unwrap();
+        HaystackClientBuilder::new(base_url, "name", "password")
+            .auth_token(auth_token)
+            .transport(transport)
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn records_and_replays() {
+        let path = fixture_path("records_and_replays");
+        let recorder =
+            Arc::new(RecordingTransport::new(Arc::new(echo_transport())));
+        let client = new_client(recorder.clone(), "s3cr3ttoken").await;
+
+        let about = client.about().await.unwrap();
+        let read1 = client.read("site", None).await.unwrap();
+        let read2 = client.read("site", None).await.unwrap();
+        assert_eq!(read2.rows()[0]["count"], 2);
+        assert_eq!(recorder.len(), 3);
+        recorder.save(&path).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("s3cr3ttoken"));
+        assert!(contents.contains("<scrubbed>"));
+
+        let replayer = Arc::new(ReplayTransport::load(&path).unwrap());
+        assert_eq!(replayer.remaining(), 3);
+        let client = new_client(replayer.clone(), "othertoken").await;
+
+        let replayed_about = client.about().await.unwrap();
+        assert_eq!(replayed_about.rows()[0]["path"], about.rows()[0]["path"]);
+        assert_eq!(
+            replayed_about.rows()[0]["auth"],
+            "BEARER authToken=<scrubbed>"
+        );
+        let replayed_read1 = client.read("site", None).await.unwrap();
+        let replayed_read2 = client.read("site", None).await.unwrap();
+        assert_eq!(replayed_read1.rows()[0]["count"], read1.rows()[0]["count"]);
+        assert_eq!(replayed_read2.rows()[0]["count"], read2.rows()[0]["count"]);
+        assert_eq!(replayer.remaining(), 0);
+
+        // Every recorded response has been replayed:
+        assert!(client.read("site", None).await.is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unrecorded_request_fails() {
+        let path = fixture_path("unrecorded_request_fails");
+        let recorder = RecordingTransport::new(Arc::new(echo_transport()));
+        assert!(recorder.is_empty());
+        recorder.save(&path).unwrap();
+
+        let replayer = ReplayTransport::load(&path).unwrap();
+        let url = Url::parse("http://localhost:1/haystack/about").unwrap();
+        let request = HttpRequest::new(Method::GET, url)
+            .with_header("authorization", "BEARER authToken=token");
+        let err = replayer.send(request).await.unwrap_err();
+        assert_eq!(
+            source_msg(&err),
+            "no recorded response for GET /haystack/about"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn authentication_requests_are_not_recorded() {
+        let recorder = RecordingTransport::new(Arc::new(echo_transport()));
+        let url = Url::parse("http://localhost:1/haystack/about").unwrap();
+        let request = HttpRequest::new(Method::GET, url)
+            .with_header("authorization", "HELLO username=bmFtZQ");
+        recorder.send(request.clone()).await.unwrap();
+        assert!(recorder.is_empty());
+
+        let path = fixture_path("authentication_requests_are_not_recorded");
+        recorder.save(&path).unwrap();
+        let replayer = ReplayTransport::load(&path).unwrap();
+        let err = replayer.send(request).await.unwrap_err();
+        assert!(source_msg(&err).contains("auth token"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scrubs_every_auth_token() {
+        let path = fixture_path("scrubs_every_auth_token");
+        let recorder =
+            Arc::new(RecordingTransport::new(Arc::new(echo_transport())));
+        let client = new_client(recorder.clone(), "firsttoken").await;
+        client.about().await.unwrap();
+
+        // A later request contains both the earlier auth token, and its own
+        // auth token:
+        let client = new_client(recorder.clone(), "secondtoken").await;
+        client.read("dis == \"firsttoken\"", None).await.unwrap();
+        recorder.save(&path).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("firsttoken"));
+        assert!(!contents.contains("secondtoken"));
+        assert!(!format!("{:?}", recorder).contains("token"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_invalid_fixture_fails() {
+        let path = fixture_path("load_invalid_fixture_fails");
+        std::fs::write(&path, r#"{"interactions": [{"request": 1}]}"#).unwrap();
+        assert!(ReplayTransport::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn records_client_which_authenticates() {
+        use crate::mock::MockServer;
+        use crate::SkySparkClientBuilder;
+
+        let server = MockServer::new("name", "s3cr3t");
+        let recorder =
+            Arc::new(RecordingTransport::new(Arc::new(server.clone())));
+        let client = SkySparkClientBuilder::new(
+            server.project_api_url(),
+            "name",
+            "s3cr3t",
+        )
+        .transport(recorder.clone())
+        .build()
+        .await
+        .unwrap();
+
+        client.about().await.unwrap();
+        assert_eq!(server.authentication_count(), 1);
+        assert_eq!(recorder.len(), 1);
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn replays_recording_which_reauthenticates() {
+        use crate::mock::MockServer;
+        use crate::SkySparkClientBuilder;
+
+        let path = fixture_path("replays_recording_which_reauthenticates");
+        let server = MockServer::new("name", "s3cr3t");
+        let builder = |transport: Arc<dyn Transport>| {
+            SkySparkClientBuilder::new(
+                server.project_api_url(),
+                "name",
+                "s3cr3t",
+            )
+            .transport(transport)
+        };
+        let recorder =
+            Arc::new(RecordingTransport::new(Arc::new(server.clone())));
+        let client = builder(recorder.clone()).build().await.unwrap();
+        let first_auth_token = client.auth_token().await;
+
+        client.about().await.unwrap();
+        server.expire_auth_tokens();
+        client.about().await.unwrap();
+        let second_auth_token = client.auth_token().await;
+        assert_ne!(first_auth_token, second_auth_token);
+
+        // The response which rejected the first auth token is not recorded:
+        assert_eq!(recorder.len(), 2);
+        recorder.save(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains(&first_auth_token));
+        assert!(!contents.contains(&second_auth_token));
+
+        let replayer = Arc::new(ReplayTransport::load(&path).unwrap());
+        let client = builder(replayer.clone())
+            .auth_token("replayed")
+            .build()
+            .await
+            .unwrap();
+        client.about().await.unwrap();
+        client.about().await.unwrap();
+        assert_eq!(replayer.remaining(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,7 @@
+use crate::trio::ParseTrioError;
+use crate::zinc::ParseZincError;
+#[cfg(feature = "grid_csv")]
+use chrono_tz::Tz;
 use raystack_core::Ref;
 use raystack_core::{is_tag_name, TagName};
 use serde_json::json;
@@ -75,6 +79,10 @@ impl Grid {
         json_grid.try_into()
     }
 
+    pub(crate) fn into_json(self) -> Value {
+        self.json
+    }
+
     pub(crate) fn new_internal(rows: Vec<Value>) -> Self {
         Self::new(rows)
             .expect("creating grids within this crate should never fail")
@@ -103,10 +111,16 @@ impl Grid {
 
     pub(crate) fn add_ref_to_meta(&mut self, hsref: &Ref) {
         use raystack_core::Hayson;
+        self.add_to_meta("id", hsref.to_hayson());
+    }
+
+    /// Add a key-value pair to the grid's metadata, overwriting any
+    /// existing value for that key.
+    pub(crate) fn add_to_meta(&mut self, key: &str, value: Value) {
         let meta = self.json["meta"]
             .as_object_mut()
             .expect("meta is a JSON Object");
-        meta.insert("id".to_owned(), hsref.to_hayson());
+        meta.insert(key.to_owned(), value);
     }
 
     /// Return a vector of JSON values which represent the columns of the grid.
@@ -452,6 +466,48 @@ impl Grid {
             .expect("serializing grid to String should never fail")
     }
 
+    /// Parse a grid from a string containing a grid encoded in the
+    /// Zinc format.
+    ///
+    /// # Example
+    /// ```rust
+    /// use raystack::Grid;
+    ///
+    /// let zinc = "ver:\"3.0\"\nfirstName,site\n\"Otis\",M\n";
+    /// let grid = Grid::from_zinc(zinc).unwrap();
+    /// assert_eq!(grid.rows()[0]["firstName"], "Otis");
+    /// ```
+    pub fn from_zinc(zinc: &str) -> Result<Self, ParseZincError> {
+        crate::zinc::parse_grid(zinc)
+    }
+
+    /// Return a string containing this grid encoded in the Zinc format.
+    pub fn to_zinc_string(&self) -> String {
+        crate::zinc::to_zinc_string(self)
+    }
+
+    /// Parse a grid from a string containing records in the Trio format.
+    /// Each record becomes a row in the grid.
+    ///
+    /// # Example
+    /// ```rust
+    /// use raystack::Grid;
+    ///
+    /// let trio = "dis: Main Street\nsite\n---\ndis: \"AHU 1\"\nequip\n";
+    /// let grid = Grid::from_trio(trio).unwrap();
+    /// assert_eq!(grid.rows()[1]["dis"], "AHU 1");
+    /// ```
+    pub fn from_trio(trio: &str) -> Result<Self, ParseTrioError> {
+        crate::trio::parse_trio(trio)
+    }
+
+    /// Return a string containing the rows of this grid as records in the
+    /// Trio format. The `id` and `dis` tags are written first in each
+    /// record, followed by the other tags in alphabetical order.
+    pub fn to_trio_string(&self) -> String {
+        crate::trio::to_trio_string(self)
+    }
+
     /// Returns true if the grid appears to be an error grid.
     pub fn is_error(&self) -> bool {
         self.meta().get("err").is_some()
@@ -538,6 +594,206 @@ impl Grid {
     pub fn to_csv_string(&self) -> Result<String, CsvError> {
         self.to_csv_string_with_ordered_cols(&self.col_name_strs())
     }
+
+    /// Create a new `Grid` from a CSV string. The first line of the CSV
+    /// string is a header containing the column names.
+    ///
+    /// A column name in the header may be followed by a colon and a type,
+    /// for example `area:Number` or `ts:DateTime Sydney`, which is parsed
+    /// as a `CsvColType`. The values in columns without a type are parsed
+    /// using `CsvColType::Auto`. Empty values are not added to the rows.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use raystack::{Grid, Number, ValueExt};
+    ///
+    /// let csv = "dis,area:Number,site\nMain Street,1200 ft²,✔\n";
+    /// let grid = Grid::from_csv_str(csv).unwrap();
+    ///
+    /// assert_eq!(grid.col_name_strs(), vec!["area", "dis", "site"]);
+    /// assert_eq!(
+    ///     grid.rows()[0]["area"].as_hs_number(),
+    ///     Some(Number::new(1200.0, Some("ft²".to_owned())))
+    /// );
+    /// assert!(grid.rows()[0]["site"].is_hs_marker());
+    /// ```
+    #[cfg(feature = "grid_csv")]
+    pub fn from_csv_str(csv: &str) -> Result<Self, CsvError> {
+        Self::from_csv_str_with_col_types(csv, &[])
+    }
+
+    /// Create a new `Grid` from a CSV string, using the given types to parse
+    /// the values in each named column. The given types take precedence
+    /// over any types in the CSV header. See `Grid::from_csv_str` for the
+    /// format of the CSV string.
+    ///
+    /// This can parse the CSV strings returned by `Grid::to_csv_string`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use raystack::{CsvColType, Grid, ValueExt};
+    ///
+    /// let csv = "siteRef,ts\n@site1,2021-01-01T00:00:00+10:00\n";
+    /// let col_types = vec![
+    ///     ("siteRef", CsvColType::Ref),
+    ///     ("ts", CsvColType::DateTime(Some(chrono_tz::Australia::Sydney))),
+    /// ];
+    /// let grid = Grid::from_csv_str_with_col_types(csv, &col_types).unwrap();
+    ///
+    /// assert!(grid.rows()[0]["siteRef"].is_hs_ref());
+    /// let ts = grid.rows()[0]["ts"].as_hs_date_time().unwrap();
+    /// assert_eq!(ts.short_time_zone(), "Sydney");
+    /// ```
+    #[cfg(feature = "grid_csv")]
+    pub fn from_csv_str_with_col_types(
+        csv: &str,
+        col_types: &[(&str, CsvColType)],
+    ) -> Result<Self, CsvError> {
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let mut cols: Vec<(TagName, CsvColType)> = Vec::new();
+
+        for header in reader.headers()?.iter() {
+            let (name, header_col_type) = match header.split_once(':') {
+                Some((name, col_type)) => {
+                    let col_type = col_type.trim().parse().map_err(|_| {
+                        CsvError::header(&format!(
+                            "'{}' is not a valid column type",
+                            col_type
+                        ))
+                    })?;
+                    (name.trim(), Some(col_type))
+                }
+                None => (header.trim(), None),
+            };
+
+            let tag_name: TagName = name.parse().map_err(|_| {
+                CsvError::header(&format!(
+                    "Column name '{}' is not a valid tag name",
+                    name
+                ))
+            })?;
+
+            if cols.iter().any(|(col_name, _)| col_name == &tag_name) {
+                return Err(CsvError::header(&format!(
+                    "Column name '{}' appears more than once",
+                    name
+                )));
+            }
+
+            let col_type = col_types
+                .iter()
+                .find(|(col_name, _)| *col_name == name)
+                .map(|(_, col_type)| col_type.clone())
+                .or(header_col_type)
+                .unwrap_or(CsvColType::Auto);
+
+            cols.push((tag_name, col_type));
+        }
+
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map_or(0, |pos| pos.line());
+            let mut row = Map::new();
+
+            for ((col_name, col_type), cell) in cols.iter().zip(record.iter()) {
+                let value =
+                    value_from_csv_string(cell, col_type).map_err(|msg| {
+                        CsvError::Value {
+                            col_name: col_name.to_string(),
+                            line,
+                            msg,
+                        }
+                    })?;
+
+                if let Some(value) = value {
+                    row.insert(col_name.to_string(), value);
+                }
+            }
+
+            rows.push(Value::Object(row));
+        }
+
+        let mut grid = Self::new_internal(rows);
+        // Keep columns which have no values:
+        let col_names = cols
+            .into_iter()
+            .map(|(col_name, _)| col_name)
+            .collect::<Vec<_>>();
+        grid.add_col_names(&col_names);
+        Ok(grid)
+    }
+}
+
+/// The Haystack type used to parse the values in a column when creating
+/// a `Grid` from a CSV string.
+#[cfg(feature = "grid_csv")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CsvColType {
+    /// Infer the type of each value. Markers (`✔`), Bools (`True` or
+    /// `False`), remove markers (`<R>`), NAs (`<Na>`), Numbers without
+    /// units, and Refs, Uris, Symbols, Coords, Xstrs, Dates, Times and
+    /// DateTimes in the Zinc format are inferred. All other values are Strs.
+    Auto,
+    /// A Bool, written as `True`, `False`, `true`, `false`, `T` or `F`.
+    Bool,
+    /// A Coord, written as `lat, lng` or `C(lat,lng)`.
+    Coord,
+    /// A Date, written as `YYYY-MM-DD`.
+    Date,
+    /// A DateTime in the RFC 3339 format, optionally followed by a space
+    /// and a time zone name. Values without a time zone name use the
+    /// given time zone, or UTC if the value ends with `Z`.
+    DateTime(Option<Tz>),
+    /// A Marker, written as `✔` or `M`.
+    Marker,
+    /// A Number, optionally followed by a unit, for example `21.5 °C`
+    /// or `21.5°C`.
+    Number,
+    /// A Ref, with or without the leading `@`.
+    Ref,
+    /// A Str. Values are not modified.
+    Str,
+    /// A Symbol, with or without the leading `^`.
+    Symbol,
+    /// A Time, written as `hh:mm:ss`.
+    Time,
+    /// A Uri, with or without surrounding backticks.
+    Uri,
+}
+
+#[cfg(feature = "grid_csv")]
+impl std::str::FromStr for CsvColType {
+    type Err = ();
+
+    /// Parse a column type from a CSV header, for example `Number` or
+    /// `DateTime New_York`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Auto" => Ok(Self::Auto),
+            "Bool" => Ok(Self::Bool),
+            "Coord" => Ok(Self::Coord),
+            "Date" => Ok(Self::Date),
+            "DateTime" => Ok(Self::DateTime(None)),
+            "Marker" => Ok(Self::Marker),
+            "Number" => Ok(Self::Number),
+            "Ref" => Ok(Self::Ref),
+            "Str" => Ok(Self::Str),
+            "Symbol" => Ok(Self::Symbol),
+            "Time" => Ok(Self::Time),
+            "Uri" => Ok(Self::Uri),
+            _ => match s.split_once(' ') {
+                Some(("DateTime", tz_name)) => {
+                    crate::skyspark_tz_string_to_tz(tz_name.trim())
+                        .map(|tz| Self::DateTime(Some(tz)))
+                        .ok_or(())
+                }
+                _ => Err(()),
+            },
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -547,6 +803,24 @@ pub enum CsvError {
     Internal(#[from] csv::Error),
     #[error("Error consuming a CSV writer")]
     Writer(#[from] Box<csv::IntoInnerError<csv::Writer<Vec<u8>>>>),
+    #[error("Invalid CSV header: {msg}")]
+    Header { msg: String },
+    #[error("Could not parse the value in column '{col_name}' on line {line}: {msg}")]
+    Value {
+        /// The name of the column containing the value.
+        col_name: String,
+        /// The line of the CSV string containing the value.
+        line: u64,
+        /// A description of why the value could not be parsed.
+        msg: String,
+    },
+}
+
+#[cfg(feature = "grid_csv")]
+impl CsvError {
+    fn header(msg: &str) -> Self {
+        CsvError::Header { msg: msg.into() }
+    }
 }
 
 #[cfg(feature = "grid_csv")]
@@ -614,6 +888,175 @@ fn value_to_string(value: &Value) -> String {
     }
 }
 
+/// Parse a CSV cell into a Hayson value, returning `None` if the cell is
+/// empty. This is the inverse of `value_to_string`.
+#[cfg(feature = "grid_csv")]
+fn value_from_csv_string(
+    cell: &str,
+    col_type: &CsvColType,
+) -> Result<Option<Value>, String> {
+    use crate::{Coord, Date, DateTime, Time, ValueExt};
+    use chrono::{NaiveDate, NaiveTime};
+    use raystack_core::{Hayson, Marker, Na, RemoveMarker, Symbol, Uri};
+
+    let trimmed = cell.trim();
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+    let invalid = |type_name: &str| {
+        Err(format!("'{}' is not a valid {}", trimmed, type_name))
+    };
+
+    let value = match col_type {
+        CsvColType::Auto => match trimmed {
+            "✔" => Marker::new().to_hayson(),
+            "<R>" => RemoveMarker::new().to_hayson(),
+            "<Na>" => Na::new().to_hayson(),
+            "True" => Value::Bool(true),
+            "False" => Value::Bool(false),
+            _ => match crate::zinc::parse_value(trimmed) {
+                Ok(value) if is_inferred_csv_value(&value) => value,
+                _ => Value::String(cell.to_owned()),
+            },
+        },
+        CsvColType::Bool => match trimmed {
+            "True" | "true" | "T" => Value::Bool(true),
+            "False" | "false" | "F" => Value::Bool(false),
+            _ => return invalid("Bool"),
+        },
+        CsvColType::Coord => {
+            let lat_lng = trimmed
+                .strip_prefix("C(")
+                .and_then(|s| s.strip_suffix(')'))
+                .unwrap_or(trimmed);
+            let coord = lat_lng.split_once(',').and_then(|(lat, lng)| {
+                let lat = lat.trim().parse().ok()?;
+                let lng = lng.trim().parse().ok()?;
+                Some(Coord::new(lat, lng))
+            });
+            match coord {
+                Some(coord) => coord.to_hayson(),
+                None => return invalid("Coord"),
+            }
+        }
+        CsvColType::Date => {
+            match NaiveDate::parse_from_str(trimmed, "%Y-%m-%d") {
+                Ok(date) => Date::new(date).to_hayson(),
+                Err(_) => return invalid("Date"),
+            }
+        }
+        CsvColType::DateTime(default_tz) => {
+            let (date_time, tz_name) = match trimmed.split_once(' ') {
+                Some((date_time, tz_name)) => (date_time, Some(tz_name.trim())),
+                None => (trimmed, None),
+            };
+            let date_time =
+                match chrono::DateTime::parse_from_rfc3339(date_time) {
+                    Ok(date_time) => date_time,
+                    Err(_) => return invalid("DateTime"),
+                };
+            let tz = match tz_name {
+                Some(tz_name) => crate::skyspark_tz_string_to_tz(tz_name)
+                    .ok_or_else(|| {
+                        format!("Unknown time zone '{}'", tz_name)
+                    })?,
+                None if default_tz.is_some() => default_tz.unwrap(),
+                None if date_time.offset().local_minus_utc() == 0 => Tz::UTC,
+                None => {
+                    return Err(format!(
+                        "DateTime '{}' does not have a time zone",
+                        trimmed
+                    ))
+                }
+            };
+            DateTime::new(date_time.with_timezone(&tz)).to_hayson()
+        }
+        CsvColType::Marker => match trimmed {
+            "✔" | "M" => Marker::new().to_hayson(),
+            _ => return invalid("Marker"),
+        },
+        CsvColType::Number => match number_from_csv_string(trimmed) {
+            Some(number) => number,
+            None => return invalid("Number"),
+        },
+        CsvColType::Ref => {
+            // Ignore any display name after the ref:
+            let id = trimmed.split(' ').next().unwrap_or(trimmed);
+            let id = if id.starts_with('@') {
+                id.to_owned()
+            } else {
+                format!("@{}", id)
+            };
+            match Ref::new(id) {
+                Ok(hs_ref) => hs_ref.to_hayson(),
+                Err(_) => return invalid("Ref"),
+            }
+        }
+        CsvColType::Str => Value::String(cell.to_owned()),
+        CsvColType::Symbol => {
+            let symbol = if trimmed.starts_with('^') {
+                trimmed.to_owned()
+            } else {
+                format!("^{}", trimmed)
+            };
+            match Symbol::new(symbol) {
+                Ok(symbol) => symbol.to_hayson(),
+                Err(_) => return invalid("Symbol"),
+            }
+        }
+        CsvColType::Time => match trimmed.parse::<NaiveTime>() {
+            Ok(time) => Time::new(time).to_hayson(),
+            Err(_) => return invalid("Time"),
+        },
+        CsvColType::Uri => {
+            let uri = trimmed
+                .strip_prefix('`')
+                .and_then(|s| s.strip_suffix('`'))
+                .unwrap_or(trimmed);
+            Uri::new(uri.to_owned()).to_hayson()
+        }
+    };
+
+    // Values which look like a Haystack type are only inferred when
+    // they are unambiguous:
+    fn is_inferred_csv_value(value: &Value) -> bool {
+        value.is_hs_coord()
+            || value.is_hs_date()
+            || value.is_hs_date_time()
+            || value.is_hs_ref()
+            || value.is_hs_symbol()
+            || value.is_hs_time()
+            || value.is_hs_uri()
+            || value.is_hs_xstr()
+            || matches!(value.as_hs_number(), Some(number) if number.unit().is_none())
+    }
+
+    Ok(Some(value))
+}
+
+/// Parse a Number with an optional unit, where the unit may be separated
+/// from the number by a space.
+#[cfg(feature = "grid_csv")]
+fn number_from_csv_string(s: &str) -> Option<Value> {
+    use crate::ValueExt;
+    use raystack_core::{Hayson, Number};
+
+    let (number, unit) = match s.split_once(' ') {
+        Some((number, unit)) => (number, Some(unit.trim())),
+        None => (s, None),
+    };
+    let number = crate::zinc::parse_value(number).ok()?.as_hs_number()?;
+
+    match unit {
+        Some(unit) if number.unit().is_none() => {
+            let value = number.as_number()?.value();
+            Some(Number::new(value, Some(unit.to_owned())).to_hayson())
+        }
+        Some(_) => None,
+        None => Some(number.to_hayson()),
+    }
+}
+
 impl std::convert::TryFrom<Value> for Grid {
     type Error = ParseJsonGridError;
 
@@ -1118,4 +1561,139 @@ mod test {
         assert_eq!(grid.rows()[0]["id"].as_str().unwrap(), "a");
         assert_eq!(grid.rows()[1]["id"].as_str().unwrap(), "b");
     }
+
+    #[cfg(feature = "grid_csv")]
+    #[test]
+    fn from_csv_str_round_trips_to_csv_string() {
+        use super::CsvColType;
+        use crate::{Date, DateTime, Hayson, Marker, Number, Ref};
+        use chrono::NaiveDate;
+
+        let ts: DateTime =
+            chrono::DateTime::parse_from_rfc3339("2021-06-01T12:30:00+10:00")
+                .unwrap()
+                .with_timezone(&chrono_tz::Australia::Sydney)
+                .into();
+        let rows = vec![
+            json!({
+                "id": Ref::new("@p:demo:r:1".to_owned()).unwrap().to_hayson(),
+                "dis": "AHU 1, North",
+                "equip": Marker::new().to_hayson(),
+                "area": Number::new(1200.5, Some("ft²".to_owned())).to_hayson(),
+                "installed": Date::new(NaiveDate::from_ymd_opt(2020, 2, 29).unwrap()).to_hayson(),
+                "ts": ts.to_hayson(),
+                "enabled": true,
+            }),
+            json!({"dis": "12"}),
+        ];
+        let grid = Grid::new(rows).unwrap();
+        let csv = grid.to_csv_string().unwrap();
+
+        let col_types = vec![
+            ("area", CsvColType::Number),
+            ("dis", CsvColType::Str),
+            (
+                "ts",
+                CsvColType::DateTime(Some(chrono_tz::Australia::Sydney)),
+            ),
+        ];
+        let parsed =
+            Grid::from_csv_str_with_col_types(&csv, &col_types).unwrap();
+        assert_eq!(parsed, grid);
+    }
+
+    #[cfg(feature = "grid_csv")]
+    #[test]
+    fn from_csv_str_with_header_types() {
+        use crate::{Hayson, Number, Symbol, ValueExt};
+
+        let csv = "name:Str,temp:Number,kind:Symbol,ts:DateTime Sydney,empty\n\
+                   12,21.5°C,elec-meter,2021-01-01T00:00:00+11:00,\n\
+                   ,-3 kW,,,\n";
+        let grid = Grid::from_csv_str(csv).unwrap();
+
+        assert_eq!(
+            grid.col_name_strs(),
+            vec!["empty", "kind", "name", "temp", "ts"]
+        );
+        let rows = grid.rows();
+        assert_eq!(rows[0]["name"], "12");
+        assert_eq!(
+            rows[0]["temp"],
+            Number::new(21.5, Some("°C".to_owned())).to_hayson()
+        );
+        assert_eq!(
+            rows[0]["kind"],
+            Symbol::new("^elec-meter".to_owned()).unwrap().to_hayson()
+        );
+        assert_eq!(
+            rows[0]["ts"].as_hs_date_time().unwrap().short_time_zone(),
+            "Sydney"
+        );
+        assert_eq!(
+            rows[1]["temp"],
+            Number::new(-3.0, Some("kW".to_owned())).to_hayson()
+        );
+        assert!(rows[1].get("name").is_none());
+    }
+
+    #[cfg(feature = "grid_csv")]
+    #[test]
+    fn from_csv_str_infers_types() {
+        use crate::{Hayson, Number, ValueExt};
+
+        let csv = "val\n✔\nTrue\n@abc\n`http://a.com`\n42\n2021-01-01\n\
+                   10:30:00\n21.5kW\nSite 1\nN\n";
+        let grid = Grid::from_csv_str(csv).unwrap();
+        let vals = grid.col_to_vec("val");
+
+        assert!(vals[0].unwrap().is_hs_marker());
+        assert_eq!(vals[1].unwrap(), true);
+        assert!(vals[2].unwrap().is_hs_ref());
+        assert!(vals[3].unwrap().is_hs_uri());
+        assert_eq!(vals[4].unwrap(), &Number::new_unitless(42.0).to_hayson());
+        assert!(vals[5].unwrap().is_hs_date());
+        assert!(vals[6].unwrap().is_hs_time());
+        // Numbers with units need a type hint:
+        assert_eq!(vals[7].unwrap(), "21.5kW");
+        assert_eq!(vals[8].unwrap(), "Site 1");
+        assert_eq!(vals[9].unwrap(), "N");
+    }
+
+    #[cfg(feature = "grid_csv")]
+    #[test]
+    fn from_csv_str_errors() {
+        use super::{CsvColType, CsvError};
+
+        assert!(matches!(
+            Grid::from_csv_str("Bad Name\n1\n"),
+            Err(CsvError::Header { .. })
+        ));
+        assert!(matches!(
+            Grid::from_csv_str("a:Unknown\n1\n"),
+            Err(CsvError::Header { .. })
+        ));
+        assert!(matches!(
+            Grid::from_csv_str("a,a\n1,2\n"),
+            Err(CsvError::Header { .. })
+        ));
+
+        let col_types = vec![("a", CsvColType::Number)];
+        let err = Grid::from_csv_str_with_col_types("a\n1\nabc\n", &col_types)
+            .unwrap_err();
+        match err {
+            CsvError::Value { col_name, line, .. } => {
+                assert_eq!(col_name, "a");
+                assert_eq!(line, 3);
+            }
+            _ => panic!("expected a value error"),
+        }
+
+        let col_types = vec![("ts", CsvColType::DateTime(None))];
+        assert!(Grid::from_csv_str_with_col_types(
+            "ts\n2021-01-01T00:00:00+10:00\n",
+            &col_types
+        )
+        .is_err());
+    }
 }
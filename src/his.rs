@@ -0,0 +1,378 @@
+//! Functions for working with the multi-point grids used by batch
+//! hisRead and hisWrite requests.
+
+use crate::{skyspark_tz_string_to_tz, DateTime, Error, Grid, Ref, ValueExt};
+use chrono::{NaiveDateTime, Utc};
+use raystack_core::Hayson;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The maximum number of values sent in each multi-point hisWrite request.
+pub(crate) const HIS_WRITE_MANY_MAX_VALUES: usize = 10_000;
+
+/// Split the grid returned by a batch hisRead request into one grid per
+/// point. The batch grid has a `ts` column, and a `v0`, `v1`, ... column
+/// for each point, where each value column has the point's id in its meta.
+///
+/// Each returned grid has the same format as a single-point hisRead grid,
+/// with `ts` and `val` columns, and the point's id in the grid meta. The
+/// grids are keyed by the string form of each point's id, such as `@abc`.
+pub(crate) fn split_his_read_many_grid(
+    grid: &Grid,
+) -> Result<HashMap<String, Grid>> {
+    let meta = grid.meta();
+    let mut his_grids = HashMap::new();
+
+    for col in grid.cols() {
+        let col_name = col["name"].as_str().expect("col name is a JSON string");
+        if col_name == "ts" {
+            continue;
+        }
+
+        let id = col["meta"]["id"].as_hs_ref().ok_or_else(|| {
+            Error::unexpected_grid(&format!(
+                "hisRead column {} does not have an id in its meta",
+                col_name
+            ))
+        })?;
+
+        let rows = grid
+            .rows()
+            .iter()
+            .filter_map(|row| match row.get(col_name) {
+                Some(val) if !val.is_null() => {
+                    Some(json!({"ts": row["ts"], "val": val}))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut his_grid = Grid::new_internal(rows);
+        his_grid.add_ref_to_meta(&id);
+        for key in &["hisStart", "hisEnd"] {
+            if let Some(value) = meta.get(*key) {
+                his_grid.add_to_meta(key, value.clone());
+            }
+        }
+
+        his_grids.insert(id.into_string(), his_grid);
+    }
+
+    Ok(his_grids)
+}
+
+/// Create the request grid for a single-point hisWrite, using `to_hayson`
+/// to encode each value.
+pub(crate) fn his_write_grid<V, F>(
+    id: &Ref,
+    his_data: &[(DateTime, V)],
+    to_hayson: F,
+) -> Grid
+where
+    F: Fn(&V) -> Value,
+{
+    let rows = his_data
+        .iter()
+        .map(|(date_time, value)| {
+            json!({
+                "ts": date_time.to_hayson(),
+                "val": to_hayson(value),
+            })
+        })
+        .collect();
+
+    let mut req_grid = Grid::new_internal(rows);
+    req_grid.add_ref_to_meta(id);
+    req_grid
+}
+
+/// Create the request grid for a single-point hisWrite, converting each
+/// UTC timestamp into the time zone with the given SkySpark time zone name.
+pub(crate) fn utc_his_write_grid<V, F>(
+    id: &Ref,
+    time_zone_name: &str,
+    his_data: &[(chrono::DateTime<Utc>, V)],
+    to_hayson: F,
+) -> Result<Grid>
+where
+    F: Fn(&V) -> Value,
+{
+    let tz = skyspark_tz_string_to_tz(time_zone_name).ok_or_else(|| {
+        Error::TimeZone {
+            err_time_zone: time_zone_name.to_owned(),
+        }
+    })?;
+
+    let his_data = his_data
+        .iter()
+        .map(|(date_time, value)| {
+            let date_time: DateTime = date_time.with_timezone(&tz).into();
+            (date_time, to_hayson(value))
+        })
+        .collect::<Vec<_>>();
+
+    Ok(his_write_grid(id, &his_data, Value::clone))
+}
+
+/// Create the request grids for a multi-point hisWrite. The values for
+/// each point are split across as many grids as required so that no grid
+/// contains more than `max_values` values.
+///
+/// Each grid has a `ts` column, and a `v0`, `v1`, ... column for each point,
+/// where each value column has the point's id in its meta.
+pub(crate) fn his_write_many_grids(
+    his_data: &[(Ref, Vec<(DateTime, Value)>)],
+    max_values: usize,
+) -> Vec<Grid> {
+    assert!(max_values > 0, "max_values must be greater than 0");
+
+    let mut grids = Vec::new();
+    let mut chunk: Vec<(&Ref, &[(DateTime, Value)])> = Vec::new();
+    let mut chunk_value_count = 0;
+
+    for (id, values) in his_data {
+        for values in values.chunks(max_values) {
+            if chunk_value_count + values.len() > max_values {
+                grids.push(his_write_many_grid(&chunk));
+                chunk.clear();
+                chunk_value_count = 0;
+            }
+            chunk.push((id, values));
+            chunk_value_count += values.len();
+        }
+    }
+
+    if !chunk.is_empty() {
+        grids.push(his_write_many_grid(&chunk));
+    }
+
+    grids
+}
+
+fn his_write_many_grid(his_data: &[(&Ref, &[(DateTime, Value)])]) -> Grid {
+    let mut cols = vec![json!({"name": "ts"})];
+    let mut rows: BTreeMap<NaiveDateTime, Value> = BTreeMap::new();
+
+    for (index, (id, values)) in his_data.iter().enumerate() {
+        let col_name = format!("v{}", index);
+        cols.push(json!({"name": col_name, "meta": {"id": id.to_hayson()}}));
+
+        for (date_time, value) in values.iter() {
+            let row = rows
+                .entry(date_time.date_time().naive_utc())
+                .or_insert_with(|| json!({"ts": date_time.to_hayson()}));
+            row.as_object_mut()
+                .expect("row is a JSON object")
+                .insert(col_name.clone(), value.clone());
+        }
+    }
+
+    json!({
+        "_kind": "grid",
+        "meta": {"ver": "3.0"},
+        "cols": cols,
+        "rows": rows.into_values().collect::<Vec<_>>(),
+    })
+    .try_into()
+    .expect("creating grids within this crate should never fail")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        his_write_many_grids, split_his_read_many_grid, utc_his_write_grid,
+        HIS_WRITE_MANY_MAX_VALUES,
+    };
+    use crate::{DateTime, Grid, Number, Ref};
+    use raystack_core::Hayson;
+    use serde_json::json;
+    use std::convert::TryInto;
+
+    #[test]
+    fn split_his_read_many_grid_works() {
+        let id1 = Ref::new("@abc".to_owned()).unwrap();
+        let id2 = Ref::new("@def".to_owned()).unwrap();
+        let ts1 = json!({"_kind": "dateTime", "val": "2021-01-01T00:00:00Z", "tz": "UTC"});
+        let ts2 = json!({"_kind": "dateTime", "val": "2021-01-01T00:05:00Z", "tz": "UTC"});
+        let num = Number::new_unitless(1.0).to_hayson();
+
+        let grid: Grid = json!({
+            "_kind": "grid",
+            "meta": {"ver": "3.0", "hisStart": ts1, "hisEnd": ts2},
+            "cols": [
+                {"name": "ts"},
+                {"name": "v0", "meta": {"id": id1.to_hayson()}},
+                {"name": "v1", "meta": {"id": id2.to_hayson()}},
+            ],
+            "rows": [
+                {"ts": ts1, "v0": num, "v1": true},
+                {"ts": ts2, "v1": false},
+            ],
+        })
+        .try_into()
+        .unwrap();
+
+        let his_grids = split_his_read_many_grid(&grid).unwrap();
+        assert_eq!(his_grids.len(), 2);
+
+        let grid1 = &his_grids[id1.as_ref()];
+        assert_eq!(grid1.size(), 1);
+        assert_eq!(grid1.rows()[0]["val"], num);
+        assert_eq!(grid1.meta()["id"], id1.to_hayson());
+        assert_eq!(grid1.meta()["hisStart"], ts1);
+
+        let grid2 = &his_grids[id2.as_ref()];
+        assert_eq!(grid2.size(), 2);
+        assert_eq!(grid2.rows()[1]["ts"], ts2);
+        assert_eq!(grid2.rows()[1]["val"], false);
+    }
+
+    #[test]
+    fn split_his_read_many_grid_without_col_id_fails() {
+        let grid = Grid::new(vec![json!({"ts": 1, "v0": 2})]).unwrap();
+        assert!(split_his_read_many_grid(&grid).is_err());
+    }
+
+    fn date_time(minutes: i64) -> DateTime {
+        let start =
+            chrono::DateTime::parse_from_rfc3339("2021-01-01T00:00:00+10:00")
+                .unwrap()
+                .with_timezone(&chrono_tz::Australia::Sydney);
+        (start + chrono::Duration::minutes(minutes)).into()
+    }
+
+    #[test]
+    fn his_write_many_grids_works() {
+        let id1 = Ref::new("@abc".to_owned()).unwrap();
+        let id2 = Ref::new("@def".to_owned()).unwrap();
+        let his_data = vec![
+            (id1.clone(), vec![(date_time(0), json!(true))]),
+            (
+                id2.clone(),
+                vec![(date_time(5), json!(false)), (date_time(0), json!(true))],
+            ),
+        ];
+
+        let grids = his_write_many_grids(&his_data, 100);
+        assert_eq!(grids.len(), 1);
+
+        let grid = &grids[0];
+        assert_eq!(grid.col_name_strs(), vec!["ts", "v0", "v1"]);
+        assert_eq!(grid.cols()[1]["meta"]["id"], id1.to_hayson());
+        assert_eq!(grid.cols()[2]["meta"]["id"], id2.to_hayson());
+
+        // Rows are merged by timestamp, and sorted by timestamp:
+        assert_eq!(grid.size(), 2);
+        assert_eq!(grid.rows()[0]["ts"], date_time(0).to_hayson());
+        assert_eq!(grid.rows()[0]["v0"], true);
+        assert_eq!(grid.rows()[0]["v1"], true);
+        assert_eq!(grid.rows()[1]["ts"], date_time(5).to_hayson());
+        assert!(grid.rows()[1].get("v0").is_none());
+        assert_eq!(grid.rows()[1]["v1"], false);
+    }
+
+    #[test]
+    fn his_write_many_grids_chunks_values() {
+        let id1 = Ref::new("@abc".to_owned()).unwrap();
+        let id2 = Ref::new("@def".to_owned()).unwrap();
+        let values = (0..5)
+            .map(|minutes| (date_time(minutes), json!(true)))
+            .collect::<Vec<_>>();
+        let his_data = vec![(id1, values.clone()), (id2.clone(), values)];
+
+        let grids = his_write_many_grids(&his_data, 3);
+        let sizes = grids
+            .iter()
+            .map(|grid| {
+                grid.rows()
+                    .iter()
+                    .map(|row| row.as_object().unwrap().len() - 1)
+                    .sum::<usize>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![3, 2, 3, 2]);
+        assert_eq!(grids[2].cols()[1]["meta"]["id"], id2.to_hayson());
+    }
+
+    #[test]
+    fn his_write_many_grids_with_no_data() {
+        assert!(his_write_many_grids(&[], 10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn his_write_many_failure_returns_written_grids() {
+        use crate::transport::TestTransport;
+        use crate::{HaystackClientBuilder, HttpResponse, RetryPolicy};
+        use reqwest::header::HeaderMap;
+        use reqwest::StatusCode;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use url::Url;
+
+        let request_count = AtomicUsize::new(0);
+        let transport = Arc::new(TestTransport::new(move |_| {
+            if request_count.fetch_add(1, Ordering::SeqCst) == 0 {
+                let grid = json!({
+                    "_kind": "grid",
+                    "meta": {"ver": "3.0", "written": true},
+                    "cols": [{"name": "empty"}],
+                    "rows": [],
+                });
+                TestTransport::json_response(StatusCode::OK, &grid)
+            } else {
+                let status = StatusCode::INTERNAL_SERVER_ERROR;
+                HttpResponse::new(status, HeaderMap::new(), Vec::new())
+            }
+        }));
+        let base_url = Url::parse("http://localhost:1/haystack/").unwrap();
+        let client = HaystackClientBuilder::new(base_url, "name", "password")
+            .auth_token("token")
+            .retry_policy(RetryPolicy::none())
+            .transport(transport.clone())
+            .build()
+            .await
+            .unwrap();
+
+        // The values are split across two requests, and the second fails:
+        let id = Ref::new("@abc".to_owned()).unwrap();
+        let values = (0..=HIS_WRITE_MANY_MAX_VALUES as i64)
+            .map(|minutes| (date_time(minutes), json!(true)))
+            .collect::<Vec<_>>();
+        let err = client.his_write_many(&[(id, values)]).await.unwrap_err();
+
+        assert_eq!(transport.requests().len(), 2);
+        assert_eq!(err.written().len(), 1);
+        assert_eq!(err.written()[0].meta()["written"], true);
+        assert!(matches!(err.error(), crate::Error::Http { .. }));
+    }
+
+    #[test]
+    fn utc_his_write_grid_works() {
+        let id = Ref::new("@abc".to_owned()).unwrap();
+        let utc_date_time =
+            date_time(0).date_time().with_timezone(&chrono::Utc);
+        let his_data = vec![(utc_date_time, Number::new_unitless(1.0))];
+
+        let grid =
+            utc_his_write_grid(&id, "Sydney", &his_data, Number::to_hayson)
+                .unwrap();
+        assert_eq!(grid.meta()["id"], id.to_hayson());
+        assert_eq!(grid.rows()[0]["ts"], date_time(0).to_hayson());
+        assert_eq!(grid.rows()[0]["val"], his_data[0].1.to_hayson());
+    }
+
+    #[test]
+    fn utc_his_write_grid_with_bad_time_zone_fails() {
+        let id = Ref::new("@abc".to_owned()).unwrap();
+        let utc_date_time =
+            date_time(0).date_time().with_timezone(&chrono::Utc);
+        let his_data = vec![(utc_date_time, true)];
+
+        let grid =
+            utc_his_write_grid(&id, "NotATimeZone", &his_data, |v| json!(v));
+        assert!(grid.is_err());
+    }
+}
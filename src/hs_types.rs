@@ -5,6 +5,7 @@
 use chrono::{NaiveDate, NaiveTime};
 use chrono_tz::Tz;
 use raystack_core::{FromHaysonError, Hayson};
+use serde_json::map::Map;
 use serde_json::{json, Value};
 use std::convert::From;
 
@@ -248,6 +249,54 @@ impl From<chrono::DateTime<Tz>> for DateTime {
     }
 }
 
+/// A Haystack Dict.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dict(Map<String, Value>);
+
+impl Dict {
+    pub fn new(map: Map<String, Value>) -> Self {
+        Dict(map)
+    }
+
+    pub fn map(&self) -> &Map<String, Value> {
+        &self.0
+    }
+
+    pub fn into_map(self) -> Map<String, Value> {
+        self.0
+    }
+}
+
+impl Hayson for Dict {
+    fn from_hayson(value: &Value) -> Result<Self, FromHaysonError> {
+        match &value {
+            Value::Object(obj) => {
+                // The kind is optional for Dicts:
+                if obj.contains_key(KIND) {
+                    if let Some(kind_err) = hayson_check_kind("dict", value) {
+                        return Err(kind_err);
+                    }
+                }
+
+                let mut map = obj.clone();
+                map.remove(KIND);
+                Ok(Dict::new(map))
+            }
+            _ => hayson_error("Dict JSON value must be an object"),
+        }
+    }
+
+    fn to_hayson(&self) -> Value {
+        Value::Object(self.map().clone())
+    }
+}
+
+impl From<Map<String, Value>> for Dict {
+    fn from(map: Map<String, Value>) -> Self {
+        Self::new(map)
+    }
+}
+
 fn hayson_error<T, M>(message: M) -> Result<T, FromHaysonError>
 where
     M: AsRef<str>,
@@ -286,10 +335,11 @@ fn hayson_check_kind(
 
 #[cfg(test)]
 mod test {
-    use crate::{Date, DateTime, Time};
+    use crate::{Date, DateTime, Dict, Time};
     use chrono::{NaiveDate, NaiveTime};
     use chrono_tz::Tz;
     use raystack_core::Hayson;
+    use serde_json::json;
 
     #[test]
     fn serde_date_works() {
@@ -345,6 +395,26 @@ mod test {
         assert_eq!(x, deserialized);
     }
 
+    #[test]
+    fn serde_dict_works() {
+        let value = json!({"dis": "Site", "site": {"_kind": "marker"}});
+        let x = Dict::new(value.as_object().unwrap().clone());
+        let value = x.to_hayson();
+        let deserialized = Dict::from_hayson(&value).unwrap();
+        assert_eq!(x, deserialized);
+    }
+
+    #[test]
+    fn dict_with_kind_works() {
+        let value = json!({"_kind": "dict", "dis": "Site"});
+        let dict = Dict::from_hayson(&value).unwrap();
+        assert_eq!(dict.map().len(), 1);
+        assert_eq!(dict.map()["dis"], "Site");
+
+        let value = json!({"_kind": "marker"});
+        assert!(Dict::from_hayson(&value).is_err());
+    }
+
     #[test]
     fn short_time_zone_works() {
         let dt: DateTime =
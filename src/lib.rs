@@ -1,8 +1,6 @@
 //! # Overview
 //! This crate provides functions which can query a SkySpark server, using
 //! the Haystack REST API and the SkySpark REST API's `eval` operation.
-//! Some Haystack operations are not implemented
-//! (watch* operations, pointWrite and invokeAction).
 //!
 //! # Example Usage
 //! Put this in the main function in the `main.rs` file to create
@@ -14,7 +12,7 @@
 //! use url::Url;
 //!
 //! let url = Url::parse("https://www.example.com/api/projName/").unwrap();
-//! let mut client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
+//! let client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
 //! let sites_grid = client.eval("readAll(site)").await.unwrap();
 //!
 //! // Print the raw JSON:
@@ -35,65 +33,287 @@
 //!
 //! Additional functions for extracting Haystack values from the underlying
 //! JSON are found in this crate's `ValueExt` trait.
+//!
+//! # Other Haystack Servers
+//! The Haystack ops are provided by `HaystackClient`, and a
+//! `SkySparkClient` provides the same ops, as well as the SkySpark ops, by
+//! calling the `HaystackClient` returned by its `as_haystack_client`
+//! method. A `HaystackClient` can also be created directly, using
+//! `HaystackClientBuilder`, to connect to Haystack 4 servers which do not
+//! use SkySpark's URL layout.
 
 mod api;
 pub mod auth;
+mod builder;
+mod credentials;
 mod err;
 pub mod eval;
+mod fixture;
 mod grid;
+mod his;
 mod hs_types;
+mod limiter;
+mod metrics;
+#[cfg(feature = "mock")]
+pub mod mock;
+mod point_write;
+mod retry;
+mod token_store;
+mod transport;
+mod trio;
 mod tz;
 mod value_ext;
+mod watch;
+mod zinc;
 
 use api::HaystackUrl;
-pub use api::HisReadRange;
+pub use api::{GridFormat, HisReadRange};
+use auth::{AuthSchemes, SaltedPasswordCache, SALTED_PASSWORD_CACHE_CAPACITY};
+pub use builder::{HaystackClientBuilder, SkySparkClientBuilder};
 use chrono::Utc;
-pub use err::{Error, NewSkySparkClientError};
+pub use credentials::{
+    CallbackCredentials, CredentialProvider, Credentials, CredentialsError,
+    EnvCredentials, StaticCredentials,
+};
+pub use err::{Error, HisWriteManyError, NewSkySparkClientError};
+pub use fixture::{RecordingTransport, ReplayTransport};
+#[cfg(feature = "grid_csv")]
+pub use grid::{CsvColType, CsvError};
 pub use grid::{Grid, ParseJsonGridError};
-pub use hs_types::{Date, DateTime, Time};
+pub use hs_types::{Date, DateTime, Dict, Time};
+use limiter::{LimitedTransport, RequestLimiter};
+pub use metrics::ClientMetrics;
+use metrics::MetricsCounters;
+pub use point_write::{PointWriteArray, PointWriteLevel, WriteLevel};
 pub use raystack_core::Coord;
 pub use raystack_core::{is_tag_name, ParseTagNameError, TagName};
 pub use raystack_core::{BasicNumber, Number, ScientificNumber};
 pub use raystack_core::{FromHaysonError, Hayson};
 pub use raystack_core::{Marker, Na, RemoveMarker, Symbol, Uri, Xstr};
 pub use raystack_core::{ParseRefError, Ref};
-use serde_json::json;
+use reqwest::{Method, StatusCode};
+pub use retry::{RetryPolicy, RetryableStatus};
+use serde_json::map::Map;
+use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+pub use token_store::{FileTokenStore, MemoryTokenStore, TokenStore};
+use tokio::sync::{Mutex, RwLock};
+pub use transport::{
+    HttpRequest, HttpResponse, ReqwestTransport, Transport, TransportError,
+    TransportErrorKind,
+};
+pub use trio::ParseTrioError;
 pub use tz::skyspark_tz_string_to_tz;
 use url::Url;
 pub use value_ext::ValueExt;
+pub use watch::{Watch, WatchStream};
+pub use zinc::ParseZincError;
 
 type Result<T> = std::result::Result<T, Error>;
 type StdResult<T, E> = std::result::Result<T, E>;
 
 pub(crate) async fn new_auth_token(
-    project_api_url: &Url,
-    reqwest_client: &reqwest::Client,
+    auth_url: &Url,
+    transport: &dyn Transport,
     username: &str,
     password: &str,
+    auth_schemes: &AuthSchemes,
+    salted_password_cache: Option<&std::sync::Mutex<SaltedPasswordCache>>,
 ) -> StdResult<String, crate::auth::AuthError> {
-    let mut auth_url = project_api_url.clone();
-    auth_url.set_path("/ui");
-
     let auth_token = auth::new_auth_token(
-        reqwest_client,
-        auth_url.as_str(),
+        transport,
+        auth_url,
         username,
         password,
+        auth_schemes,
+        salted_password_cache,
     )
     .await?;
 
     Ok(auth_token)
 }
 
+/// Return the URL used to authenticate with the SkySpark server which
+/// hosts the given project API URL.
+pub(crate) fn skyspark_auth_url(project_api_url: &Url) -> Url {
+    let mut auth_url = project_api_url.clone();
+    auth_url.set_path("/ui");
+    auth_url
+}
+
+/// A client for interacting with a Haystack 4 server.
+///
+/// The client sends each Haystack op to a URL relative to its base URL,
+/// such as `{base_url}read` for the `read` op, and authenticates by sending
+/// requests to its auth URL. A `SkySparkClient` contains a `HaystackClient`
+/// which uses the URL layout of a SkySpark server.
+///
+/// Cloning a `HaystackClient` is cheap, and all clones share the same
+/// auth token. A client can be cloned and used from many tasks at once,
+/// without needing to be wrapped in a `Mutex`.
+///
+/// Use `HaystackClientBuilder` to create a client with options such as
+/// timeouts, a proxy, or custom TLS settings.
+#[derive(Clone, Debug)]
+pub struct HaystackClient {
+    auth: Arc<ClientAuth>,
+    transport: Arc<dyn Transport>,
+    grid_format: GridFormat,
+    base_url: Url,
+    auth_url: Url,
+    retry_policy: RetryPolicy,
+    total_timeout: Option<Duration>,
+    metrics: Arc<MetricsCounters>,
+    limiter: Arc<RequestLimiter>,
+}
+
 /// A client for interacting with a SkySpark server.
-#[derive(Debug)]
+///
+/// A `SkySparkClient` provides the Haystack ops, such as `read` and
+/// `his_read`, by calling the `HaystackClient` returned by
+/// `as_haystack_client`. It also provides the operations which are
+/// specific to SkySpark, such as `eval`.
+///
+/// Cloning a `SkySparkClient` is cheap, and all clones share the same
+/// auth token. A client can be cloned and used from many tasks at once,
+/// without needing to be wrapped in a `Mutex`.
+///
+/// Use `SkySparkClientBuilder` to create a client with options such as
+/// timeouts, a proxy, or custom TLS settings.
+#[derive(Clone, Debug)]
 pub struct SkySparkClient {
-    auth_token: String,
-    client: reqwest::Client,
-    username: String,
-    password: String,
-    project_api_url: Url,
+    haystack_client: HaystackClient,
+}
+
+/// The authentication state shared by all clones of a `HaystackClient`.
+struct ClientAuth {
+    auth_token: RwLock<String>,
+    /// Held while a new auth token is being obtained, so that concurrent
+    /// requests rejected by the server only cause one re-authentication.
+    update_lock: Mutex<()>,
+    credential_provider: Arc<dyn CredentialProvider>,
+    token_store: Option<Arc<dyn TokenStore>>,
+    auth_schemes: AuthSchemes,
+    salted_password_cache: std::sync::Mutex<SaltedPasswordCache>,
+}
+
+impl std::fmt::Debug for ClientAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientAuth")
+            .field("auth_token", &"<redacted>")
+            .field("credential_provider", &self.credential_provider)
+            .field("token_store", &self.token_store)
+            .field("auth_schemes", &self.auth_schemes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientAuth {
+    fn new(
+        auth_token: String,
+        credential_provider: Arc<dyn CredentialProvider>,
+        token_store: Option<Arc<dyn TokenStore>>,
+        auth_schemes: AuthSchemes,
+    ) -> Self {
+        Self {
+            auth_token: RwLock::new(auth_token),
+            update_lock: Mutex::new(()),
+            credential_provider,
+            token_store,
+            auth_schemes,
+            salted_password_cache: std::sync::Mutex::new(
+                SaltedPasswordCache::new(SALTED_PASSWORD_CACHE_CAPACITY),
+            ),
+        }
+    }
+
+    /// Authenticate with the server using the credentials from the
+    /// credential provider, and return the new auth token.
+    async fn new_auth_token(
+        &self,
+        auth_url: &Url,
+        transport: &dyn Transport,
+    ) -> StdResult<String, crate::auth::AuthError> {
+        let credentials = self.credential_provider.credentials()?;
+        new_auth_token(
+            auth_url,
+            transport,
+            credentials.username(),
+            credentials.password(),
+            &self.auth_schemes,
+            Some(&self.salted_password_cache),
+        )
+        .await
+    }
+
+    /// Call `f` with the token store and the username, if there is a token
+    /// store and the username is available. Token stores may read and write
+    /// files, so `f` is called on a thread where blocking is acceptable.
+    async fn with_token_store<T, F>(&self, f: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn TokenStore, &str) -> T + Send + 'static,
+    {
+        let token_store = self.token_store.clone()?;
+        let credential_provider = self.credential_provider.clone();
+        tokio::task::spawn_blocking(move || {
+            let username = credential_provider.username().ok()?;
+            Some(f(token_store.as_ref(), &username))
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    /// Return the auth token saved in the token store, if there is one.
+    async fn load_stored_auth_token(
+        &self,
+        project_api_url: &Url,
+    ) -> Option<String> {
+        let project_api_url = project_api_url.clone();
+        self.with_token_store(move |token_store, username| {
+            // Errors are ignored, so a broken token store never prevents
+            // the client from authenticating:
+            token_store.load(&project_api_url, username).ok().flatten()
+        })
+        .await
+        .flatten()
+    }
+
+    /// Save the auth token to the token store, if there is one.
+    async fn store_auth_token(&self, project_api_url: &Url, auth_token: &str) {
+        let project_api_url = project_api_url.clone();
+        let auth_token = auth_token.to_owned();
+        self.with_token_store(move |token_store, username| {
+            // Errors are ignored, so a broken token store never prevents
+            // the client from authenticating:
+            let _ = token_store.save(&project_api_url, username, &auth_token);
+        })
+        .await;
+    }
+
+    /// Remove the auth token from the token store, if there is one and it
+    /// contains the given auth token.
+    async fn remove_stored_auth_token(
+        &self,
+        project_api_url: &Url,
+        auth_token: &str,
+    ) {
+        let project_api_url = project_api_url.clone();
+        let auth_token = auth_token.to_owned();
+        self.with_token_store(move |token_store, username| {
+            let stored_auth_token =
+                token_store.load(&project_api_url, username).ok().flatten();
+            if stored_auth_token.as_deref() == Some(auth_token.as_str()) {
+                // Errors are ignored, as the auth token is no longer valid:
+                let _ = token_store.remove(&project_api_url, username);
+            }
+        })
+        .await;
+    }
 }
 
 impl SkySparkClient {
@@ -105,7 +325,7 @@ impl SkySparkClient {
     /// use raystack::SkySparkClient;
     /// use url::Url;
     /// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
-    /// let mut client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
+    /// let client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
     /// # }
     /// ```
     pub async fn new(
@@ -113,8 +333,9 @@ impl SkySparkClient {
         username: &str,
         password: &str,
     ) -> std::result::Result<Self, NewSkySparkClientError> {
-        let client = reqwest::Client::new();
-        Self::new_with_client(project_api_url, username, password, client).await
+        SkySparkClientBuilder::new(project_api_url, username, password)
+            .build()
+            .await
     }
 
     /// Create a new `SkySparkClient`, passing in an existing
@@ -128,7 +349,7 @@ impl SkySparkClient {
     /// use url::Url;
     /// let reqwest_client = Client::new();
     /// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
-    /// let mut client = SkySparkClient::new_with_client(url, "username", "p4ssw0rd", reqwest_client).await.unwrap();
+    /// let client = SkySparkClient::new_with_client(url, "username", "p4ssw0rd", reqwest_client).await.unwrap();
     /// # }
     /// ```
     ///
@@ -153,130 +374,440 @@ impl SkySparkClient {
         password: &str,
         reqwest_client: reqwest::Client,
     ) -> std::result::Result<Self, NewSkySparkClientError> {
-        let project_api_url = add_backslash_if_necessary(project_api_url);
+        let transport = Arc::new(ReqwestTransport::new(reqwest_client));
+        SkySparkClientBuilder::new(project_api_url, username, password)
+            .transport(transport)
+            .build()
+            .await
+    }
 
-        if project_api_url.cannot_be_a_base() {
-            let url_err_msg = "the project API URL must be a valid base URL";
-            return Err(NewSkySparkClientError::url(url_err_msg));
-        }
+    /// Create a new `SkySparkClient` which uses an existing auth token,
+    /// such as one returned by `auth_token` in a previous process. No
+    /// requests are sent to the server until the client is used. If the
+    /// server rejects the auth token, the client obtains a new auth token
+    /// using the username and password.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn run() {
+    /// use raystack::SkySparkClient;
+    /// use reqwest::Client;
+    /// use url::Url;
+    /// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
+    /// let saved_token = std::fs::read_to_string("auth_token.txt").unwrap();
+    /// let client = SkySparkClient::new_with_auth_token(url, "username", "p4ssw0rd", &saved_token, Client::new()).unwrap();
+    /// let grid = client.about().await.unwrap();
+    ///
+    /// // The auth token may have been replaced if the saved token expired:
+    /// std::fs::write("auth_token.txt", client.auth_token().await).unwrap();
+    /// # }
+    /// ```
+    pub fn new_with_auth_token(
+        project_api_url: Url,
+        username: &str,
+        password: &str,
+        auth_token: &str,
+        reqwest_client: reqwest::Client,
+    ) -> std::result::Result<Self, NewSkySparkClientError> {
+        let transport = Arc::new(ReqwestTransport::new(reqwest_client));
+        SkySparkClientBuilder::new(project_api_url, username, password)
+            .auth_token(auth_token)
+            .transport(transport)
+            .build_unauthenticated()
+    }
+}
 
-        if !has_valid_path_segments(&project_api_url) {
-            let url_err_msg = "URL must be formatted similarly to http://www.test.com/api/project/";
-            return Err(NewSkySparkClientError::url(url_err_msg));
-        }
+impl HaystackClient {
+    /// Create a new `HaystackClient`, which sends Haystack ops to URLs
+    /// relative to `base_url`, and authenticates by sending requests to
+    /// `auth_url`.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn run() {
+    /// use raystack::HaystackClient;
+    /// use url::Url;
+    /// let base_url = Url::parse("https://haystack.company.com/haystack/").unwrap();
+    /// let auth_url = base_url.join("about").unwrap();
+    /// let client = HaystackClient::new(base_url, auth_url, "username", "p4ssw0rd").await.unwrap();
+    /// let grid = client.about().await.unwrap();
+    /// # }
+    /// ```
+    pub async fn new(
+        base_url: Url,
+        auth_url: Url,
+        username: &str,
+        password: &str,
+    ) -> std::result::Result<Self, NewSkySparkClientError> {
+        HaystackClientBuilder::new(base_url, username, password)
+            .auth_url(auth_url)
+            .build()
+            .await
+    }
 
-        Ok(SkySparkClient {
-            auth_token: new_auth_token(
-                &project_api_url,
-                &reqwest_client,
-                username,
-                password,
-            )
-            .await?,
-            client: reqwest_client,
-            username: username.to_owned(),
-            password: password.to_owned(),
-            project_api_url,
-        })
+    /// Return the URL which the Haystack op URLs are relative to.
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
     }
 
-    #[cfg(test)]
-    pub(crate) fn test_manually_set_auth_token(&mut self, auth_token: &str) {
-        self.auth_token = auth_token.to_owned();
+    /// Return the URL which authentication requests are sent to.
+    pub fn auth_url(&self) -> &Url {
+        &self.auth_url
     }
 
     #[cfg(test)]
-    pub(crate) fn test_auth_token(&self) -> &str {
-        &self.auth_token
+    pub(crate) async fn test_manually_set_auth_token(&self, auth_token: &str) {
+        *self.auth.auth_token.write().await = auth_token.to_owned();
     }
 
+    /// Return the auth token currently used by this client. The auth token
+    /// changes whenever the client obtains a new auth token from the
+    /// server, after the server rejects the previous auth token.
+    pub async fn auth_token(&self) -> String {
+        self.auth.auth_token.read().await.clone()
+    }
+
+    /// Obtain a new auth token to replace the given auth token, which was
+    /// rejected by the server, and return the new auth token. If another
+    /// request, or another process using the same token store, has already
+    /// replaced the rejected auth token, that auth token is returned
+    /// without re-authenticating.
     async fn update_auth_token(
-        &mut self,
-    ) -> StdResult<(), crate::auth::AuthError> {
-        let auth_token = new_auth_token(
-            self.project_api_url(),
-            self.client(),
-            &self.username,
-            &self.password,
-        )
-        .await?;
-        self.auth_token = auth_token;
-        Ok(())
+        &self,
+        rejected_auth_token: &str,
+    ) -> StdResult<String, crate::auth::AuthError> {
+        let _update_guard = self.auth.update_lock.lock().await;
+
+        let current_auth_token = self.auth_token().await;
+        if current_auth_token != rejected_auth_token {
+            return Ok(current_auth_token);
+        }
+
+        let stored_auth_token = self
+            .auth
+            .load_stored_auth_token(self.base_url())
+            .await
+            .filter(|auth_token| auth_token != rejected_auth_token);
+
+        let auth_token = match stored_auth_token {
+            Some(auth_token) => auth_token,
+            None => {
+                let auth_token = self
+                    .auth
+                    .new_auth_token(self.auth_url(), &self.limited_transport())
+                    .await?;
+                self.auth
+                    .store_auth_token(self.base_url(), &auth_token)
+                    .await;
+                self.metrics.record_reauthentication();
+                tracing::Span::current().record("reauthenticated", true);
+                auth_token
+            }
+        };
+
+        *self.auth.auth_token.write().await = auth_token.clone();
+        Ok(auth_token)
     }
 
-    pub fn client(&self) -> &reqwest::Client {
-        &self.client
+    /// Return the transport used to send requests to the server.
+    pub fn transport(&self) -> &Arc<dyn Transport> {
+        &self.transport
     }
 
-    fn auth_header_value(&self) -> String {
-        format!("BEARER authToken={}", self.auth_token)
+    /// Return a transport which sends requests using this client's
+    /// transport, within the concurrency and rate limits shared by this
+    /// client and all of its clones.
+    fn limited_transport(&self) -> LimitedTransport<'_> {
+        LimitedTransport::new(self.transport.as_ref(), &self.limiter)
     }
 
-    fn eval_url(&self) -> Url {
-        self.append_to_url("eval")
+    /// Return the format used to encode grids sent to and received from
+    /// the server.
+    pub fn grid_format(&self) -> GridFormat {
+        self.grid_format
+    }
+
+    /// Set the format used to encode grids sent to and received from
+    /// the server. The default format is `GridFormat::Json`. This only
+    /// affects this client, and not any of its clones.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn run() {
+    /// use raystack::{GridFormat, SkySparkClient};
+    /// use url::Url;
+    /// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
+    /// let mut client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
+    /// client.set_grid_format(GridFormat::Zinc);
+    /// let grid = client.about().await.unwrap();
+    /// # }
+    /// ```
+    pub fn set_grid_format(&mut self, grid_format: GridFormat) {
+        self.grid_format = grid_format;
     }
 
-    async fn get(&mut self, url: Url) -> Result<Grid> {
-        let res = self.get_response(url.clone()).await?;
+    /// Return the policy used to retry requests which fail due to
+    /// transient errors.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
 
-        if res.status() == reqwest::StatusCode::FORBIDDEN {
-            self.update_auth_token().await?;
-            let retry_res = self.get_response(url).await?;
-            http_response_to_grid(retry_res).await
-        } else {
-            http_response_to_grid(res).await
-        }
+    /// Return the maximum amount of time an operation may take, including
+    /// all retries of its requests and any re-authentication, if the client
+    /// has a total timeout.
+    pub fn total_timeout(&self) -> Option<Duration> {
+        self.total_timeout
     }
 
-    async fn get_response(&self, url: Url) -> Result<reqwest::Response> {
-        self.client()
-            .get(url)
-            .header("Accept", "application/json")
-            .header("Authorization", self.auth_header_value())
-            .send()
-            .await
-            .map_err(|err| err.into())
+    /// Return the maximum number of HTTP requests which the client and all
+    /// of its clones may have in progress at once, if there is a limit.
+    pub fn max_in_flight(&self) -> Option<usize> {
+        self.limiter.max_in_flight()
+    }
+
+    /// Return the maximum number of HTTP requests which the client and all
+    /// of its clones may send each second, if there is a limit.
+    pub fn max_requests_per_second(&self) -> Option<u32> {
+        self.limiter.max_requests_per_second()
+    }
+
+    /// Return the number of ops, errors and re-authentications counted by
+    /// this client and all of its clones.
+    pub fn metrics(&self) -> ClientMetrics {
+        self.metrics.snapshot()
+    }
+
+    fn auth_header_value(auth_token: &str) -> String {
+        auth::authorization_header_value(auth_token)
+    }
+
+    async fn get(&self, url: Url) -> Result<Grid> {
+        self.request(url, None, true).await
+    }
+
+    /// Send a POST request for an operation which can safely be repeated.
+    async fn post(&self, url: Url, grid: &Grid) -> Result<Grid> {
+        self.request(url, Some(grid), true).await
+    }
+
+    /// Send a POST request for an operation which changes the state of
+    /// the server, and so is only retried if the retry policy allows it.
+    async fn post_non_idempotent(&self, url: Url, grid: &Grid) -> Result<Grid> {
+        self.request(url, Some(grid), false).await
+    }
+
+    /// Send a GET request if `grid` is `None`, otherwise send a POST request
+    /// containing `grid`. Failed requests are retried according to the
+    /// client's retry policy, until the client's total timeout elapses.
+    /// Each attempt is not sent until the client's concurrency and rate
+    /// limits allow it.
+    ///
+    /// The op is performed inside a `haystack_op` tracing span, which
+    /// records the status and size of the last response, the latency of the
+    /// whole op, and whether the client had to re-authenticate.
+    #[tracing::instrument(
+        name = "haystack_op",
+        skip_all,
+        fields(
+            op = op_name(&url),
+            project = %self.base_url,
+            request_rows = grid.map(|grid| grid.size()).unwrap_or(0),
+            response_size = tracing::field::Empty,
+            status = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+            reauthenticated = false,
+        )
+    )]
+    async fn request(
+        &self,
+        url: Url,
+        grid: Option<&Grid>,
+        is_idempotent: bool,
+    ) -> Result<Grid> {
+        let start = Instant::now();
+        let request = self.request_with_retries(url, grid, is_idempotent);
+
+        let result = match self.total_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, request)
+                .await
+                .unwrap_or(Err(Error::Timeout { timeout })),
+            None => request.await,
+        };
+
+        let latency_ms = start.elapsed().as_millis() as u64;
+        tracing::Span::current().record("latency_ms", latency_ms);
+        self.metrics.record_op(result.is_err());
+        match &result {
+            Ok(_) => tracing::debug!("Haystack op succeeded"),
+            Err(err) => tracing::debug!(error = %err, "Haystack op failed"),
+        }
+        result
     }
 
-    async fn post(&mut self, url: Url, grid: &Grid) -> Result<Grid> {
-        let res = self.post_response(url.clone(), grid).await?;
+    async fn request_with_retries(
+        &self,
+        url: Url,
+        grid: Option<&Grid>,
+        is_idempotent: bool,
+    ) -> Result<Grid> {
+        let policy = &self.retry_policy;
+        let mut attempts = 1;
+
+        loop {
+            let res = self.authorized_response(url.clone(), grid).await;
+            let can_retry = policy.allows_retry(attempts, is_idempotent);
+
+            let result = match res {
+                Ok(res) if policy.is_retryable_status(res.status()) => {
+                    if can_retry {
+                        None
+                    } else {
+                        Some(Err(error_status_response_to_err(res)))
+                    }
+                }
+                Ok(res) => Some(http_response_to_grid(res)),
+                Err(Error::Http { err })
+                    if can_retry && policy.is_retryable_error(&err) =>
+                {
+                    None
+                }
+                Err(err) => Some(Err(err)),
+            };
+
+            if let Some(result) = result {
+                return result
+                    .map_err(|err| Error::with_attempts(err, attempts));
+            }
+
+            tokio::time::sleep(policy.backoff(attempts)).await;
+            attempts += 1;
+        }
+    }
 
-        if res.status() == reqwest::StatusCode::FORBIDDEN {
-            self.update_auth_token().await?;
-            let retry_res = self.post_response(url, grid).await?;
-            http_response_to_grid(retry_res).await
+    /// Send a request using the current auth token. If the server rejects
+    /// the auth token, a new auth token is obtained and the request is
+    /// sent again.
+    async fn authorized_response(
+        &self,
+        url: Url,
+        grid: Option<&Grid>,
+    ) -> Result<HttpResponse> {
+        let auth_token = self.auth_token().await;
+        let res = self.response(url.clone(), grid, &auth_token).await?;
+
+        if res.status() == StatusCode::FORBIDDEN {
+            let auth_token = self.update_auth_token(&auth_token).await?;
+            self.response(url, grid, &auth_token).await
         } else {
-            http_response_to_grid(res).await
+            Ok(res)
         }
     }
 
+    async fn response(
+        &self,
+        url: Url,
+        grid: Option<&Grid>,
+        auth_token: &str,
+    ) -> Result<HttpResponse> {
+        let res = match grid {
+            Some(grid) => self.post_response(url, grid, auth_token).await?,
+            None => self.get_response(url, auth_token).await?,
+        };
+
+        let span = tracing::Span::current();
+        span.record("status", res.status().as_u16());
+        span.record("response_size", res.body().len());
+        Ok(res)
+    }
+
+    async fn get_response(
+        &self,
+        url: Url,
+        auth_token: &str,
+    ) -> Result<HttpResponse> {
+        let request = HttpRequest::new(Method::GET, url)
+            .with_header("Accept", self.grid_format.mime_type())
+            .try_with_header(
+                "Authorization",
+                &Self::auth_header_value(auth_token),
+            )?;
+        Ok(self.limited_transport().send(request).await?)
+    }
+
     async fn post_response(
         &self,
         url: Url,
         grid: &Grid,
-    ) -> Result<reqwest::Response> {
-        self.client()
-            .post(url)
-            .header("Accept", "application/json")
-            .header("Authorization", self.auth_header_value())
-            .header("Content-Type", "application/json")
-            .body(grid.to_json_string())
-            .send()
-            .await
-            .map_err(|err| err.into())
+        auth_token: &str,
+    ) -> Result<HttpResponse> {
+        let body = match self.grid_format {
+            GridFormat::Json => grid.to_json_string(),
+            GridFormat::Zinc => grid.to_zinc_string(),
+        };
+
+        let request = HttpRequest::new(Method::POST, url)
+            .with_header("Accept", self.grid_format.mime_type())
+            .with_header("Content-Type", self.grid_format.mime_type())
+            .try_with_header(
+                "Authorization",
+                &Self::auth_header_value(auth_token),
+            )?
+            .with_body(body);
+        Ok(self.limited_transport().send(request).await?)
     }
 
     fn append_to_url(&self, s: &str) -> Url {
-        self.project_api_url
+        self.base_url
             .join(s)
             .expect("since url ends with '/' this should never fail")
     }
 
+    /// Send a request to the given URL to end the session on the server,
+    /// and remove the auth token from the client and its token store.
+    async fn end_session(&self, logout_url: Url) -> Result<()> {
+        let _update_guard = self.auth.update_lock.lock().await;
+
+        let auth_token = self.auth_token().await;
+        if auth_token.is_empty() {
+            // The client has already logged out.
+            return Ok(());
+        }
+
+        let request = HttpRequest::new(Method::GET, logout_url)
+            .try_with_header(
+                "Authorization",
+                &Self::auth_header_value(&auth_token),
+            )?;
+        let transport = self.limited_transport();
+        let send = transport.send(request);
+        let res = match self.total_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, send)
+                .await
+                .map_err(|_| Error::Timeout { timeout })??,
+            None => send.await?,
+        };
+
+        // The server rejects auth tokens for sessions which have already
+        // ended, so there is nothing left to log out of:
+        if res.status() != StatusCode::FORBIDDEN {
+            res.error_for_status()?;
+        }
+
+        self.auth
+            .remove_stored_auth_token(self.base_url(), &auth_token)
+            .await;
+        *self.auth.auth_token.write().await = String::new();
+        Ok(())
+    }
+}
+
+impl SkySparkClient {
     /// Return the project name for this client.
     pub fn project_name(&self) -> &str {
         // Since the URL is validated by the `SkySparkClient::new` function,
         // the following code shouldn't panic:
-        self.project_api_url
+        self.haystack_client
+            .base_url()
             .path_segments()
             .expect("proj api url is a valid base URL so this shouldn't fail")
             .nth(1)
@@ -285,10 +816,406 @@ impl SkySparkClient {
 
     /// Return the project API url being used by this client.
     pub fn project_api_url(&self) -> &Url {
-        &self.project_api_url
+        self.haystack_client.base_url()
+    }
+
+    /// End the session on the server, so the current auth token can no
+    /// longer be used. The auth token is also removed from the client,
+    /// and from the client's token store if it has one.
+    ///
+    /// Since all clones share the same auth token, this ends the session
+    /// for every clone of this client. If the client is used after logging
+    /// out, it authenticates with the server again.
+    pub async fn logout(&self) -> Result<()> {
+        self.haystack_client.end_session(self.logout_url()).await
+    }
+
+    /// Log out and consume this client. This should be called when a
+    /// program has finished using a client, so the session does not
+    /// remain open on the server until it expires.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn run() {
+    /// use raystack::SkySparkClient;
+    /// use url::Url;
+    /// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
+    /// let client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
+    /// let grid = client.about().await.unwrap();
+    /// client.shutdown().await.unwrap();
+    /// # }
+    /// ```
+    pub async fn shutdown(self) -> Result<()> {
+        self.logout().await
+    }
+
+    fn logout_url(&self) -> Url {
+        let mut logout_url = self.haystack_client.base_url().clone();
+        logout_url.set_path("/user/logout");
+        logout_url
+    }
+
+    fn eval_url(&self) -> Url {
+        self.haystack_client.append_to_url("eval")
+    }
+
+    /// Return the `HaystackClient` used by this client, which provides the
+    /// Haystack ops.
+    pub fn as_haystack_client(&self) -> &HaystackClient {
+        &self.haystack_client
+    }
+
+    /// Convert this client into the `HaystackClient` it uses.
+    pub fn into_haystack_client(self) -> HaystackClient {
+        self.haystack_client
+    }
+}
+
+impl SkySparkClient {
+    #[cfg(test)]
+    pub(crate) async fn test_manually_set_auth_token(&self, auth_token: &str) {
+        self.haystack_client
+            .test_manually_set_auth_token(auth_token)
+            .await;
+    }
+
+    /// Return the auth token currently used by this client.
+    pub async fn auth_token(&self) -> String {
+        self.haystack_client.auth_token().await
+    }
+
+    /// Return the format used to encode grids sent to and received from
+    /// the server.
+    pub fn grid_format(&self) -> GridFormat {
+        self.haystack_client.grid_format()
+    }
+
+    /// Set the format used to encode grids sent to and received from
+    /// the server. This only affects this client, and not any of its clones.
+    pub fn set_grid_format(&mut self, grid_format: GridFormat) {
+        self.haystack_client.set_grid_format(grid_format);
+    }
+
+    /// Return the number of ops, errors and re-authentications counted by
+    /// this client and all of its clones.
+    pub fn metrics(&self) -> ClientMetrics {
+        self.haystack_client.metrics()
+    }
+
+    /// Returns a grid containing basic server information.
+    pub async fn about(&self) -> Result<Grid> {
+        self.haystack_client.about().await
+    }
+
+    /// Returns a grid describing what MIME types are available.
+    pub async fn filetypes(&self) -> Result<Grid> {
+        self.haystack_client.filetypes().await
+    }
+
+    /// Returns a grid of history data for a single point.
+    pub async fn his_read(
+        &self,
+        id: &Ref,
+        range: &HisReadRange,
+    ) -> Result<Grid> {
+        self.haystack_client.his_read(id, range).await
+    }
+
+    /// Returns history data for many points, using a single batch hisRead
+    /// request.
+    pub async fn his_read_many(
+        &self,
+        ids: &[Ref],
+        range: &HisReadRange,
+    ) -> Result<HashMap<String, Grid>> {
+        self.haystack_client.his_read_many(ids, range).await
+    }
+
+    /// Writes values of any Haystack type to a single point.
+    pub async fn his_write<V: Hayson>(
+        &self,
+        id: &Ref,
+        his_data: &[(DateTime, V)],
+    ) -> Result<Grid> {
+        self.haystack_client.his_write(id, his_data).await
+    }
+
+    /// Writes boolean values to a single point.
+    pub async fn his_write_bool(
+        &self,
+        id: &Ref,
+        his_data: &[(DateTime, bool)],
+    ) -> Result<Grid> {
+        self.haystack_client.his_write_bool(id, his_data).await
+    }
+
+    /// Writes numeric values to a single point.
+    pub async fn his_write_num(
+        &self,
+        id: &Ref,
+        his_data: &[(DateTime, Number)],
+    ) -> Result<Grid> {
+        self.haystack_client.his_write_num(id, his_data).await
+    }
+
+    /// Writes string values to a single point.
+    pub async fn his_write_str(
+        &self,
+        id: &Ref,
+        his_data: &[(DateTime, String)],
+    ) -> Result<Grid> {
+        self.haystack_client.his_write_str(id, his_data).await
+    }
+
+    /// Writes values to many points, using multi-point hisWrite requests.
+    pub async fn his_write_many(
+        &self,
+        his_data: &[(Ref, Vec<(DateTime, Value)>)],
+    ) -> std::result::Result<Vec<Grid>, HisWriteManyError> {
+        self.haystack_client.his_write_many(his_data).await
+    }
+
+    /// Writes values of any Haystack type with UTC timestamps to a
+    /// single point.
+    pub async fn utc_his_write<V: Hayson>(
+        &self,
+        id: &Ref,
+        time_zone_name: &str,
+        his_data: &[(chrono::DateTime<Utc>, V)],
+    ) -> Result<Grid> {
+        self.haystack_client
+            .utc_his_write(id, time_zone_name, his_data)
+            .await
+    }
+
+    /// Writes boolean values with UTC timestamps to a single point.
+    pub async fn utc_his_write_bool(
+        &self,
+        id: &Ref,
+        time_zone_name: &str,
+        his_data: &[(chrono::DateTime<Utc>, bool)],
+    ) -> Result<Grid> {
+        self.haystack_client
+            .utc_his_write_bool(id, time_zone_name, his_data)
+            .await
+    }
+
+    /// Writes numeric values with UTC timestamps to a single point.
+    pub async fn utc_his_write_num(
+        &self,
+        id: &Ref,
+        time_zone_name: &str,
+        his_data: &[(chrono::DateTime<Utc>, Number)],
+    ) -> Result<Grid> {
+        self.haystack_client
+            .utc_his_write_num(id, time_zone_name, his_data)
+            .await
+    }
+
+    /// Writes string values with UTC timestamps to a single point.
+    pub async fn utc_his_write_str(
+        &self,
+        id: &Ref,
+        time_zone_name: &str,
+        his_data: &[(chrono::DateTime<Utc>, String)],
+    ) -> Result<Grid> {
+        self.haystack_client
+            .utc_his_write_str(id, time_zone_name, his_data)
+            .await
+    }
+
+    /// The Haystack invokeAction operation, which invokes the action named
+    /// `action` on the record with the given id.
+    pub async fn invoke_action(
+        &self,
+        id: &Ref,
+        action: &str,
+        args: &Map<String, Value>,
+    ) -> Result<Grid> {
+        self.haystack_client.invoke_action(id, action, args).await
+    }
+
+    /// The Haystack nav operation.
+    pub async fn nav(&self, nav_id: Option<&Ref>) -> Result<Grid> {
+        self.haystack_client.nav(nav_id).await
+    }
+
+    /// Returns a grid containing the operations available on the server.
+    pub async fn ops(&self) -> Result<Grid> {
+        self.haystack_client.ops().await
+    }
+
+    /// Writes a boolean value to a writable point at the given priority
+    /// level.
+    pub async fn point_write_bool(
+        &self,
+        id: &Ref,
+        level: WriteLevel,
+        val: bool,
+        who: Option<&str>,
+        duration: Option<&Number>,
+    ) -> Result<Grid> {
+        self.haystack_client
+            .point_write_bool(id, level, val, who, duration)
+            .await
+    }
+
+    /// Writes a numeric value to a writable point at the given priority
+    /// level.
+    pub async fn point_write_num(
+        &self,
+        id: &Ref,
+        level: WriteLevel,
+        val: &Number,
+        who: Option<&str>,
+        duration: Option<&Number>,
+    ) -> Result<Grid> {
+        self.haystack_client
+            .point_write_num(id, level, val, who, duration)
+            .await
+    }
+
+    /// Writes a string value to a writable point at the given priority
+    /// level.
+    pub async fn point_write_str(
+        &self,
+        id: &Ref,
+        level: WriteLevel,
+        val: &str,
+        who: Option<&str>,
+        duration: Option<&Number>,
+    ) -> Result<Grid> {
+        self.haystack_client
+            .point_write_str(id, level, val, who, duration)
+            .await
+    }
+
+    /// Releases the given priority level of a writable point, so that
+    /// the level no longer has a value.
+    pub async fn point_release(
+        &self,
+        id: &Ref,
+        level: WriteLevel,
+        who: Option<&str>,
+    ) -> Result<Grid> {
+        self.haystack_client.point_release(id, level, who).await
+    }
+
+    /// Returns the priority array of a writable point.
+    pub async fn point_write_array(&self, id: &Ref) -> Result<PointWriteArray> {
+        self.haystack_client.point_write_array(id).await
+    }
+
+    /// Returns a grid containing the records matching the given Axon
+    /// filter string.
+    pub async fn read(&self, filter: &str, limit: Option<u64>) -> Result<Grid> {
+        self.haystack_client.read(filter, limit).await
+    }
+
+    /// Returns a grid containing the records matching the given id
+    /// `Ref`s.
+    pub async fn read_by_ids(&self, ids: &[Ref]) -> Result<Grid> {
+        self.haystack_client.read_by_ids(ids).await
+    }
+
+    /// The Haystack watchSub operation, which opens a new watch on the
+    /// records with the given ids.
+    pub async fn watch_sub(
+        &self,
+        watch_dis: &str,
+        ids: &[Ref],
+        lease: Option<&Number>,
+    ) -> Result<Grid> {
+        self.haystack_client.watch_sub(watch_dis, ids, lease).await
+    }
+
+    /// The Haystack watchSub operation, which adds the records with the
+    /// given ids to an existing watch.
+    pub async fn watch_sub_existing(
+        &self,
+        watch_id: &str,
+        ids: &[Ref],
+        lease: Option<&Number>,
+    ) -> Result<Grid> {
+        self.haystack_client
+            .watch_sub_existing(watch_id, ids, lease)
+            .await
+    }
+
+    /// The Haystack watchUnsub operation, which removes the records with
+    /// the given ids from an existing watch.
+    pub async fn watch_unsub(
+        &self,
+        watch_id: &str,
+        ids: &[Ref],
+    ) -> Result<Grid> {
+        self.haystack_client.watch_unsub(watch_id, ids).await
+    }
+
+    /// The Haystack watchUnsub operation, which closes an existing watch.
+    pub async fn watch_close(&self, watch_id: &str) -> Result<Grid> {
+        self.haystack_client.watch_close(watch_id).await
+    }
+
+    /// The Haystack watchPoll operation.
+    pub async fn watch_poll(
+        &self,
+        watch_id: &str,
+        refresh: bool,
+    ) -> Result<Grid> {
+        self.haystack_client.watch_poll(watch_id, refresh).await
+    }
+}
+
+impl From<SkySparkClient> for HaystackClient {
+    fn from(client: SkySparkClient) -> Self {
+        client.into_haystack_client()
     }
 }
 
+/// Return the given base URL with a trailing backslash, or an error if
+/// Haystack op URLs cannot be created relative to the URL.
+pub(crate) fn validate_base_url(
+    base_url: Url,
+) -> StdResult<Url, NewSkySparkClientError> {
+    let base_url = add_backslash_if_necessary(base_url);
+
+    if base_url.cannot_be_a_base() {
+        let url_err_msg = "the base URL must be a valid base URL";
+        return Err(NewSkySparkClientError::url(url_err_msg));
+    }
+
+    Ok(base_url)
+}
+
+/// Return the name of the op sent to the given URL, which is the last
+/// segment of its path.
+fn op_name(url: &Url) -> &str {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or("")
+}
+
+/// Return the given project API URL with a trailing backslash, or an error
+/// if the URL is not formatted like a SkySpark project API URL.
+pub(crate) fn validate_project_api_url(
+    project_api_url: Url,
+) -> StdResult<Url, NewSkySparkClientError> {
+    let project_api_url = add_backslash_if_necessary(project_api_url);
+
+    if project_api_url.cannot_be_a_base() {
+        let url_err_msg = "the project API URL must be a valid base URL";
+        return Err(NewSkySparkClientError::url(url_err_msg));
+    }
+
+    if !has_valid_path_segments(&project_api_url) {
+        let url_err_msg = "URL must be formatted similarly to http://www.test.com/api/project/";
+        return Err(NewSkySparkClientError::url(url_err_msg));
+    }
+
+    Ok(project_api_url)
+}
+
 /// If the given url ends with a backslash, return the url without
 /// any modifications. If the given url does not end with a backslash,
 /// append a backslash to the end and return a new `Url`.
@@ -302,20 +1229,20 @@ pub(crate) fn add_backslash_if_necessary(url: Url) -> Url {
     }
 }
 
-impl SkySparkClient {
+impl HaystackClient {
     /// Returns a grid containing basic server information.
-    pub async fn about(&mut self) -> Result<Grid> {
+    pub async fn about(&self) -> Result<Grid> {
         self.get(self.about_url()).await
     }
 
     /// Returns a grid describing what MIME types are available.
-    pub async fn filetypes(&mut self) -> Result<Grid> {
+    pub async fn filetypes(&self) -> Result<Grid> {
         self.get(self.filetypes_url()).await
     }
 
     /// Returns a grid of history data for a single point.
     pub async fn his_read(
-        &mut self,
+        &self,
         id: &Ref,
         range: &HisReadRange,
     ) -> Result<Grid> {
@@ -328,102 +1255,153 @@ impl SkySparkClient {
         self.post(self.his_read_url(), &req_grid).await
     }
 
+    /// Returns history data for many points, using a single batch hisRead
+    /// request. The result maps the string form of each point's id, such as
+    /// `@abc`, to a grid with the same format as a grid returned by
+    /// `his_read`.
+    pub async fn his_read_many(
+        &self,
+        ids: &[Ref],
+        range: &HisReadRange,
+    ) -> Result<HashMap<String, Grid>> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let rows = ids.iter().map(|id| json!({"id": id.to_hayson()})).collect();
+        let mut req_grid = Grid::new_internal(rows);
+        req_grid.add_to_meta("range", json!(range.to_json_request_string()));
+
+        let grid = self.post(self.his_read_url(), &req_grid).await?;
+        his::split_his_read_many_grid(&grid)
+    }
+
+    /// Writes values of any Haystack type to a single point.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn run() {
+    /// use raystack::{Coord, Ref, SkySparkClient};
+    /// use chrono::DateTime;
+    /// use chrono_tz::Australia::Sydney;
+    /// use url::Url;
+    ///
+    /// let url = Url::parse("https://www.example.com/api/projName/").unwrap();
+    /// let client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
+    /// let id = Ref::new("@p:projName:r:2a3d29f9-c79fdd5e".to_owned()).unwrap();
+    ///
+    /// let date_time = DateTime::parse_from_rfc3339("2021-01-01T00:00:00+10:00")
+    ///     .unwrap()
+    ///     .with_timezone(&Sydney);
+    /// let his_data = vec![(date_time.into(), Coord::new(-33.87, 151.21))];
+    /// client.his_write(&id, &his_data).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn his_write<V: Hayson>(
+        &self,
+        id: &Ref,
+        his_data: &[(DateTime, V)],
+    ) -> Result<Grid> {
+        let req_grid = his::his_write_grid(id, his_data, V::to_hayson);
+        self.post_non_idempotent(self.his_write_url(), &req_grid)
+            .await
+    }
+
     /// Writes boolean values to a single point.
     pub async fn his_write_bool(
-        &mut self,
+        &self,
         id: &Ref,
         his_data: &[(DateTime, bool)],
     ) -> Result<Grid> {
-        let rows = his_data
-            .iter()
-            .map(|(date_time, value)| {
-                json!({
-                    "ts": date_time.to_hayson(),
-                    "val": value
-                })
-            })
-            .collect();
-
-        let mut req_grid = Grid::new_internal(rows);
-        req_grid.add_ref_to_meta(id);
-
-        self.post(self.his_write_url(), &req_grid).await
+        let req_grid = his::his_write_grid(id, his_data, |value| json!(value));
+        self.post_non_idempotent(self.his_write_url(), &req_grid)
+            .await
     }
 
     /// Writes numeric values to a single point. `unit` must be a valid
     /// Haystack unit literal, such as `L/s` or `celsius`.
     pub async fn his_write_num(
-        &mut self,
+        &self,
         id: &Ref,
         his_data: &[(DateTime, Number)],
     ) -> Result<Grid> {
-        let rows = his_data
-            .iter()
-            .map(|(date_time, value)| {
-                json!({
-                    "ts": date_time.to_hayson(),
-                    "val": value.to_hayson(),
-                })
-            })
-            .collect();
-
-        let mut req_grid = Grid::new_internal(rows);
-        req_grid.add_ref_to_meta(id);
-
-        self.post(self.his_write_url(), &req_grid).await
+        self.his_write(id, his_data).await
     }
 
     /// Writes string values to a single point.
     pub async fn his_write_str(
-        &mut self,
+        &self,
         id: &Ref,
         his_data: &[(DateTime, String)],
     ) -> Result<Grid> {
-        let rows = his_data
-            .iter()
-            .map(|(date_time, value)| {
-                json!({
-                    "ts": date_time.to_hayson(),
-                    "val": value
-                })
-            })
-            .collect();
+        let req_grid = his::his_write_grid(id, his_data, |value| json!(value));
+        self.post_non_idempotent(self.his_write_url(), &req_grid)
+            .await
+    }
 
-        let mut req_grid = Grid::new_internal(rows);
-        req_grid.add_ref_to_meta(id);
+    /// Writes values to many points, using multi-point hisWrite requests.
+    /// Each value must be Hayson-encoded, for example by using the
+    /// `to_hayson` function from the `Hayson` trait. Large amounts of
+    /// history data are automatically split across multiple requests, and
+    /// the grid returned by each request is returned.
+    ///
+    /// The requests are sent one at a time, and no more are sent after a
+    /// request fails. The values sent by earlier requests have already been
+    /// written, so the returned error contains the grids returned by those
+    /// requests.
+    pub async fn his_write_many(
+        &self,
+        his_data: &[(Ref, Vec<(DateTime, Value)>)],
+    ) -> std::result::Result<Vec<Grid>, HisWriteManyError> {
+        let req_grids =
+            his::his_write_many_grids(his_data, his::HIS_WRITE_MANY_MAX_VALUES);
+        let mut res_grids = Vec::with_capacity(req_grids.len());
+
+        for req_grid in req_grids {
+            match self
+                .post_non_idempotent(self.his_write_url(), &req_grid)
+                .await
+            {
+                Ok(res_grid) => res_grids.push(res_grid),
+                Err(err) => return Err(HisWriteManyError::new(res_grids, err)),
+            }
+        }
 
-        self.post(self.his_write_url(), &req_grid).await
+        Ok(res_grids)
+    }
+
+    /// Writes values of any Haystack type with UTC timestamps to a
+    /// single point. `time_zone_name` must be a valid SkySpark timezone name.
+    pub async fn utc_his_write<V: Hayson>(
+        &self,
+        id: &Ref,
+        time_zone_name: &str,
+        his_data: &[(chrono::DateTime<Utc>, V)],
+    ) -> Result<Grid> {
+        let req_grid = his::utc_his_write_grid(
+            id,
+            time_zone_name,
+            his_data,
+            V::to_hayson,
+        )?;
+        self.post_non_idempotent(self.his_write_url(), &req_grid)
+            .await
     }
 
     /// Writes boolean values with UTC timestamps to a single point.
     /// `time_zone_name` must be a valid SkySpark timezone name.
     pub async fn utc_his_write_bool(
-        &mut self,
+        &self,
         id: &Ref,
         time_zone_name: &str,
         his_data: &[(chrono::DateTime<Utc>, bool)],
     ) -> Result<Grid> {
-        let tz = skyspark_tz_string_to_tz(time_zone_name).ok_or_else(|| {
-            Error::TimeZone {
-                err_time_zone: time_zone_name.to_owned(),
-            }
-        })?;
-
-        let rows = his_data
-            .iter()
-            .map(|(date_time, value)| {
-                let date_time: DateTime = date_time.with_timezone(&tz).into();
-                json!({
-                    "ts": date_time.to_hayson(),
-                    "val": value
-                })
-            })
-            .collect();
-
-        let mut req_grid = Grid::new_internal(rows);
-        req_grid.add_ref_to_meta(id);
-
-        self.post(self.his_write_url(), &req_grid).await
+        let req_grid =
+            his::utc_his_write_grid(id, time_zone_name, his_data, |value| {
+                json!(value)
+            })?;
+        self.post_non_idempotent(self.his_write_url(), &req_grid)
+            .await
     }
 
     /// Writes numeric values with UTC timestamps to a single point.
@@ -431,92 +1409,191 @@ impl SkySparkClient {
     /// `celsius`.
     /// `time_zone_name` must be a valid SkySpark timezone name.
     pub async fn utc_his_write_num(
-        &mut self,
+        &self,
         id: &Ref,
         time_zone_name: &str,
         his_data: &[(chrono::DateTime<Utc>, Number)],
     ) -> Result<Grid> {
-        let tz = skyspark_tz_string_to_tz(time_zone_name).ok_or_else(|| {
-            Error::TimeZone {
-                err_time_zone: time_zone_name.to_owned(),
-            }
-        })?;
-
-        let rows = his_data
-            .iter()
-            .map(|(date_time, value)| {
-                let date_time: DateTime = date_time.with_timezone(&tz).into();
-
-                json!({
-                    "ts": date_time.to_hayson(),
-                    "val": value.to_hayson(),
-                })
-            })
-            .collect();
-
-        let mut req_grid = Grid::new_internal(rows);
-        req_grid.add_ref_to_meta(id);
-
-        self.post(self.his_write_url(), &req_grid).await
+        self.utc_his_write(id, time_zone_name, his_data).await
     }
 
     /// Writes string values with UTC timestamps to a single point.
     /// `time_zone_name` must be a valid SkySpark timezone name.
     pub async fn utc_his_write_str(
-        &mut self,
+        &self,
         id: &Ref,
         time_zone_name: &str,
         his_data: &[(chrono::DateTime<Utc>, String)],
     ) -> Result<Grid> {
-        let tz = skyspark_tz_string_to_tz(time_zone_name).ok_or_else(|| {
-            Error::TimeZone {
-                err_time_zone: time_zone_name.to_owned(),
+        let req_grid =
+            his::utc_his_write_grid(id, time_zone_name, his_data, |value| {
+                json!(value)
+            })?;
+        self.post_non_idempotent(self.his_write_url(), &req_grid)
+            .await
+    }
+
+    /// The Haystack invokeAction operation, which invokes the action named
+    /// `action` on the record with the given id. Each entry in `args` is
+    /// passed to the action as an argument, and each value must be
+    /// Hayson-encoded. Returns the grid produced by the action.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn run() {
+    /// use raystack::{Ref, SkySparkClient};
+    /// use serde_json::{json, Map};
+    /// use url::Url;
+    ///
+    /// let url = Url::parse("https://www.example.com/api/projName/").unwrap();
+    /// let client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
+    /// let id = Ref::new("@p:projName:r:2a3d29f9-c79fdd5e".to_owned()).unwrap();
+    ///
+    /// let mut args = Map::new();
+    /// args.insert("reason".to_owned(), json!("Operator said \"restart\""));
+    /// let grid = client.invoke_action(&id, "restart", &args).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn invoke_action(
+        &self,
+        id: &Ref,
+        action: &str,
+        args: &Map<String, Value>,
+    ) -> Result<Grid> {
+        // A grid with no columns cannot contain a row in the Zinc format, so
+        // an action without arguments is sent with no rows:
+        let rows = if args.is_empty() {
+            Vec::new()
+        } else {
+            vec![Value::Object(args.clone())]
+        };
+        let mut req_grid = Grid::new(rows)?;
+        req_grid.add_ref_to_meta(id);
+        req_grid.add_to_meta("action", json!(action));
+
+        self.post_non_idempotent(self.invoke_action_url(), &req_grid)
+            .await
+    }
+
+    /// The Haystack nav operation.
+    pub async fn nav(&self, nav_id: Option<&Ref>) -> Result<Grid> {
+        let req_grid = match nav_id {
+            Some(nav_id) => {
+                let row = json!({ "navId": nav_id.to_hayson() });
+                Grid::new_internal(vec![row])
             }
-        })?;
+            None => Grid::new_internal(Vec::new()),
+        };
 
-        let rows = his_data
-            .iter()
-            .map(|(date_time, value)| {
-                let date_time: DateTime = date_time.with_timezone(&tz).into();
+        self.post(self.nav_url(), &req_grid).await
+    }
+
+    /// Returns a grid containing the operations available on the server.
+    pub async fn ops(&self) -> Result<Grid> {
+        self.get(self.ops_url()).await
+    }
+
+    /// Writes a boolean value to a writable point at the given priority
+    /// level. `duration` is only used by the server when writing to the
+    /// manual override level (level 8), and must be a Number with a
+    /// duration unit, such as `min` or `h`.
+    pub async fn point_write_bool(
+        &self,
+        id: &Ref,
+        level: WriteLevel,
+        val: bool,
+        who: Option<&str>,
+        duration: Option<&Number>,
+    ) -> Result<Grid> {
+        self.point_write(id, level, Some(json!(val)), who, duration)
+            .await
+    }
+
+    /// Writes a numeric value to a writable point at the given priority
+    /// level. `duration` is only used by the server when writing to the
+    /// manual override level (level 8), and must be a Number with a
+    /// duration unit, such as `min` or `h`.
+    pub async fn point_write_num(
+        &self,
+        id: &Ref,
+        level: WriteLevel,
+        val: &Number,
+        who: Option<&str>,
+        duration: Option<&Number>,
+    ) -> Result<Grid> {
+        self.point_write(id, level, Some(val.to_hayson()), who, duration)
+            .await
+    }
+
+    /// Writes a string value to a writable point at the given priority
+    /// level. `duration` is only used by the server when writing to the
+    /// manual override level (level 8), and must be a Number with a
+    /// duration unit, such as `min` or `h`.
+    pub async fn point_write_str(
+        &self,
+        id: &Ref,
+        level: WriteLevel,
+        val: &str,
+        who: Option<&str>,
+        duration: Option<&Number>,
+    ) -> Result<Grid> {
+        self.point_write(id, level, Some(json!(val)), who, duration)
+            .await
+    }
 
-                json!({
-                    "ts": date_time.to_hayson(),
-                    "val": value,
-                })
-            })
-            .collect();
+    /// Releases the given priority level of a writable point, so that
+    /// the level no longer has a value.
+    pub async fn point_release(
+        &self,
+        id: &Ref,
+        level: WriteLevel,
+        who: Option<&str>,
+    ) -> Result<Grid> {
+        self.point_write(id, level, None, who, None).await
+    }
 
-        let mut req_grid = Grid::new_internal(rows);
-        req_grid.add_ref_to_meta(id);
+    /// Returns the priority array of a writable point.
+    pub async fn point_write_array(&self, id: &Ref) -> Result<PointWriteArray> {
+        let row = json!({ "id": id.to_hayson() });
+        let req_grid = Grid::new_internal(vec![row]);
 
-        self.post(self.his_write_url(), &req_grid).await
+        let grid = self.post(self.point_write_url(), &req_grid).await?;
+        PointWriteArray::from_grid(&grid)
     }
 
-    /// The Haystack nav operation.
-    pub async fn nav(&mut self, nav_id: Option<&Ref>) -> Result<Grid> {
-        let req_grid = match nav_id {
-            Some(nav_id) => {
-                let row = json!({ "navId": nav_id.to_hayson() });
-                Grid::new_internal(vec![row])
-            }
-            None => Grid::new_internal(Vec::new()),
-        };
+    async fn point_write(
+        &self,
+        id: &Ref,
+        level: WriteLevel,
+        val: Option<Value>,
+        who: Option<&str>,
+        duration: Option<&Number>,
+    ) -> Result<Grid> {
+        let level = Number::new_unitless(f64::from(level.value()));
+        let mut row = json!({
+            "id": id.to_hayson(),
+            "level": level.to_hayson(),
+        });
+        let row_map = row.as_object_mut().expect("row is a JSON object");
 
-        self.post(self.nav_url(), &req_grid).await
-    }
+        if let Some(val) = val {
+            row_map.insert("val".to_owned(), val);
+        }
+        if let Some(who) = who {
+            row_map.insert("who".to_owned(), json!(who));
+        }
+        if let Some(duration) = duration {
+            row_map.insert("duration".to_owned(), duration.to_hayson());
+        }
 
-    /// Returns a grid containing the operations available on the server.
-    pub async fn ops(&mut self) -> Result<Grid> {
-        self.get(self.ops_url()).await
+        let req_grid = Grid::new_internal(vec![row]);
+        self.post_non_idempotent(self.point_write_url(), &req_grid)
+            .await
     }
 
     /// Returns a grid containing the records matching the given Axon
     /// filter string.
-    pub async fn read(
-        &mut self,
-        filter: &str,
-        limit: Option<u64>,
-    ) -> Result<Grid> {
+    pub async fn read(&self, filter: &str, limit: Option<u64>) -> Result<Grid> {
         let row = match limit {
             Some(integer) => json!({"filter": filter, "limit": integer}),
             None => json!({ "filter": filter }),
@@ -528,15 +1605,106 @@ impl SkySparkClient {
 
     /// Returns a grid containing the records matching the given id
     /// `Ref`s.
-    pub async fn read_by_ids(&mut self, ids: &[Ref]) -> Result<Grid> {
+    pub async fn read_by_ids(&self, ids: &[Ref]) -> Result<Grid> {
         let rows = ids.iter().map(|id| json!({"id": id.to_hayson()})).collect();
 
         let req_grid = Grid::new_internal(rows);
         self.post(self.read_url(), &req_grid).await
     }
+
+    /// The Haystack watchSub operation, which opens a new watch on the
+    /// records with the given ids. `lease` must be a Number with a
+    /// duration unit, such as `min` or `s`. The returned grid contains
+    /// the current state of the watched records, and its metadata contains
+    /// the `watchId` and `lease` of the new watch.
+    ///
+    /// See the `Watch` struct for a higher-level interface to watches.
+    pub async fn watch_sub(
+        &self,
+        watch_dis: &str,
+        ids: &[Ref],
+        lease: Option<&Number>,
+    ) -> Result<Grid> {
+        let mut req_grid = watch_req_grid(ids);
+        req_grid.add_to_meta("watchDis", json!(watch_dis));
+        if let Some(lease) = lease {
+            req_grid.add_to_meta("lease", lease.to_hayson());
+        }
+
+        self.post_non_idempotent(self.watch_sub_url(), &req_grid)
+            .await
+    }
+
+    /// The Haystack watchSub operation, which adds the records with the
+    /// given ids to an existing watch. If `lease` is given, the lease of
+    /// the watch is also updated.
+    pub async fn watch_sub_existing(
+        &self,
+        watch_id: &str,
+        ids: &[Ref],
+        lease: Option<&Number>,
+    ) -> Result<Grid> {
+        let mut req_grid = watch_req_grid(ids);
+        req_grid.add_to_meta("watchId", json!(watch_id));
+        if let Some(lease) = lease {
+            req_grid.add_to_meta("lease", lease.to_hayson());
+        }
+
+        self.post(self.watch_sub_url(), &req_grid).await
+    }
+
+    /// The Haystack watchUnsub operation, which removes the records with
+    /// the given ids from an existing watch.
+    pub async fn watch_unsub(
+        &self,
+        watch_id: &str,
+        ids: &[Ref],
+    ) -> Result<Grid> {
+        let mut req_grid = watch_req_grid(ids);
+        req_grid.add_to_meta("watchId", json!(watch_id));
+
+        self.post(self.watch_unsub_url(), &req_grid).await
+    }
+
+    /// The Haystack watchUnsub operation, which closes an existing watch.
+    pub async fn watch_close(&self, watch_id: &str) -> Result<Grid> {
+        let mut req_grid = Grid::new_internal(Vec::new());
+        req_grid.add_to_meta("watchId", json!(watch_id));
+        req_grid.add_to_meta("close", Marker::new().to_hayson());
+
+        self.post(self.watch_unsub_url(), &req_grid).await
+    }
+
+    /// The Haystack watchPoll operation. If `refresh` is false, the returned
+    /// grid only contains the records which have changed since the last
+    /// poll. If `refresh` is true, the returned grid contains all the
+    /// records in the watch.
+    pub async fn watch_poll(
+        &self,
+        watch_id: &str,
+        refresh: bool,
+    ) -> Result<Grid> {
+        let mut req_grid = Grid::new_internal(Vec::new());
+        req_grid.add_to_meta("watchId", json!(watch_id));
+        if refresh {
+            req_grid.add_to_meta("refresh", Marker::new().to_hayson());
+        }
+
+        // A poll which is not a refresh changes the state of the watch,
+        // so any changes returned to a lost response would be missed:
+        let is_idempotent = refresh;
+        self.request(self.watch_poll_url(), Some(&req_grid), is_idempotent)
+            .await
+    }
+}
+
+/// Return a request grid with an `id` column containing the given ids.
+fn watch_req_grid(ids: &[Ref]) -> Grid {
+    let rows = ids.iter().map(|id| json!({"id": id.to_hayson()})).collect();
+    Grid::new_internal(rows)
 }
 
-impl HaystackUrl for SkySparkClient {
+impl HaystackUrl for HaystackClient {
     fn about_url(&self) -> Url {
         self.append_to_url("about")
     }
@@ -553,6 +1721,10 @@ impl HaystackUrl for SkySparkClient {
         self.append_to_url("hisWrite")
     }
 
+    fn invoke_action_url(&self) -> Url {
+        self.append_to_url("invokeAction")
+    }
+
     fn nav_url(&self) -> Url {
         self.append_to_url("nav")
     }
@@ -561,22 +1733,57 @@ impl HaystackUrl for SkySparkClient {
         self.append_to_url("ops")
     }
 
+    fn point_write_url(&self) -> Url {
+        self.append_to_url("pointWrite")
+    }
+
     fn read_url(&self) -> Url {
         self.append_to_url("read")
     }
+
+    fn watch_poll_url(&self) -> Url {
+        self.append_to_url("watchPoll")
+    }
+
+    fn watch_sub_url(&self) -> Url {
+        self.append_to_url("watchSub")
+    }
+
+    fn watch_unsub_url(&self) -> Url {
+        self.append_to_url("watchUnsub")
+    }
 }
 
 impl SkySparkClient {
-    pub async fn eval(&mut self, axon_expr: &str) -> Result<Grid> {
+    pub async fn eval(&self, axon_expr: &str) -> Result<Grid> {
         let row = json!({ "expr": axon_expr });
         let req_grid = Grid::new_internal(vec![row]);
-        self.post(self.eval_url(), &req_grid).await
+        self.haystack_client
+            .post_non_idempotent(self.eval_url(), &req_grid)
+            .await
     }
 }
 
-async fn http_response_to_grid(res: reqwest::Response) -> Result<Grid> {
-    let json: serde_json::Value = res.json().await?;
-    let grid: Grid = json.try_into()?;
+fn http_response_to_grid(res: HttpResponse) -> Result<Grid> {
+    let is_zinc = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(|content_type| content_type.starts_with("text/zinc"))
+        .unwrap_or(false);
+
+    let grid = if is_zinc {
+        let zinc = String::from_utf8(res.into_body()).map_err(|err| {
+            TransportError::new(TransportErrorKind::Decode, err)
+        })?;
+        Grid::from_zinc(&zinc)?
+    } else {
+        let json: serde_json::Value = serde_json::from_slice(res.body())
+            .map_err(|err| {
+                TransportError::new(TransportErrorKind::Decode, err)
+            })?;
+        json.try_into()?
+    };
 
     if grid.is_error() {
         Err(Error::Grid { err_grid: grid })
@@ -585,6 +1792,18 @@ async fn http_response_to_grid(res: reqwest::Response) -> Result<Grid> {
     }
 }
 
+/// Return the error for a response with an error status. If the response
+/// contains an error grid, an `Error::Grid` is returned, so the server's
+/// description of the error is not lost. Otherwise, an error for the status
+/// is returned.
+fn error_status_response_to_err(res: HttpResponse) -> Error {
+    let status = res.status();
+    match http_response_to_grid(res) {
+        Err(err @ Error::Grid { .. }) => err,
+        _ => TransportError::status(status).into(),
+    }
+}
+
 /// Returns true if the given URL appears to have the correct path
 /// segments for a SkySpark API URL. The URL should end with a '/' character.
 pub(crate) fn has_valid_path_segments(project_api_url: &Url) -> bool {
@@ -609,10 +1828,16 @@ mod test {
     use crate::api::HisReadRange;
     use crate::SkySparkClient;
     use crate::ValueExt;
+    use crate::{Grid, GridFormat, Hayson};
     use raystack_core::{Number, Ref};
-    use serde_json::json;
+    use serde_json::{json, Map, Value};
     use url::Url;
 
+    // The tests which use a live SkySpark server are ignored by default.
+    // To run them, set the RAYSTACK_SKYSPARK_PROJECT_API_URL,
+    // RAYSTACK_SKYSPARK_USERNAME and RAYSTACK_SKYSPARK_PASSWORD environment
+    // variables, and run `cargo test -- --include-ignored`.
+
     fn project_api_url() -> Url {
         let url_str =
             std::env::var("RAYSTACK_SKYSPARK_PROJECT_API_URL").unwrap();
@@ -642,32 +1867,49 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn about() {
+        let client = new_client().await;
+        let grid = client.about().await.unwrap();
+        assert_eq!(grid.rows()[0]["whoami"], json!(username()));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
+    async fn zinc_grid_format() {
         let mut client = new_client().await;
+        client.set_grid_format(crate::GridFormat::Zinc);
         let grid = client.about().await.unwrap();
         assert_eq!(grid.rows()[0]["whoami"], json!(username()));
+
+        let grid = client.read("site", Some(1)).await.unwrap();
+        assert!(grid.size() <= 1);
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn filetypes() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         let grid = client.filetypes().await.unwrap();
         assert!(grid.rows()[0]["dis"].is_string());
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_read_today() {
         let range = HisReadRange::Today;
         his_read(&range).await;
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_read_yesterday() {
         let range = HisReadRange::Yesterday;
         his_read(&range).await;
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_read_date() {
         let range =
             HisReadRange::Date(chrono::NaiveDate::from_ymd(2019, 1, 1).into());
@@ -675,6 +1917,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_read_date_span() {
         let range = HisReadRange::DateSpan {
             start: chrono::NaiveDate::from_ymd(2019, 1, 1).into(),
@@ -684,6 +1927,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_read_date_time_span() {
         use chrono::{DateTime, Duration};
         use chrono_tz::Australia::Sydney;
@@ -700,6 +1944,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_read_date_time() {
         use chrono::DateTime;
         use chrono_tz::Australia::Sydney;
@@ -715,6 +1960,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_read_date_time_utc() {
         use chrono::DateTime;
         use chrono_tz::Etc::UTC;
@@ -731,7 +1977,7 @@ mod test {
     async fn his_read(range: &HisReadRange) {
         let filter = format!("point and his and hisEnd");
 
-        let mut client = new_client().await;
+        let client = new_client().await;
         let points_grid = client.read(&filter, Some(1)).await.unwrap();
 
         let point_ref = points_grid.rows()[0]["id"].as_hs_ref().unwrap();
@@ -741,16 +1987,99 @@ mod test {
         assert!(his_grid.meta()["hisEnd"].is_hs_date_time());
     }
 
-    async fn get_ref_for_filter(
-        client: &mut SkySparkClient,
-        filter: &str,
-    ) -> Ref {
+    async fn invoke_action_request_grid(
+        grid_format: GridFormat,
+        args: &Map<String, Value>,
+    ) -> Grid {
+        use crate::transport::TestTransport;
+        use crate::HaystackClientBuilder;
+        use std::convert::TryInto;
+        use std::sync::Arc;
+
+        let transport = Arc::new(TestTransport::empty_grid());
+        let base_url = Url::parse("http://localhost:1/haystack/").unwrap();
+        let client = HaystackClientBuilder::new(base_url, "name", "password")
+            .auth_token("token")
+            .grid_format(grid_format)
+            .transport(transport.clone())
+            .build()
+            .await
+            .unwrap();
+        let id = Ref::new("@abc".to_owned()).unwrap();
+        client.invoke_action(&id, "restart", args).await.unwrap();
+
+        let requests = transport.requests();
+        assert_eq!(requests[0].url().path(), "/haystack/invokeAction");
+        let body = std::str::from_utf8(requests[0].body().unwrap()).unwrap();
+        let grid: Grid = match grid_format {
+            GridFormat::Json => {
+                let json: Value = serde_json::from_str(body).unwrap();
+                json.try_into().unwrap()
+            }
+            GridFormat::Zinc => Grid::from_zinc(body).unwrap(),
+        };
+        assert_eq!(grid.meta()["id"], id.to_hayson());
+        assert_eq!(grid.meta()["action"], "restart");
+        grid
+    }
+
+    #[tokio::test]
+    async fn invoke_action_request_with_args() {
+        let mut args = Map::new();
+        args.insert("reason".to_owned(), json!("Operator said \"restart\""));
+        args.insert("delay".to_owned(), Number::new_unitless(5.0).to_hayson());
+
+        for grid_format in &[GridFormat::Json, GridFormat::Zinc] {
+            let grid = invoke_action_request_grid(*grid_format, &args).await;
+            assert_eq!(grid.col_name_strs(), vec!["delay", "reason"]);
+            assert_eq!(grid.size(), 1);
+            assert_eq!(grid.rows()[0], Value::Object(args.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn invoke_action_request_without_args() {
+        let args = Map::new();
+        for grid_format in &[GridFormat::Json, GridFormat::Zinc] {
+            let grid = invoke_action_request_grid(*grid_format, &args).await;
+            assert_eq!(grid.size(), 0);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
+    async fn his_read_many() {
+        let client = new_client().await;
+        let points_grid = client
+            .read("point and his and hisEnd", Some(3))
+            .await
+            .unwrap();
+        let ids = points_grid
+            .rows()
+            .iter()
+            .map(|row| row["id"].as_hs_ref().unwrap())
+            .collect::<Vec<_>>();
+
+        let his_grids = client
+            .his_read_many(&ids, &HisReadRange::Yesterday)
+            .await
+            .unwrap();
+
+        assert_eq!(his_grids.len(), ids.len());
+        for id in ids {
+            let his_grid = &his_grids[id.as_ref()];
+            assert_eq!(his_grid.meta()["id"].as_hs_ref().unwrap(), id);
+        }
+    }
+
+    async fn get_ref_for_filter(client: &SkySparkClient, filter: &str) -> Ref {
         let points_grid = client.read(filter, Some(1)).await.unwrap();
         let point_ref = points_grid.rows()[0]["id"].as_hs_ref().unwrap();
         point_ref
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn utc_his_write_bool() {
         use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 
@@ -764,10 +2093,10 @@ mod test {
         let date_time2 = date_time1 + Duration::minutes(5);
         let date_time3 = date_time1 + Duration::minutes(10);
 
-        let mut client = new_client().await;
+        let client = new_client().await;
 
         let id = get_ref_for_filter(
-            &mut client,
+            &client,
             "continuousIntegrationHisWritePoint and kind == \"Bool\"",
         )
         .await;
@@ -785,11 +2114,12 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_write_bool() {
         use chrono::{DateTime, Duration};
         use chrono_tz::Australia::Sydney;
 
-        let mut client = new_client().await;
+        let client = new_client().await;
 
         let date_time1 =
             DateTime::parse_from_rfc3339("2019-08-01T00:00:00+10:00")
@@ -799,7 +2129,7 @@ mod test {
         let date_time3 = date_time1 + Duration::minutes(10);
 
         let id = get_ref_for_filter(
-            &mut client,
+            &client,
             "continuousIntegrationHisWritePoint and kind == \"Bool\"",
         )
         .await;
@@ -814,6 +2144,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn utc_his_write_num() {
         use chrono::{Duration, NaiveDateTime, Utc};
 
@@ -828,10 +2159,10 @@ mod test {
         let date_time2 = date_time1 + Duration::minutes(5);
         let date_time3 = date_time1 + Duration::minutes(10);
 
-        let mut client = new_client().await;
+        let client = new_client().await;
 
         let id = get_ref_for_filter(
-            &mut client,
+            &client,
             "continuousIntegrationHisWritePoint and kind == \"Number\" and unit",
         )
         .await;
@@ -852,6 +2183,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_write_num() {
         use chrono::{DateTime, Duration};
         use chrono_tz::Australia::Sydney;
@@ -863,10 +2195,10 @@ mod test {
         let date_time2 = date_time1 + Duration::minutes(5);
         let date_time3 = date_time1 + Duration::minutes(10);
 
-        let mut client = new_client().await;
+        let client = new_client().await;
 
         let id = get_ref_for_filter(
-            &mut client,
+            &client,
             "continuousIntegrationHisWritePoint and kind == \"Number\" and unit",
         )
         .await;
@@ -884,6 +2216,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn utc_his_write_num_no_unit() {
         use chrono::{Duration, NaiveDateTime, Utc};
 
@@ -898,10 +2231,10 @@ mod test {
         let date_time2 = date_time1 + Duration::minutes(5);
         let date_time3 = date_time1 + Duration::minutes(10);
 
-        let mut client = new_client().await;
+        let client = new_client().await;
 
         let id = get_ref_for_filter(
-            &mut client,
+            &client,
             "continuousIntegrationHisWritePoint and kind == \"Number\" and not unit",
         )
         .await;
@@ -919,6 +2252,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_write_num_no_unit() {
         use chrono::{DateTime, Duration};
         use chrono_tz::Australia::Sydney;
@@ -930,10 +2264,10 @@ mod test {
         let date_time2 = date_time1 + Duration::minutes(5);
         let date_time3 = date_time1 + Duration::minutes(10);
 
-        let mut client = new_client().await;
+        let client = new_client().await;
 
         let id = get_ref_for_filter(
-            &mut client,
+            &client,
             "continuousIntegrationHisWritePoint and kind == \"Number\" and not unit",
         )
         .await;
@@ -949,6 +2283,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn utc_his_write_str() {
         use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 
@@ -962,9 +2297,9 @@ mod test {
         let date_time2 = date_time1 + Duration::minutes(5);
         let date_time3 = date_time1 + Duration::minutes(10);
 
-        let mut client = new_client().await;
+        let client = new_client().await;
         let id = get_ref_for_filter(
-            &mut client,
+            &client,
             "continuousIntegrationHisWritePoint and kind == \"Str\"",
         )
         .await;
@@ -983,6 +2318,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn his_write_str() {
         use chrono::{DateTime, Duration};
         use chrono_tz::Australia::Sydney;
@@ -994,9 +2330,9 @@ mod test {
         let date_time2 = date_time1 + Duration::minutes(5);
         let date_time3 = date_time1 + Duration::minutes(10);
 
-        let mut client = new_client().await;
+        let client = new_client().await;
         let id = get_ref_for_filter(
-            &mut client,
+            &client,
             "continuousIntegrationHisWritePoint and kind == \"Str\"",
         )
         .await;
@@ -1012,15 +2348,93 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
+    async fn his_write_generic() {
+        use chrono::{DateTime, Duration};
+        use chrono_tz::Australia::Sydney;
+
+        let date_time1 =
+            DateTime::parse_from_rfc3339("2019-08-01T00:00:00+10:00")
+                .unwrap()
+                .with_timezone(&Sydney);
+        let date_time2 = date_time1 + Duration::minutes(5);
+
+        let client = new_client().await;
+        let id = get_ref_for_filter(
+            &client,
+            "continuousIntegrationHisWritePoint and kind == \"Number\" and unit",
+        )
+        .await;
+
+        let unit = Some("L/s".to_owned());
+        let his_data = [
+            (date_time1.into(), Number::new(1.0, unit.clone())),
+            (date_time2.into(), Number::new(2.0, unit.clone())),
+        ];
+
+        let res = client.his_write(&id, &his_data[..]).await.unwrap();
+        assert_eq!(res.rows().len(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
+    async fn his_write_many() {
+        use chrono::{DateTime, Duration};
+        use chrono_tz::Australia::Sydney;
+        use raystack_core::Hayson;
+
+        let date_time1 =
+            DateTime::parse_from_rfc3339("2019-08-01T00:00:00+10:00")
+                .unwrap()
+                .with_timezone(&Sydney);
+        let date_time2 = date_time1 + Duration::minutes(5);
+
+        let client = new_client().await;
+        let bool_id = get_ref_for_filter(
+            &client,
+            "continuousIntegrationHisWritePoint and kind == \"Bool\"",
+        )
+        .await;
+        let num_id = get_ref_for_filter(
+            &client,
+            "continuousIntegrationHisWritePoint and kind == \"Number\" and not unit",
+        )
+        .await;
+
+        let his_data = vec![
+            (
+                bool_id,
+                vec![
+                    (date_time1.into(), json!(true)),
+                    (date_time2.into(), json!(false)),
+                ],
+            ),
+            (
+                num_id,
+                vec![
+                    (date_time1.into(), Number::new_unitless(1.5).to_hayson()),
+                    (date_time2.into(), Number::new_unitless(2.5).to_hayson()),
+                ],
+            ),
+        ];
+
+        let res = client.his_write_many(&his_data).await.unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].rows().len(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn nav_root() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         let grid = client.nav(None).await.unwrap();
         assert!(grid.rows()[0]["navId"].is_hs_ref());
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn nav() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         let root_grid = client.nav(None).await.unwrap();
         let child_nav_id = root_grid.rows()[0]["navId"].as_hs_ref().unwrap();
 
@@ -1030,15 +2444,17 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn ops() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         let grid = client.ops().await.unwrap();
         assert_eq!(grid.rows()[0]["def"]["_kind"], "symbol");
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn read_with_no_limit() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         let grid = client.read("point", None).await.unwrap();
 
         assert!(grid.rows()[0]["id"].is_hs_ref());
@@ -1046,15 +2462,17 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn read_with_zero_limit() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         let grid = client.read("id", Some(0)).await.unwrap();
         assert_eq!(grid.rows().len(), 0);
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn read_with_non_zero_limit() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         let grid = client.read("id", Some(1)).await.unwrap();
         assert_eq!(grid.rows().len(), 1);
 
@@ -1063,16 +2481,18 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn read_by_ids_with_no_ids() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         let ids = vec![];
         let grid_result = client.read_by_ids(&ids).await;
         assert!(grid_result.is_err());
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn read_by_ids_single() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         // Get some valid ids:
         let grid1 = client.read("id", Some(1)).await.unwrap();
         let ref1 = grid1.rows()[0]["id"].as_hs_ref().unwrap().clone();
@@ -1082,8 +2502,9 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn read_by_ids_multiple() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         // Get some valid ids:
         let grid1 = client.read("id", Some(2)).await.unwrap();
         let ref1 = grid1.rows()[0]["id"].as_hs_ref().unwrap().clone();
@@ -1095,8 +2516,77 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
+    async fn watch_sub_poll_and_close() {
+        use crate::Watch;
+
+        let client = new_client().await.into_haystack_client();
+        let points_grid = client.read("point and cur", Some(2)).await.unwrap();
+        let ids = points_grid
+            .rows()
+            .iter()
+            .map(|row| row["id"].as_hs_ref().unwrap())
+            .collect::<Vec<_>>();
+
+        let lease = Number::new(1.0, Some("min".to_owned()));
+        let (watch, grid) =
+            Watch::open(&client, "raystack test", &ids, Some(&lease))
+                .await
+                .unwrap();
+        assert_eq!(grid.size(), ids.len());
+        assert!(!watch.id().is_empty());
+
+        watch.poll(&client).await.unwrap();
+        let refreshed_grid = watch.refresh(&client).await.unwrap();
+        assert_eq!(refreshed_grid.size(), ids.len());
+
+        watch.close(&client).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
+    async fn watch_stream() {
+        use crate::WatchStream;
+        use futures::StreamExt;
+        use std::time::Duration;
+
+        let client = new_client().await;
+        let points_grid = client.read("point and cur", Some(2)).await.unwrap();
+        let ids = points_grid
+            .rows()
+            .iter()
+            .map(|row| row["id"].as_hs_ref().unwrap())
+            .collect::<Vec<_>>();
+        let ids_len = ids.len();
+
+        let stream = WatchStream::new(
+            client,
+            "raystack test",
+            ids,
+            Duration::from_secs(1),
+            None,
+        );
+        let records = stream.take(ids_len).collect::<Vec<_>>().await;
+
+        assert_eq!(records.len(), ids_len);
+        for record in records {
+            assert!(record.unwrap()["id"].is_hs_ref());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
+    async fn point_write_array() {
+        let client = new_client().await;
+        let id = get_ref_for_filter(&client, "point and writable").await;
+        let array = client.point_write_array(&id).await.unwrap();
+        assert_eq!(array.levels().len(), 17);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn eval() {
-        let mut client = new_client().await;
+        let client = new_client().await;
         let axon_expr = "readAll(id and mod)[0..1].keepCols([\"id\", \"mod\"])";
         let grid = client.eval(axon_expr).await.unwrap();
         assert!(grid.rows()[0]["id"].is_hs_ref());
@@ -1119,10 +2609,11 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn error_grid() {
         use crate::err::Error;
 
-        let mut client = new_client().await;
+        let client = new_client().await;
         let grid_result = client.eval("reabDDDAll(test").await;
 
         assert!(grid_result.is_err());
@@ -1138,6 +2629,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn project_name_works() {
         let client = new_client().await;
         assert!(client.project_name().len() > 3);
@@ -1163,22 +2655,119 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
     async fn recovers_from_invalid_auth_token() {
-        let mut client = new_client().await;
+        let client = new_client().await;
 
         let bad_token = "badauthtoken";
 
-        assert_ne!(client.test_auth_token(), bad_token);
+        assert_ne!(client.auth_token().await, bad_token);
 
         // Check the client works before modifying the auth token:
         let grid1 = client.about().await.unwrap();
         assert_eq!(grid1.rows()[0]["whoami"], json!(username()));
 
-        client.test_manually_set_auth_token(bad_token);
-        assert_eq!(client.test_auth_token(), bad_token);
+        client.test_manually_set_auth_token(bad_token).await;
+        assert_eq!(client.auth_token().await, bad_token);
 
         // Check the client still works after setting a bad auth token:
         let grid2 = client.about().await.unwrap();
         assert_eq!(grid2.rows()[0]["whoami"], json!(username()));
     }
+
+    #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
+    async fn logout() {
+        let client = new_client().await;
+        let auth_token = client.auth_token().await;
+
+        client.logout().await.unwrap();
+        assert_eq!(client.auth_token().await, "");
+
+        // Logging out again does nothing:
+        client.logout().await.unwrap();
+
+        // The client authenticates again when it is next used:
+        let grid = client.about().await.unwrap();
+        assert_eq!(grid.rows()[0]["whoami"], json!(username()));
+        let new_auth_token = client.auth_token().await;
+        assert_ne!(new_auth_token, "");
+        assert_ne!(new_auth_token, auth_token);
+
+        client.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a live SkySpark server"]
+    async fn clones_recover_from_invalid_auth_token_concurrently() {
+        let client = new_client().await;
+        let bad_token = "badauthtoken";
+        client.test_manually_set_auth_token(bad_token).await;
+
+        let handles = (0..4)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.about().await })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            let grid = handle.await.unwrap().unwrap();
+            assert_eq!(grid.rows()[0]["whoami"], json!(username()));
+        }
+
+        // All clones share the same new auth token:
+        let auth_token = client.auth_token().await;
+        assert_ne!(auth_token, bad_token);
+        assert_eq!(client.clone().auth_token().await, auth_token);
+    }
+
+    #[tokio::test]
+    async fn new_with_auth_token_does_not_authenticate() {
+        let url = Url::parse("http://localhost:1/api/proj").unwrap();
+        let client = SkySparkClient::new_with_auth_token(
+            url,
+            "name",
+            "password",
+            "savedtoken",
+            reqwest::Client::new(),
+        )
+        .unwrap();
+        assert_eq!(client.auth_token().await, "savedtoken");
+        assert_eq!(client.clone().auth_token().await, "savedtoken");
+
+        let bad_url = Url::parse("http://localhost:1/notapi").unwrap();
+        let result = SkySparkClient::new_with_auth_token(
+            bad_url,
+            "name",
+            "password",
+            "savedtoken",
+            reqwest::Client::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn client_debug_output_does_not_contain_password() {
+        let url = Url::parse("http://localhost:1/api/proj").unwrap();
+        let client = SkySparkClient::new_with_auth_token(
+            url,
+            "name",
+            "s3cr3tpassw0rd",
+            "savedtoken",
+            reqwest::Client::new(),
+        )
+        .unwrap();
+        let debug = format!("{:?}", client);
+        assert!(debug.contains("name"));
+        assert!(!debug.contains("s3cr3tpassw0rd"));
+        assert!(!debug.contains("savedtoken"));
+    }
+
+    #[test]
+    fn client_is_send_sync_and_clone() {
+        fn assert_send_sync_clone<T: Send + Sync + Clone>() {}
+        assert_send_sync_clone::<SkySparkClient>();
+        assert_send_sync_clone::<crate::HaystackClient>();
+    }
 }
@@ -0,0 +1,269 @@
+//! Limits on the number of concurrent requests and the rate of requests
+//! sent by a client, shared by all clones of the client.
+
+use crate::transport::{HttpRequest, HttpResponse, Transport, TransportError};
+use futures::future::BoxFuture;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+/// Limits the requests sent by all clones of a `HaystackClient`.
+#[derive(Debug, Default)]
+pub(crate) struct RequestLimiter {
+    max_in_flight: Option<usize>,
+    in_flight: Option<Semaphore>,
+    rate_limit: Option<RateLimit>,
+}
+
+impl RequestLimiter {
+    /// Create a new `RequestLimiter`. If either limit is `None`, that limit
+    /// is not enforced. Neither limit may be zero.
+    pub(crate) fn new(
+        max_in_flight: Option<usize>,
+        max_requests_per_second: Option<u32>,
+    ) -> Self {
+        Self {
+            max_in_flight,
+            in_flight: max_in_flight.map(Semaphore::new),
+            rate_limit: max_requests_per_second.map(RateLimit::new),
+        }
+    }
+
+    pub(crate) fn max_in_flight(&self) -> Option<usize> {
+        self.max_in_flight
+    }
+
+    pub(crate) fn max_requests_per_second(&self) -> Option<u32> {
+        self.rate_limit
+            .as_ref()
+            .map(|rate_limit| rate_limit.requests_per_second)
+    }
+
+    /// Wait until a request can be sent without exceeding either limit. The
+    /// returned permit must be held until the response has been received.
+    pub(crate) async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let permit = match &self.in_flight {
+            Some(in_flight) => Some(
+                in_flight
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.wait().await;
+        }
+
+        permit
+    }
+}
+
+/// Spaces requests evenly, so that no more than `requests_per_second`
+/// requests are sent each second.
+#[derive(Debug)]
+struct RateLimit {
+    requests_per_second: u32,
+    interval: Duration,
+    /// The earliest time the next request may be sent.
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl RateLimit {
+    fn new(requests_per_second: u32) -> Self {
+        Self {
+            requests_per_second,
+            interval: Duration::from_secs(1) / requests_per_second,
+            next_slot: Mutex::new(None),
+        }
+    }
+
+    /// Reserve the next available slot, and wait until it starts.
+    async fn wait(&self) {
+        let slot = {
+            let mut next_slot =
+                self.next_slot.lock().expect("lock not poisoned");
+            let now = Instant::now();
+            let slot = next_slot.map_or(now, |next_slot| next_slot.max(now));
+            *next_slot = Some(slot + self.interval);
+            slot
+        };
+
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// A transport which waits for the limiter before sending each request,
+/// and holds the limiter's permit only until the response is received.
+#[derive(Debug)]
+pub(crate) struct LimitedTransport<'a> {
+    transport: &'a dyn Transport,
+    limiter: &'a RequestLimiter,
+}
+
+impl<'a> LimitedTransport<'a> {
+    pub(crate) fn new(
+        transport: &'a dyn Transport,
+        limiter: &'a RequestLimiter,
+    ) -> Self {
+        Self { transport, limiter }
+    }
+}
+
+impl Transport for LimitedTransport<'_> {
+    fn send(
+        &self,
+        request: HttpRequest,
+    ) -> BoxFuture<'_, Result<HttpResponse, TransportError>> {
+        Box::pin(async move {
+            let _permit = self.limiter.acquire().await;
+            self.transport.send(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transport::TestTransport;
+    use crate::{
+        HaystackClient, HaystackClientBuilder, HttpResponse, RetryPolicy,
+    };
+    use futures::future::join_all;
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use url::Url;
+
+    fn slow_transport() -> Arc<TestTransport> {
+        Arc::new(
+            TestTransport::empty_grid().with_delay(Duration::from_millis(20)),
+        )
+    }
+
+    fn builder(transport: Arc<TestTransport>) -> HaystackClientBuilder {
+        let base_url = Url::parse("http://localhost:1/haystack/").unwrap();
+        HaystackClientBuilder::new(base_url, "name", "password")
+            .auth_token("token")
+            .transport(transport)
+    }
+
+    async fn run_ops(client: &HaystackClient, count: usize) {
+        let ops = (0..count).map(|_| {
+            let client = client.clone();
+            async move { client.about().await.unwrap() }
+        });
+        join_all(ops).await;
+    }
+
+    #[tokio::test]
+    async fn unlimited_by_default() {
+        let transport = slow_transport();
+        let client = builder(transport.clone()).build().await.unwrap();
+        assert_eq!(client.max_in_flight(), None);
+        assert_eq!(client.max_requests_per_second(), None);
+
+        run_ops(&client, 5).await;
+        assert_eq!(transport.max_in_flight(), 5);
+    }
+
+    #[tokio::test]
+    async fn max_in_flight_is_shared_by_clones() {
+        let transport = slow_transport();
+        let client = builder(transport.clone())
+            .max_in_flight(2)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(client.max_in_flight(), Some(2));
+
+        run_ops(&client, 10).await;
+        assert_eq!(transport.max_in_flight(), 2);
+    }
+
+    #[tokio::test]
+    async fn max_requests_per_second_spaces_requests() {
+        let client = builder(slow_transport())
+            .max_requests_per_second(50)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(client.max_requests_per_second(), Some(50));
+
+        // The first op starts immediately, and each of the others starts
+        // 20ms after the previous op:
+        let start = Instant::now();
+        run_ops(&client, 6).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    fn unavailable_ops_transport() -> Arc<TestTransport> {
+        Arc::new(TestTransport::new(|request| {
+            if request.url().path() == "/haystack/ops" {
+                let status = StatusCode::SERVICE_UNAVAILABLE;
+                HttpResponse::new(status, HeaderMap::new(), Vec::new())
+            } else {
+                let grid = json!({
+                    "_kind": "grid",
+                    "meta": {"ver": "3.0"},
+                    "cols": [{"name": "empty"}],
+                    "rows": [],
+                });
+                TestTransport::json_response(StatusCode::OK, &grid)
+            }
+        }))
+    }
+
+    #[tokio::test]
+    async fn max_requests_per_second_applies_to_retries() {
+        let transport = unavailable_ops_transport();
+        let retry_policy = RetryPolicy::new(4)
+            .with_backoff(Duration::from_millis(0), Duration::from_millis(0))
+            .with_jitter(false);
+        let client = builder(transport.clone())
+            .retry_policy(retry_policy)
+            .max_requests_per_second(20)
+            .build()
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        assert!(client.ops().await.is_err());
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(transport.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn max_in_flight_is_not_held_during_backoff() {
+        let retry_policy = RetryPolicy::new(2)
+            .with_backoff(Duration::from_millis(500), Duration::from_secs(1))
+            .with_jitter(false);
+        let client = builder(unavailable_ops_transport())
+            .retry_policy(retry_policy)
+            .max_in_flight(1)
+            .build()
+            .await
+            .unwrap();
+
+        let retrying_client = client.clone();
+        let retrying_op =
+            tokio::spawn(async move { retrying_client.ops().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // The retrying op is waiting to retry, so another op can be sent:
+        let start = Instant::now();
+        client.about().await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(250));
+        assert!(retrying_op.await.unwrap().is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_max_in_flight_panics() {
+        let url = Url::parse("http://localhost:1/haystack/").unwrap();
+        HaystackClientBuilder::new(url, "name", "password").max_in_flight(0);
+    }
+}
@@ -0,0 +1,184 @@
+//! Counters of the operations performed by a client.
+//!
+//! Each Haystack op is also performed inside a `haystack_op` tracing span,
+//! and each authentication inside a `haystack_auth` span. Auth tokens and
+//! passwords are never recorded in spans or events.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A snapshot of the counters shared by all clones of a `HaystackClient`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ClientMetrics {
+    ops: u64,
+    errors: u64,
+    reauthentications: u64,
+}
+
+impl ClientMetrics {
+    /// Return the number of Haystack ops the client has performed,
+    /// including ops which failed.
+    pub fn ops(&self) -> u64 {
+        self.ops
+    }
+
+    /// Return the number of Haystack ops which returned an error.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Return the number of times the client authenticated again, after the
+    /// server rejected its auth token.
+    pub fn reauthentications(&self) -> u64 {
+        self.reauthentications
+    }
+}
+
+/// The counters shared by all clones of a `HaystackClient`.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsCounters {
+    ops: AtomicU64,
+    errors: AtomicU64,
+    reauthentications: AtomicU64,
+}
+
+impl MetricsCounters {
+    pub(crate) fn record_op(&self, is_error: bool) {
+        self.ops.fetch_add(1, Ordering::Relaxed);
+        if is_error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_reauthentication(&self) {
+        self.reauthentications.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ClientMetrics {
+        ClientMetrics {
+            ops: self.ops.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            reauthentications: self.reauthentications.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transport::TestTransport;
+    use crate::{HaystackClientBuilder, HttpResponse, RetryPolicy};
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::format::FmtSpan;
+    use tracing_subscriber::fmt::MakeWriter;
+    use url::Url;
+
+    /// Collects the output of a tracing subscriber.
+    #[derive(Clone, Default)]
+    struct LogWriter {
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl LogWriter {
+        fn output(&self) -> String {
+            String::from_utf8(self.output.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for LogWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for LogWriter {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn subscriber(writer: LogWriter) -> impl tracing::Subscriber {
+        tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_max_level(tracing::Level::TRACE)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_writer(writer)
+            .finish()
+    }
+
+    #[tokio::test]
+    async fn errors_are_counted_and_traced() {
+        let writer = LogWriter::default();
+        let _guard =
+            tracing::subscriber::set_default(subscriber(writer.clone()));
+
+        let base_url = Url::parse("http://localhost:1/haystack/").unwrap();
+        let client = HaystackClientBuilder::new(base_url, "name", "s3cr3t")
+            .auth_token("t0k3n")
+            .retry_policy(RetryPolicy::none())
+            .transport(Arc::new(TestTransport::new(|_| {
+                let status = StatusCode::INTERNAL_SERVER_ERROR;
+                HttpResponse::new(status, HeaderMap::new(), Vec::new())
+            })))
+            .build()
+            .await
+            .unwrap();
+        let clone = client.clone();
+
+        assert!(client.about().await.is_err());
+        assert!(clone.ops().await.is_err());
+
+        let metrics = client.metrics();
+        assert_eq!(metrics.ops(), 2);
+        assert_eq!(metrics.errors(), 2);
+        assert_eq!(metrics.reauthentications(), 0);
+
+        let output = writer.output();
+        assert!(output.contains("haystack_op"));
+        assert!(output.contains("op=\"about\""));
+        assert!(output.contains("status=500"));
+        assert!(output.contains("Haystack op failed"));
+        assert!(!output.contains("t0k3n"));
+        assert!(!output.contains("s3cr3t"));
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn reauthentications_are_counted_and_traced() {
+        use crate::mock::MockServer;
+
+        let writer = LogWriter::default();
+        let _guard =
+            tracing::subscriber::set_default(subscriber(writer.clone()));
+
+        let server = MockServer::new("name", "s3cr3t");
+        let client = server.client().await.unwrap();
+        let first_auth_token = client.auth_token().await;
+
+        server.expire_auth_tokens();
+        client.read("site", None).await.unwrap();
+        let second_auth_token = client.auth_token().await;
+
+        let metrics = client.metrics();
+        assert_eq!(metrics.ops(), 1);
+        assert_eq!(metrics.errors(), 0);
+        assert_eq!(metrics.reauthentications(), 1);
+
+        let output = writer.output();
+        assert!(output.contains("haystack_auth"));
+        assert!(output.contains("scheme=\"SCRAM\""));
+        assert!(output.contains("op=\"read\""));
+        assert!(output.contains("request_rows=1"));
+        assert!(output.contains("reauthenticated=true"));
+        assert!(!output.contains(&first_auth_token));
+        assert!(!output.contains(&second_auth_token));
+        assert!(!output.contains("s3cr3t"));
+    }
+}
@@ -0,0 +1,1176 @@
+//! An in-process mock Haystack server, for testing code which uses a
+//! `SkySparkClient` without a live SkySpark server.
+//!
+//! The mock server is a `Transport`, so requests sent by a client which uses
+//! it never leave the process. It authenticates clients with the SCRAM
+//! scheme, and supports the `about`, `read`, `nav`, `hisRead`, `hisWrite`
+//! and `eval` ops, using records and history data seeded by the test.
+//!
+//! This module is only available when the `mock` feature is enabled.
+
+use crate::{
+    DateTime, Grid, Hayson, HttpRequest, HttpResponse, NewSkySparkClientError,
+    Ref, SkySparkClient, SkySparkClientBuilder, Transport, TransportError,
+};
+use chrono::{NaiveDate, Utc};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac, NewMac};
+use pbkdf2::pbkdf2;
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::StatusCode;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex, MutexGuard};
+use url::Url;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The number of PBKDF2 iterations the server asks clients to use. This is
+/// lower than a real server would use, so tests run quickly.
+const ITERATIONS: u32 = 1000;
+
+const PROJECT_API_URL: &str = "http://raystack.mock/api/mock/";
+
+/// An in-process mock Haystack server.
+///
+/// Clones of a `MockServer` share the same records, history data and
+/// sessions.
+///
+/// Filters used by the `read` op and by `readAll(...)` expressions in the
+/// `eval` op support tag names, `not`, `and`, `or`, and the `==` and `!=`
+/// comparisons with strings, numbers, refs and booleans. Each part of a
+/// filter must be separated by whitespace, and parentheses are not
+/// supported.
+///
+/// # Example
+/// ```rust
+/// # async fn run() {
+/// use raystack::mock::MockServer;
+/// use raystack::{Hayson, Marker, Ref};
+/// use serde_json::json;
+///
+/// let server = MockServer::new("username", "p4ssw0rd");
+/// let site_id = Ref::new("@site1".to_owned()).unwrap();
+/// server.add_record(json!({
+///     "id": site_id.to_hayson(),
+///     "dis": "Site 1",
+///     "site": Marker::new().to_hayson(),
+/// }));
+///
+/// let client = server.client().await.unwrap();
+/// let grid = client.read("site", None).await.unwrap();
+/// assert_eq!(grid.size(), 1);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct MockServer {
+    state: Arc<Mutex<MockState>>,
+}
+
+struct MockState {
+    username: String,
+    password: String,
+    salt: Vec<u8>,
+    handshakes: HashMap<String, Handshake>,
+    auth_tokens: HashSet<String>,
+    authentication_count: usize,
+    records: Vec<Map<String, Value>>,
+    his: HashMap<String, Vec<(DateTime, Value)>>,
+    eval_results: HashMap<String, Grid>,
+}
+
+/// The state of a SCRAM handshake which has not finished yet.
+struct Handshake {
+    username: String,
+    client_first_msg: Option<String>,
+    server_first_msg: Option<String>,
+    nonce: Option<String>,
+}
+
+impl MockServer {
+    /// Create a new `MockServer`, which accepts the given username and
+    /// password, and has no records.
+    pub fn new(username: &str, password: &str) -> Self {
+        let state = MockState {
+            username: username.to_owned(),
+            password: password.to_owned(),
+            salt: rand::thread_rng().gen::<[u8; 16]>().to_vec(),
+            handshakes: HashMap::new(),
+            auth_tokens: HashSet::new(),
+            authentication_count: 0,
+            records: Vec::new(),
+            his: HashMap::new(),
+            eval_results: HashMap::new(),
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Return the project API URL of the mock server. Requests sent to any
+    /// URL are handled by the mock server, but `SkySparkClient`s must be
+    /// created with a URL formatted like this one.
+    pub fn project_api_url(&self) -> Url {
+        Url::parse(PROJECT_API_URL).expect("mock project API URL is valid")
+    }
+
+    /// Create a `SkySparkClient` which uses this mock server, and
+    /// authenticate with the username and password of the mock server.
+    pub async fn client(
+        &self,
+    ) -> Result<SkySparkClient, NewSkySparkClientError> {
+        let (username, password) = {
+            let state = self.state();
+            (state.username.clone(), state.password.clone())
+        };
+
+        SkySparkClientBuilder::new(self.project_api_url(), &username, &password)
+            .transport(Arc::new(self.clone()))
+            .build()
+            .await
+    }
+
+    /// Add a record, which must be a JSON object containing Hayson-encoded
+    /// tags. Records should have an `id` tag containing a Ref.
+    ///
+    /// # Panics
+    /// Panics if the record is not a JSON object.
+    pub fn add_record(&self, record: Value) {
+        match record {
+            Value::Object(record) => self.state().records.push(record),
+            _ => panic!("mock server records must be JSON objects"),
+        }
+    }
+
+    /// Return all of the records in the mock server.
+    pub fn records(&self) -> Vec<Value> {
+        self.state()
+            .records
+            .iter()
+            .map(|record| Value::Object(record.clone()))
+            .collect()
+    }
+
+    /// Add history data for the point with the given id, replacing any
+    /// existing values with the same timestamps.
+    pub fn add_his(&self, id: &Ref, his_data: &[(DateTime, Value)]) {
+        self.state().add_his(&ref_key(id), his_data.to_vec());
+    }
+
+    /// Return the history data for the point with the given id, in
+    /// timestamp order.
+    pub fn his(&self, id: &Ref) -> Vec<(DateTime, Value)> {
+        self.state()
+            .his
+            .get(&ref_key(id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Set the grid returned by the `eval` op for the given Axon
+    /// expression.
+    pub fn set_eval_result(&self, axon_expr: &str, grid: Grid) {
+        self.state().eval_results.insert(axon_expr.to_owned(), grid);
+    }
+
+    /// End every session, as if they had expired. Clients must
+    /// authenticate again before the server accepts their requests.
+    pub fn expire_auth_tokens(&self) {
+        self.state().auth_tokens.clear();
+    }
+
+    /// Return the number of times a client has successfully authenticated
+    /// with the server.
+    pub fn authentication_count(&self) -> usize {
+        self.state().authentication_count
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        let mut state = self.state();
+
+        let auth_header = request
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        let (scheme, params) = split_auth_header(auth_header);
+
+        match scheme.to_ascii_uppercase().as_str() {
+            "HELLO" => return state.hello(&params),
+            "SCRAM" => return state.scram(&params),
+            "BEARER" => {
+                let auth_token = params.get("authToken");
+                let is_valid = auth_token
+                    .map(|auth_token| state.auth_tokens.contains(auth_token))
+                    .unwrap_or(false);
+                if !is_valid {
+                    return empty_response(StatusCode::FORBIDDEN);
+                }
+            }
+            _ => return empty_response(StatusCode::UNAUTHORIZED),
+        }
+
+        let path = request.url().path();
+        if path == "/user/logout" {
+            if let Some(auth_token) = params.get("authToken") {
+                state.auth_tokens.remove(auth_token);
+            }
+            return empty_response(StatusCode::OK);
+        }
+
+        let (status, grid) = match request_grid(request) {
+            Ok(req_grid) => match path.rsplit('/').next().unwrap_or("") {
+                "about" => (StatusCode::OK, about_grid()),
+                "read" => (StatusCode::OK, state.read(&req_grid)),
+                "nav" => (StatusCode::OK, state.nav(&req_grid)),
+                "hisRead" => (StatusCode::OK, state.his_read(&req_grid)),
+                "hisWrite" => (StatusCode::OK, state.his_write(&req_grid)),
+                "eval" => (StatusCode::OK, state.eval(&req_grid)),
+                op => {
+                    let msg =
+                        format!("the mock server does not support {}", op);
+                    (StatusCode::NOT_FOUND, error_grid(&msg))
+                }
+            },
+            Err(msg) => (StatusCode::BAD_REQUEST, error_grid(&msg)),
+        };
+
+        grid_response(request, status, &grid)
+    }
+}
+
+impl Debug for MockServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockServer").finish_non_exhaustive()
+    }
+}
+
+impl Transport for MockServer {
+    fn send(
+        &self,
+        request: HttpRequest,
+    ) -> BoxFuture<'_, Result<HttpResponse, TransportError>> {
+        let response = self.handle(&request);
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+impl MockState {
+    /// Start a SCRAM handshake in response to a `HELLO` request.
+    fn hello(&mut self, params: &HashMap<String, String>) -> HttpResponse {
+        let username = match params.get("username").and_then(|u| decode(u)) {
+            Some(username) => username,
+            None => return empty_response(StatusCode::BAD_REQUEST),
+        };
+
+        let handshake_token = random_token();
+        let handshake = Handshake {
+            username,
+            client_first_msg: None,
+            server_first_msg: None,
+            nonce: None,
+        };
+        self.handshakes.insert(handshake_token.clone(), handshake);
+
+        let www_authenticate =
+            format!("SCRAM handshakeToken={}, hash=SHA-256", handshake_token);
+        header_response(
+            StatusCode::UNAUTHORIZED,
+            "www-authenticate",
+            &www_authenticate,
+        )
+    }
+
+    /// Continue a SCRAM handshake, in response to either the client's first
+    /// message or the client's final message.
+    fn scram(&mut self, params: &HashMap<String, String>) -> HttpResponse {
+        let handshake_token = params.get("handshakeToken");
+        let data = params.get("data").and_then(|data| decode(data));
+        let (handshake_token, data) = match (handshake_token, data) {
+            (Some(handshake_token), Some(data)) => (handshake_token, data),
+            _ => return empty_response(StatusCode::BAD_REQUEST),
+        };
+
+        let is_first_msg = match self.handshakes.get(handshake_token) {
+            Some(handshake) => handshake.client_first_msg.is_none(),
+            None => return empty_response(StatusCode::FORBIDDEN),
+        };
+
+        if is_first_msg {
+            self.scram_first(handshake_token, data)
+        } else {
+            let handshake = self
+                .handshakes
+                .remove(handshake_token)
+                .expect("handshake exists");
+            self.scram_final(handshake, &data)
+        }
+    }
+
+    fn scram_first(
+        &mut self,
+        handshake_token: &str,
+        client_first_msg: String,
+    ) -> HttpResponse {
+        let client_nonce = match parse_scram_data(&client_first_msg).get("r") {
+            Some(client_nonce) => client_nonce.clone(),
+            None => return empty_response(StatusCode::BAD_REQUEST),
+        };
+
+        let nonce = format!("{}{}", client_nonce, random_token());
+        let server_first_msg = format!(
+            "r={},s={},i={}",
+            nonce,
+            base64::encode(&self.salt),
+            ITERATIONS
+        );
+
+        let handshake = self
+            .handshakes
+            .get_mut(handshake_token)
+            .expect("handshake exists");
+        handshake.client_first_msg = Some(client_first_msg);
+        handshake.server_first_msg = Some(server_first_msg.clone());
+        handshake.nonce = Some(nonce);
+
+        let www_authenticate = format!(
+            "SCRAM handshakeToken={}, hash=SHA-256, data={}",
+            handshake_token,
+            encode(&server_first_msg)
+        );
+        header_response(
+            StatusCode::UNAUTHORIZED,
+            "www-authenticate",
+            &www_authenticate,
+        )
+    }
+
+    fn scram_final(
+        &mut self,
+        handshake: Handshake,
+        client_final_msg: &str,
+    ) -> HttpResponse {
+        let data = parse_scram_data(client_final_msg);
+        let (nonce, proof) = match (data.get("r"), data.get("p")) {
+            (Some(nonce), Some(proof)) => (nonce, proof),
+            _ => return empty_response(StatusCode::BAD_REQUEST),
+        };
+
+        let client_final_no_proof = format!("c=biws,r={}", nonce);
+        let auth_msg = format!(
+            "{},{},{}",
+            handshake.client_first_msg.unwrap_or_default(),
+            handshake.server_first_msg.unwrap_or_default(),
+            client_final_no_proof
+        );
+
+        let mut salted_password = [0u8; 32];
+        pbkdf2::<HmacSha256>(
+            self.password.as_bytes(),
+            &self.salt,
+            ITERATIONS,
+            &mut salted_password,
+        );
+        let client_key = hmac_sign(&salted_password, b"Client Key");
+        let stored_key = Sha256::digest(&client_key);
+        let client_signature = hmac_sign(&stored_key, auth_msg.as_bytes());
+        let expected_proof: Vec<u8> = client_key
+            .iter()
+            .zip(client_signature)
+            .map(|(key_byte, sig_byte)| key_byte ^ sig_byte)
+            .collect();
+
+        let base64_config =
+            base64::Config::new(base64::CharacterSet::Standard, false);
+        let proof = base64::decode_config(proof, base64_config);
+
+        let is_valid = handshake.username == self.username
+            && handshake.nonce.as_ref() == Some(nonce)
+            && proof.ok() == Some(expected_proof);
+        if !is_valid {
+            return empty_response(StatusCode::FORBIDDEN);
+        }
+
+        let server_key = hmac_sign(&salted_password, b"Server Key");
+        let server_signature = hmac_sign(&server_key, auth_msg.as_bytes());
+        let server_final_msg =
+            format!("v={}", base64::encode(server_signature));
+
+        let auth_token = random_token();
+        self.auth_tokens.insert(auth_token.clone());
+        self.authentication_count += 1;
+
+        let auth_info = format!(
+            "authToken={}, hash=SHA-256, data={}",
+            auth_token,
+            encode(&server_final_msg)
+        );
+        header_response(StatusCode::OK, "authentication-info", &auth_info)
+    }
+
+    fn read(&self, req_grid: &Grid) -> Grid {
+        let row = match req_grid.rows().first() {
+            Some(row) => row,
+            None => return Grid::empty(),
+        };
+
+        match row["filter"].as_str() {
+            Some(filter) => {
+                let limit = number(&row["limit"]).map(|limit| limit as usize);
+                self.read_filter(filter, limit)
+            }
+            None => {
+                let rows = req_grid
+                    .rows()
+                    .iter()
+                    .map(|row| {
+                        let record = self.records.iter().find(|record| {
+                            same_ref(record.get("id"), &row["id"])
+                        });
+                        match record {
+                            Some(record) => Value::Object(record.clone()),
+                            None => json!({}),
+                        }
+                    })
+                    .collect();
+                records_grid(rows)
+            }
+        }
+    }
+
+    fn read_filter(&self, filter: &str, limit: Option<usize>) -> Grid {
+        let filter = match Filter::parse(filter) {
+            Ok(filter) => filter,
+            Err(msg) => return error_grid(&msg),
+        };
+
+        let rows = self
+            .records
+            .iter()
+            .filter(|record| filter.matches(record))
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|record| Value::Object(record.clone()))
+            .collect();
+        records_grid(rows)
+    }
+
+    /// Sites are at the top level of the navigation tree, followed by the
+    /// equips of each site, and then the points of each equip.
+    fn nav(&self, req_grid: &Grid) -> Grid {
+        let nav_id = req_grid
+            .rows()
+            .first()
+            .map(|row| &row["navId"])
+            .filter(|nav_id| !nav_id.is_null());
+
+        let rows = self
+            .records
+            .iter()
+            .filter(|record| match nav_id {
+                None => record.contains_key("site"),
+                Some(nav_id) => {
+                    (record.contains_key("equip")
+                        && same_ref(record.get("siteRef"), nav_id))
+                        || (record.contains_key("point")
+                            && same_ref(record.get("equipRef"), nav_id))
+                }
+            })
+            .map(|record| {
+                let mut record = record.clone();
+                if let Some(id) = record.get("id").cloned() {
+                    record.insert("navId".to_owned(), id);
+                }
+                Value::Object(record)
+            })
+            .collect();
+        records_grid(rows)
+    }
+
+    fn his_read(&self, req_grid: &Grid) -> Grid {
+        let row = match req_grid.rows() {
+            rows if rows.len() == 1 => &rows[0],
+            _ => {
+                let msg = "the mock server only supports hisRead requests \
+                           for a single point";
+                return error_grid(msg);
+            }
+        };
+        let id = match ref_value(&row["id"]) {
+            Some(id) => id,
+            None => return error_grid("hisRead request has no id"),
+        };
+        let range = row["range"].as_str().unwrap_or("");
+
+        let items = self.his.get(&id).map(|items| items.as_slice());
+        let mut rows = Vec::new();
+        for (ts, val) in items.unwrap_or(&[]) {
+            match is_in_range(ts, range) {
+                Ok(true) => {
+                    rows.push(json!({"ts": ts.to_hayson(), "val": val}))
+                }
+                Ok(false) => (),
+                Err(msg) => return error_grid(&msg),
+            }
+        }
+
+        let mut grid = records_grid(rows);
+        grid.add_to_meta("id", row["id"].clone());
+        grid
+    }
+
+    fn his_write(&mut self, req_grid: &Grid) -> Grid {
+        let id = match ref_value(&req_grid.meta()["id"]) {
+            Some(id) => id,
+            None => {
+                let msg = "the mock server only supports hisWrite requests \
+                           with an id in the grid meta";
+                return error_grid(msg);
+            }
+        };
+
+        let mut his_data = Vec::new();
+        for row in req_grid.rows() {
+            match DateTime::from_hayson(&row["ts"]) {
+                Ok(ts) => his_data.push((ts, row["val"].clone())),
+                Err(_) => return error_grid("hisWrite row has an invalid ts"),
+            }
+        }
+
+        self.add_his(&id, his_data);
+        Grid::empty()
+    }
+
+    fn eval(&self, req_grid: &Grid) -> Grid {
+        let expr = req_grid
+            .rows()
+            .first()
+            .and_then(|row| row["expr"].as_str())
+            .unwrap_or("")
+            .trim();
+
+        if let Some(grid) = self.eval_results.get(expr) {
+            return grid.clone();
+        }
+
+        let filter = expr
+            .strip_prefix("readAll(")
+            .and_then(|expr| expr.strip_suffix(')'));
+        match filter {
+            Some(filter) => self.read_filter(filter, None),
+            None => {
+                let msg = format!(
+                    "the mock server cannot evaluate the expression {}",
+                    expr
+                );
+                error_grid(&msg)
+            }
+        }
+    }
+
+    fn add_his(&mut self, id: &str, his_data: Vec<(DateTime, Value)>) {
+        let items = self.his.entry(id.to_owned()).or_default();
+        for (ts, val) in his_data {
+            items.retain(|(existing_ts, _)| {
+                existing_ts.date_time() != ts.date_time()
+            });
+            items.push((ts, val));
+        }
+        items.sort_by_key(|(ts, _)| ts.date_time().with_timezone(&Utc));
+    }
+}
+
+/// A filter used by the `read` op.
+enum Filter {
+    Or(Vec<Filter>),
+    And(Vec<Filter>),
+    Has(String),
+    Missing(String),
+    Eq(String, Literal),
+    NotEq(String, Literal),
+}
+
+enum Literal {
+    Bool(bool),
+    Number(f64),
+    Ref(String),
+    Str(String),
+}
+
+impl Filter {
+    fn parse(filter: &str) -> Result<Self, String> {
+        let tokens = tokenize(filter)?;
+        let or_parts = tokens
+            .split(|token| token == "or")
+            .map(|and_tokens| {
+                let and_parts = and_tokens
+                    .split(|token| token == "and")
+                    .map(Self::parse_term)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Self::And(and_parts))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self::Or(or_parts))
+    }
+
+    fn parse_term(tokens: &[String]) -> Result<Self, String> {
+        let tokens = tokens.iter().map(|t| t.as_str()).collect::<Vec<_>>();
+        match tokens.as_slice() {
+            [name] => Ok(Self::Has(parse_tag_name(name)?)),
+            ["not", name] => Ok(Self::Missing(parse_tag_name(name)?)),
+            [name, "==", literal] => {
+                Ok(Self::Eq(parse_tag_name(name)?, Literal::parse(literal)?))
+            }
+            [name, "!=", literal] => {
+                Ok(Self::NotEq(parse_tag_name(name)?, Literal::parse(literal)?))
+            }
+            _ => Err(format!(
+                "the mock server does not support the filter term '{}'",
+                tokens.join(" ")
+            )),
+        }
+    }
+
+    fn matches(&self, record: &Map<String, Value>) -> bool {
+        match self {
+            Self::Or(filters) => filters.iter().any(|f| f.matches(record)),
+            Self::And(filters) => filters.iter().all(|f| f.matches(record)),
+            Self::Has(name) => record.contains_key(name),
+            Self::Missing(name) => !record.contains_key(name),
+            Self::Eq(name, literal) => record
+                .get(name)
+                .map(|value| literal.matches(value))
+                .unwrap_or(false),
+            Self::NotEq(name, literal) => record
+                .get(name)
+                .map(|value| !literal.matches(value))
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl Literal {
+    fn parse(literal: &str) -> Result<Self, String> {
+        if let Some(s) = literal.strip_prefix('"') {
+            Ok(Self::Str(s.trim_end_matches('"').to_owned()))
+        } else if let Some(id) = literal.strip_prefix('@') {
+            Ok(Self::Ref(id.to_owned()))
+        } else if literal == "true" || literal == "false" {
+            Ok(Self::Bool(literal == "true"))
+        } else {
+            let digits = literal
+                .trim_end_matches(|c: char| !c.is_ascii_digit() && c != '.');
+            digits.parse().map(Self::Number).map_err(|_| {
+                format!("the mock server cannot parse the value {}", literal)
+            })
+        }
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            Self::Bool(b) => value.as_bool() == Some(*b),
+            Self::Number(n) => number(value) == Some(*n),
+            Self::Ref(id) => ref_value(value).as_deref() == Some(id),
+            Self::Str(s) => value.as_str() == Some(s),
+        }
+    }
+}
+
+/// Split a filter into tokens separated by whitespace, keeping quoted
+/// strings together.
+fn tokenize(filter: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = filter.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' {
+            return Err("the mock server does not support parentheses in \
+                        filters"
+                .to_owned());
+        } else {
+            let mut token = String::new();
+            let mut in_string = false;
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() && !in_string {
+                    break;
+                }
+                if c == '"' {
+                    in_string = !in_string;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+
+    if tokens.is_empty() {
+        Err("the filter is empty".to_owned())
+    } else {
+        Ok(tokens)
+    }
+}
+
+fn parse_tag_name(name: &str) -> Result<String, String> {
+    if crate::is_tag_name(name) {
+        Ok(name.to_owned())
+    } else {
+        Err(format!("'{}' is not a valid tag name", name))
+    }
+}
+
+/// Return true if the timestamp is within the hisRead range, which is
+/// encoded in the same way as `HisReadRange` encodes ranges in requests.
+fn is_in_range(ts: &DateTime, range: &str) -> Result<bool, String> {
+    let date_time = ts.date_time();
+    let date = date_time.date_naive();
+    let today = Utc::now().with_timezone(&date_time.timezone()).date_naive();
+
+    let parse_date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok();
+    let parse_date_time = |s: &str| {
+        let rfc3339 = s.split_whitespace().next().unwrap_or("");
+        chrono::DateTime::parse_from_rfc3339(rfc3339)
+            .map(|date_time| date_time.with_timezone(&Utc))
+            .map_err(|_| format!("invalid hisRead range {}", range))
+    };
+    let utc = date_time.with_timezone(&Utc);
+
+    let parts = range.split(',').map(|s| s.trim()).collect::<Vec<_>>();
+    match parts.as_slice() {
+        ["today"] => Ok(date == today),
+        ["yesterday"] => Ok(Some(date) == today.pred_opt()),
+        [start] => match parse_date(start) {
+            Some(start) => Ok(date == start),
+            None => Ok(utc >= parse_date_time(start)?),
+        },
+        [start, end] => match (parse_date(start), parse_date(end)) {
+            (Some(start), Some(end)) => Ok(start <= date && date <= end),
+            _ => {
+                let start = parse_date_time(start)?;
+                let end = parse_date_time(end)?;
+                Ok(start <= utc && utc < end)
+            }
+        },
+        _ => Err(format!("invalid hisRead range {}", range)),
+    }
+}
+
+fn about_grid() -> Grid {
+    records_grid(vec![json!({
+        "haystackVersion": "4.0",
+        "productName": "raystack mock server",
+        "productVersion": env!("CARGO_PKG_VERSION"),
+        "serverName": "mock",
+        "tz": "UTC",
+        "vendorName": "raystack",
+    })])
+}
+
+fn records_grid(rows: Vec<Value>) -> Grid {
+    Grid::new(rows).unwrap_or_else(|err| error_grid(&err.to_string()))
+}
+
+fn error_grid(msg: &str) -> Grid {
+    let mut grid = Grid::empty();
+    grid.add_to_meta("err", json!({"_kind": "marker"}));
+    grid.add_to_meta("dis", json!(msg));
+    grid
+}
+
+/// Parse the grid in the body of the request. Requests without a body
+/// contain an empty grid.
+fn request_grid(request: &HttpRequest) -> Result<Grid, String> {
+    let body = match request.body() {
+        Some(body) => std::str::from_utf8(body)
+            .map_err(|_| "the request body is not valid UTF-8".to_owned())?,
+        None => return Ok(Grid::empty()),
+    };
+
+    let is_zinc = request
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("text/zinc"))
+        .unwrap_or(false);
+
+    if is_zinc {
+        Grid::from_zinc(body).map_err(|err| err.to_string())
+    } else {
+        let json: Value =
+            serde_json::from_str(body).map_err(|err| err.to_string())?;
+        json.try_into()
+            .map_err(|err: crate::ParseJsonGridError| err.to_string())
+    }
+}
+
+/// Encode the grid in the format requested by the request's `Accept`
+/// header.
+fn grid_response(
+    request: &HttpRequest,
+    status: StatusCode,
+    grid: &Grid,
+) -> HttpResponse {
+    let is_zinc = request
+        .headers()
+        .get("accept")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("text/zinc"))
+        .unwrap_or(false);
+
+    let (content_type, body) = if is_zinc {
+        ("text/zinc", grid.to_zinc_string())
+    } else {
+        ("application/json", grid.to_json_string())
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", HeaderValue::from_static(content_type));
+    HttpResponse::new(status, headers, body.into_bytes())
+}
+
+fn header_response(
+    status: StatusCode,
+    name: &'static str,
+    value: &str,
+) -> HttpResponse {
+    let mut headers = HeaderMap::new();
+    let value = HeaderValue::from_str(value).expect("header value is valid");
+    headers.insert(name, value);
+    HttpResponse::new(status, headers, Vec::new())
+}
+
+fn empty_response(status: StatusCode) -> HttpResponse {
+    HttpResponse::new(status, HeaderMap::new(), Vec::new())
+}
+
+/// Split an `Authorization` header value into its scheme and parameters.
+fn split_auth_header(value: &str) -> (&str, HashMap<String, String>) {
+    let value = value.trim();
+    let (scheme, params) = match value.find(' ') {
+        Some(index) => value.split_at(index),
+        None => (value, ""),
+    };
+
+    let params = params
+        .split(',')
+        .filter_map(|param| {
+            let param = param.trim();
+            let (name, value) = param.split_at(param.find('=')?);
+            Some((name.to_owned(), value[1..].to_owned()))
+        })
+        .collect();
+    (scheme, params)
+}
+
+/// Parse a SCRAM message, such as `n=user,r=nonce`.
+fn parse_scram_data(data: &str) -> HashMap<String, String> {
+    data.split(',')
+        .filter_map(|attribute| {
+            let (name, value) = attribute.split_at(attribute.find('=')?);
+            Some((name.to_owned(), value[1..].to_owned()))
+        })
+        .collect()
+}
+
+fn hmac_sign(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac =
+        HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn encode(s: &str) -> String {
+    let config = base64::Config::new(base64::CharacterSet::Standard, false);
+    base64::encode_config(s, config)
+}
+
+fn decode(s: &str) -> Option<String> {
+    let config = base64::Config::new(base64::CharacterSet::Standard, false);
+    let bytes = base64::decode_config(s, config).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn random_token() -> String {
+    rand::thread_rng()
+        .gen::<[u8; 16]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Return the value of a Hayson-encoded Ref, without the leading `@`.
+fn ref_value(value: &Value) -> Option<String> {
+    if value["_kind"] == "ref" {
+        value["val"].as_str().map(|id| id.to_owned())
+    } else {
+        None
+    }
+}
+
+fn ref_key(id: &Ref) -> String {
+    ref_value(&id.to_hayson()).expect("a Ref is encoded as a Hayson ref")
+}
+
+fn same_ref(value: Option<&Value>, id: &Value) -> bool {
+    match (value.and_then(ref_value), ref_value(id)) {
+        (Some(value), Some(id)) => value == id,
+        _ => false,
+    }
+}
+
+/// Return the value of a JSON number, or a Hayson-encoded Number.
+fn number(value: &Value) -> Option<f64> {
+    value.as_f64().or_else(|| {
+        if value["_kind"] == "number" {
+            value["val"].as_f64()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::MockServer;
+    use crate::auth::AuthError;
+    use crate::{
+        DateTime, Error, Grid, GridFormat, HisReadRange, Marker,
+        NewSkySparkClientError, Number, Ref, SkySparkClientBuilder,
+    };
+    use chrono_tz::Tz;
+    use raystack_core::Hayson;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn id(s: &str) -> Ref {
+        Ref::new(format!("@{}", s)).unwrap()
+    }
+
+    fn server() -> MockServer {
+        let server = MockServer::new("name", "s3cr3t");
+        let marker = Marker::new().to_hayson();
+        server.add_record(json!({
+            "id": id("site1").to_hayson(),
+            "dis": "Site 1",
+            "site": marker,
+            "area": Number::new_unitless(100.0).to_hayson(),
+        }));
+        server.add_record(json!({
+            "id": id("site2").to_hayson(),
+            "dis": "Site 2",
+            "site": marker,
+        }));
+        server.add_record(json!({
+            "id": id("equip1").to_hayson(),
+            "dis": "AHU 1",
+            "equip": marker,
+            "siteRef": id("site1").to_hayson(),
+        }));
+        server.add_record(json!({
+            "id": id("point1").to_hayson(),
+            "dis": "Temp",
+            "point": marker,
+            "his": marker,
+            "equipRef": id("equip1").to_hayson(),
+            "siteRef": id("site1").to_hayson(),
+        }));
+        server
+    }
+
+    fn date_time(rfc3339: &str) -> DateTime {
+        let tz: Tz = "Australia/Sydney".parse().unwrap();
+        chrono::DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&tz)
+            .into()
+    }
+
+    fn dis(grid: &Grid) -> Vec<&str> {
+        grid.rows()
+            .iter()
+            .map(|row| row["dis"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn authenticates_with_scram() {
+        let server = server();
+        let client = server.client().await.unwrap();
+        assert_eq!(server.authentication_count(), 1);
+        assert!(!client.auth_token().await.is_empty());
+
+        let grid = client.about().await.unwrap();
+        assert_eq!(grid.rows()[0]["productName"], "raystack mock server");
+    }
+
+    #[tokio::test]
+    async fn wrong_password_fails() {
+        let server = server();
+        let result =
+            SkySparkClientBuilder::new(server.project_api_url(), "name", "bad")
+                .transport(Arc::new(server.clone()))
+                .build()
+                .await;
+        assert!(matches!(
+            result,
+            Err(NewSkySparkClientError::Auth(AuthError::Internal(_)))
+        ));
+        assert_eq!(server.authentication_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn expired_auth_token_is_replaced() {
+        let server = server();
+        let client = server.client().await.unwrap();
+        let auth_token = client.auth_token().await;
+
+        server.expire_auth_tokens();
+        client.about().await.unwrap();
+        assert_eq!(server.authentication_count(), 2);
+        assert_ne!(client.auth_token().await, auth_token);
+    }
+
+    #[tokio::test]
+    async fn logout() {
+        let server = server();
+        let client = server.client().await.unwrap();
+        client.logout().await.unwrap();
+        assert_eq!(client.auth_token().await, "");
+
+        // The client authenticates again when it is next used:
+        client.about().await.unwrap();
+        assert_eq!(server.authentication_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn read() {
+        let client = server().client().await.unwrap();
+
+        let grid = client.read("site", None).await.unwrap();
+        assert_eq!(dis(&grid), vec!["Site 1", "Site 2"]);
+
+        let grid = client.read("site", Some(1)).await.unwrap();
+        assert_eq!(dis(&grid), vec!["Site 1"]);
+
+        let grid = client.read("equip or point", None).await.unwrap();
+        assert_eq!(dis(&grid), vec!["AHU 1", "Temp"]);
+
+        let filter = "point and siteRef == @site1 and not equip";
+        let grid = client.read(filter, None).await.unwrap();
+        assert_eq!(dis(&grid), vec!["Temp"]);
+
+        let grid = client.read("area == 100", None).await.unwrap();
+        assert_eq!(dis(&grid), vec!["Site 1"]);
+
+        let grid = client.read("dis != \"Site 1\" and site", None);
+        assert_eq!(dis(&grid.await.unwrap()), vec!["Site 2"]);
+
+        let err = client.read("(site)", None).await.unwrap_err();
+        assert!(err.is_grid());
+    }
+
+    #[tokio::test]
+    async fn read_by_ids() {
+        let client = server().client().await.unwrap();
+        let grid = client
+            .read_by_ids(&[id("point1"), id("site2")])
+            .await
+            .unwrap();
+        assert_eq!(dis(&grid), vec!["Temp", "Site 2"]);
+    }
+
+    #[tokio::test]
+    async fn nav() {
+        let client = server().client().await.unwrap();
+
+        let grid = client.nav(None).await.unwrap();
+        assert_eq!(dis(&grid), vec!["Site 1", "Site 2"]);
+
+        let grid = client.nav(Some(&id("site1"))).await.unwrap();
+        assert_eq!(dis(&grid), vec!["AHU 1"]);
+
+        let grid = client.nav(Some(&id("equip1"))).await.unwrap();
+        assert_eq!(dis(&grid), vec!["Temp"]);
+    }
+
+    #[tokio::test]
+    async fn his_write_and_his_read() {
+        let server = server();
+        let client = server.client().await.unwrap();
+        let point_id = id("point1");
+
+        server.add_his(
+            &point_id,
+            &[(date_time("2021-01-01T00:00:00+11:00"), json!(1))],
+        );
+        let his_data = vec![
+            (
+                date_time("2021-01-02T00:00:00+11:00"),
+                Number::new_unitless(2.0),
+            ),
+            (
+                date_time("2021-01-01T12:00:00+11:00"),
+                Number::new_unitless(1.5),
+            ),
+        ];
+        client.his_write_num(&point_id, &his_data).await.unwrap();
+        assert_eq!(server.his(&point_id).len(), 3);
+
+        let range = HisReadRange::Date(
+            date_time("2021-01-01T00:00:00+11:00")
+                .date_time()
+                .date_naive()
+                .into(),
+        );
+        let grid = client.his_read(&point_id, &range).await.unwrap();
+        assert_eq!(grid.size(), 2);
+        assert_eq!(grid.meta()["id"], point_id.to_hayson());
+
+        let range = HisReadRange::DateTimeSpan {
+            start: date_time("2021-01-01T06:00:00+11:00"),
+            end: date_time("2021-01-03T00:00:00+11:00"),
+        };
+        let grid = client.his_read(&point_id, &range).await.unwrap();
+        assert_eq!(grid.size(), 2);
+        assert_eq!(grid.rows()[0]["val"]["val"], 1.5);
+    }
+
+    #[tokio::test]
+    async fn eval() {
+        let server = server();
+        let client = server.client().await.unwrap();
+
+        let grid = client.eval("readAll(equip)").await.unwrap();
+        assert_eq!(dis(&grid), vec!["AHU 1"]);
+
+        let result = Grid::new(vec![json!({"val": 42})]).unwrap();
+        server.set_eval_result("6 * 7", result.clone());
+        assert_eq!(client.eval("6 * 7").await.unwrap(), result);
+
+        let err = client.eval("now()").await.unwrap_err();
+        assert!(matches!(err, Error::Grid { .. }));
+    }
+
+    #[tokio::test]
+    async fn zinc_grid_format() {
+        let mut client = server().client().await.unwrap();
+        client.set_grid_format(GridFormat::Zinc);
+
+        let grid = client.read("equip", None).await.unwrap();
+        assert_eq!(dis(&grid), vec!["AHU 1"]);
+        let grid = client.nav(Some(&id("site1"))).await.unwrap();
+        assert_eq!(dis(&grid), vec!["AHU 1"]);
+    }
+}
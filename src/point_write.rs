@@ -0,0 +1,199 @@
+use crate::{Error, Grid, ValueExt};
+use serde_json::Value;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A priority level in a writable point's priority array, from 1 (the
+/// highest priority) to 17 (the lowest priority).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct WriteLevel(u8);
+
+impl WriteLevel {
+    /// The emergency level (level 1).
+    pub const EMERGENCY: WriteLevel = WriteLevel(1);
+    /// The manual override level (level 8). Writes to this level may
+    /// have a duration, after which the level is released.
+    pub const MANUAL_OVERRIDE: WriteLevel = WriteLevel(8);
+    /// The default level (level 17).
+    pub const DEFAULT: WriteLevel = WriteLevel(17);
+
+    /// Create a new `WriteLevel`. Returns `None` if `level` is not between
+    /// 1 and 17 (inclusive).
+    pub fn new(level: u8) -> Option<Self> {
+        if (1..=17).contains(&level) {
+            Some(WriteLevel(level))
+        } else {
+            None
+        }
+    }
+
+    /// Return the level as an integer between 1 and 17.
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+/// The priority array of a writable point, as returned by
+/// the pointWrite operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointWriteArray {
+    levels: Vec<PointWriteLevel>,
+}
+
+impl PointWriteArray {
+    /// Parse a `PointWriteArray` from the grid returned by the pointWrite
+    /// operation.
+    pub(crate) fn from_grid(grid: &Grid) -> Result<Self> {
+        let mut levels = grid
+            .rows()
+            .iter()
+            .map(PointWriteLevel::from_row)
+            .collect::<Result<Vec<_>>>()?;
+        levels.sort_by_key(|level| level.level);
+        Ok(Self { levels })
+    }
+
+    /// Return all levels in the priority array, sorted from the highest
+    /// priority (level 1) to the lowest priority (level 17).
+    pub fn levels(&self) -> &[PointWriteLevel] {
+        &self.levels
+    }
+
+    /// Return the entry for the given level.
+    pub fn level(&self, level: WriteLevel) -> Option<&PointWriteLevel> {
+        self.levels.iter().find(|entry| entry.level == level)
+    }
+
+    /// Return the highest priority level which has a value. This is the
+    /// level which currently controls the point.
+    pub fn effective(&self) -> Option<&PointWriteLevel> {
+        self.levels.iter().find(|entry| entry.val.is_some())
+    }
+}
+
+/// A single level in a writable point's priority array.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointWriteLevel {
+    level: WriteLevel,
+    level_dis: Option<String>,
+    val: Option<Value>,
+    who: Option<String>,
+}
+
+impl PointWriteLevel {
+    fn from_row(row: &Value) -> Result<Self> {
+        let level = row
+            .get("level")
+            .and_then(|level| level.as_hs_number())
+            .and_then(|level| level.as_number().map(|num| num.value()))
+            .filter(|level| level.fract() == 0.0 && *level >= 0.0)
+            .and_then(|level| WriteLevel::new(level as u8))
+            .ok_or_else(|| {
+                Error::unexpected_grid(
+                    "pointWrite grid row does not have a valid level",
+                )
+            })?;
+        let level_dis = row
+            .get("levelDis")
+            .and_then(|dis| dis.as_hs_str())
+            .map(|dis| dis.to_owned());
+        let val = row.get("val").filter(|val| !val.is_null()).cloned();
+        let who = row
+            .get("who")
+            .and_then(|who| who.as_hs_str())
+            .map(|who| who.to_owned());
+
+        Ok(Self {
+            level,
+            level_dis,
+            val,
+            who,
+        })
+    }
+
+    /// Return the priority level of this entry.
+    pub fn level(&self) -> WriteLevel {
+        self.level
+    }
+
+    /// Return the display name of this level, if the server provided one.
+    pub fn level_dis(&self) -> Option<&str> {
+        self.level_dis.as_deref()
+    }
+
+    /// Return the Hayson-encoded value at this level, or `None` if
+    /// the level is not set.
+    pub fn val(&self) -> Option<&Value> {
+        self.val.as_ref()
+    }
+
+    /// Return the name of who last wrote to this level.
+    pub fn who(&self) -> Option<&str> {
+        self.who.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PointWriteArray, WriteLevel};
+    use crate::{Grid, Number};
+    use raystack_core::Hayson;
+    use serde_json::json;
+
+    #[test]
+    fn write_level_bounds() {
+        assert!(WriteLevel::new(0).is_none());
+        assert_eq!(WriteLevel::new(1), Some(WriteLevel::EMERGENCY));
+        assert_eq!(WriteLevel::new(8), Some(WriteLevel::MANUAL_OVERRIDE));
+        assert_eq!(WriteLevel::new(17), Some(WriteLevel::DEFAULT));
+        assert!(WriteLevel::new(18).is_none());
+    }
+
+    #[test]
+    fn point_write_array_from_grid() {
+        let rows = (1..=17)
+            .rev()
+            .map(|level| {
+                let level_num = Number::new_unitless(f64::from(level));
+                match level {
+                    8 => json!({
+                        "level": level_num.to_hayson(),
+                        "levelDis": "Manual Override",
+                        "val": Number::new_unitless(21.5).to_hayson(),
+                        "who": "operator",
+                    }),
+                    17 => json!({
+                        "level": level_num.to_hayson(),
+                        "levelDis": "Default",
+                        "val": Number::new_unitless(20.0).to_hayson(),
+                    }),
+                    _ => json!({
+                        "level": level_num.to_hayson(),
+                        "levelDis": format!("Level {}", level),
+                    }),
+                }
+            })
+            .collect();
+        let grid = Grid::new(rows).unwrap();
+
+        let array = PointWriteArray::from_grid(&grid).unwrap();
+        assert_eq!(array.levels().len(), 17);
+        assert_eq!(array.levels()[0].level(), WriteLevel::EMERGENCY);
+
+        let effective = array.effective().unwrap();
+        assert_eq!(effective.level(), WriteLevel::MANUAL_OVERRIDE);
+        assert_eq!(effective.who(), Some("operator"));
+        assert_eq!(effective.level_dis(), Some("Manual Override"));
+
+        let default = array.level(WriteLevel::DEFAULT).unwrap();
+        assert!(default.val().is_some());
+        assert!(default.who().is_none());
+        assert!(array.level(WriteLevel::EMERGENCY).unwrap().val().is_none());
+    }
+
+    #[test]
+    fn point_write_array_with_bad_level_fails() {
+        let grid = Grid::new(vec![json!({"level": "one"})]).unwrap();
+        assert!(PointWriteArray::from_grid(&grid).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+//! Policies for retrying requests which failed due to transient errors,
+//! such as a SkySpark server restarting.
+
+use crate::TransportError;
+use rand::Rng;
+use reqwest::StatusCode;
+use std::time::Duration;
+
+/// A class of HTTP response statuses which indicate that a request may
+/// succeed if it is retried.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RetryableStatus {
+    /// A single HTTP status code.
+    Code(StatusCode),
+    /// Any server error status code (500 to 599).
+    ServerError,
+}
+
+impl RetryableStatus {
+    /// Return true if the given status code belongs to this class.
+    pub fn matches(&self, status: StatusCode) -> bool {
+        match self {
+            Self::Code(code) => *code == status,
+            Self::ServerError => status.is_server_error(),
+        }
+    }
+}
+
+/// Determines how a `SkySparkClient` retries requests which fail due to
+/// transient errors, such as connection errors or 502, 503 and 504
+/// responses.
+///
+/// Only operations which are safe to repeat, such as `read` and `his_read`,
+/// are retried by default. Operations which change the state of the
+/// server, such as `his_write`, `point_write_num` and `eval`, are only
+/// retried if `with_non_idempotent_retries(true)` is used.
+///
+/// The delay before each retry grows exponentially from the initial
+/// backoff, up to the maximum backoff. If jitter is enabled, each delay is
+/// a random duration between half of the delay and the full delay.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use raystack::{RetryPolicy, RetryableStatus, SkySparkClientBuilder};
+/// use std::time::Duration;
+/// use url::Url;
+///
+/// let retry_policy = RetryPolicy::new(5)
+///     .with_backoff(Duration::from_millis(200), Duration::from_secs(10))
+///     .with_retryable_statuses(vec![RetryableStatus::ServerError]);
+///
+/// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
+/// let client = SkySparkClientBuilder::new(url, "username", "p4ssw0rd")
+///     .retry_policy(retry_policy)
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    retryable_statuses: Vec<RetryableStatus>,
+    retry_connection_errors: bool,
+    retry_non_idempotent: bool,
+}
+
+impl RetryPolicy {
+    /// Create a new `RetryPolicy` which attempts each request at most
+    /// `max_attempts` times, including the first attempt. The other
+    /// settings have the same values as `RetryPolicy::default()`.
+    ///
+    /// A `max_attempts` of 0 is treated as 1.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            ..Self::default()
+        }
+    }
+
+    /// Create a new `RetryPolicy` which never retries requests.
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    /// Set the delay before the first retry, and the maximum delay before
+    /// any retry.
+    pub fn with_backoff(
+        mut self,
+        initial_backoff: Duration,
+        max_backoff: Duration,
+    ) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff.max(initial_backoff);
+        self
+    }
+
+    /// Set whether a random jitter is applied to each delay.
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the response statuses which cause a request to be retried.
+    pub fn with_retryable_statuses(
+        mut self,
+        retryable_statuses: Vec<RetryableStatus>,
+    ) -> Self {
+        self.retryable_statuses = retryable_statuses;
+        self
+    }
+
+    /// Set whether requests are retried when the connection to the server
+    /// fails, or the request times out.
+    pub fn with_connection_error_retries(
+        mut self,
+        retry_connection_errors: bool,
+    ) -> Self {
+        self.retry_connection_errors = retry_connection_errors;
+        self
+    }
+
+    /// Set whether operations which change the state of the server, such
+    /// as `his_write`, are retried. Retrying these operations may cause
+    /// them to be performed more than once, if the server received the
+    /// original request but the response was lost.
+    pub fn with_non_idempotent_retries(
+        mut self,
+        retry_non_idempotent: bool,
+    ) -> Self {
+        self.retry_non_idempotent = retry_non_idempotent;
+        self
+    }
+
+    /// Return the maximum number of times a request is attempted,
+    /// including the first attempt.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Return the delay before the first retry.
+    pub fn initial_backoff(&self) -> Duration {
+        self.initial_backoff
+    }
+
+    /// Return the maximum delay before any retry.
+    pub fn max_backoff(&self) -> Duration {
+        self.max_backoff
+    }
+
+    /// Return true if a random jitter is applied to each delay.
+    pub fn jitter(&self) -> bool {
+        self.jitter
+    }
+
+    /// Return the response statuses which cause a request to be retried.
+    pub fn retryable_statuses(&self) -> &[RetryableStatus] {
+        &self.retryable_statuses
+    }
+
+    /// Return true if requests are retried when the connection to the
+    /// server fails, or the request times out.
+    pub fn retries_connection_errors(&self) -> bool {
+        self.retry_connection_errors
+    }
+
+    /// Return true if operations which change the state of the server
+    /// are retried.
+    pub fn retries_non_idempotent(&self) -> bool {
+        self.retry_non_idempotent
+    }
+
+    /// Return true if another attempt may be made after the given number
+    /// of attempts.
+    pub(crate) fn allows_retry(
+        &self,
+        attempts: u32,
+        is_idempotent: bool,
+    ) -> bool {
+        attempts < self.max_attempts
+            && (is_idempotent || self.retry_non_idempotent)
+    }
+
+    /// Return true if a response with the given status should be retried.
+    pub(crate) fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retryable_statuses
+            .iter()
+            .any(|retryable| retryable.matches(status))
+    }
+
+    /// Return true if the given error, which occurred when sending a
+    /// request, should be retried.
+    pub(crate) fn is_retryable_error(&self, err: &TransportError) -> bool {
+        self.retry_connection_errors
+            && (err.is_connect() || err.is_timeout() || err.is_request())
+    }
+
+    /// Return the delay before the retry which follows the given number
+    /// of attempts.
+    pub(crate) fn backoff(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(31);
+        let delay = self
+            .initial_backoff
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+
+        if self.jitter && delay > Duration::from_millis(0) {
+            let half_delay = delay / 2;
+            rand::thread_rng().gen_range(half_delay..=delay)
+        } else {
+            delay
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Attempt each request at most 3 times, starting with a backoff of
+    /// 100 milliseconds, up to a maximum backoff of 5 seconds, with jitter.
+    /// Connection errors and 502, 503 and 504 responses are retried, and
+    /// operations which change the state of the server are not retried.
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+            retryable_statuses: vec![
+                RetryableStatus::Code(StatusCode::BAD_GATEWAY),
+                RetryableStatus::Code(StatusCode::SERVICE_UNAVAILABLE),
+                RetryableStatus::Code(StatusCode::GATEWAY_TIMEOUT),
+            ],
+            retry_connection_errors: true,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RetryPolicy, RetryableStatus};
+    use crate::transport::TestTransport;
+    use crate::{Error, HaystackClient, HaystackClientBuilder, HttpResponse};
+    use reqwest::header::HeaderMap;
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use url::Url;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_max() {
+        let policy = RetryPolicy::new(10)
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .with_jitter(false);
+
+        let delays = (1..=6).map(|n| policy.backoff(n)).collect::<Vec<_>>();
+        let expected = [100, 200, 400, 800, 1000, 1000]
+            .iter()
+            .map(|ms| Duration::from_millis(*ms))
+            .collect::<Vec<_>>();
+        assert_eq!(delays, expected);
+
+        // Very large numbers of attempts should not overflow:
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_with_jitter_is_within_bounds() {
+        let policy = RetryPolicy::new(10)
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1));
+
+        for _ in 0..100 {
+            let delay = policy.backoff(3);
+            assert!(delay >= Duration::from_millis(200));
+            assert!(delay <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        let policy = RetryPolicy::default();
+        assert!(policy.is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!policy.is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!policy.is_retryable_status(StatusCode::NOT_FOUND));
+
+        let policy = policy.with_retryable_statuses(vec![
+            RetryableStatus::ServerError,
+            RetryableStatus::Code(StatusCode::TOO_MANY_REQUESTS),
+        ]);
+        assert!(policy.is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(policy.is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!policy.is_retryable_status(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn non_idempotent_retries_are_opt_in() {
+        let policy = RetryPolicy::new(3);
+        assert!(policy.allows_retry(2, true));
+        assert!(!policy.allows_retry(3, true));
+        assert!(!policy.allows_retry(1, false));
+
+        let policy = policy.with_non_idempotent_retries(true);
+        assert!(policy.allows_retry(1, false));
+    }
+
+    #[test]
+    fn no_retries() {
+        assert!(!RetryPolicy::none().allows_retry(1, true));
+        assert_eq!(RetryPolicy::new(0).max_attempts(), 1);
+    }
+
+    async fn unavailable_client(transport: TestTransport) -> HaystackClient {
+        let retry_policy = RetryPolicy::new(2)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let base_url = Url::parse("http://localhost:1/haystack/").unwrap();
+        HaystackClientBuilder::new(base_url, "name", "password")
+            .auth_token("token")
+            .retry_policy(retry_policy)
+            .transport(Arc::new(transport))
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn last_retryable_response_returns_error_grid() {
+        let transport = TestTransport::new(|_| {
+            let err_grid = json!({
+                "_kind": "grid",
+                "meta": {
+                    "ver": "3.0",
+                    "err": {"_kind": "marker"},
+                    "dis": "Server is starting up",
+                },
+                "cols": [{"name": "empty"}],
+                "rows": [],
+            });
+            let status = StatusCode::SERVICE_UNAVAILABLE;
+            TestTransport::json_response(status, &err_grid)
+        });
+        let client = unavailable_client(transport).await;
+
+        let err = client.about().await.unwrap_err();
+        assert!(matches!(err, Error::Retried { attempts: 2, .. }));
+        let err_grid = err.grid().unwrap();
+        assert_eq!(err_grid.meta()["dis"], "Server is starting up");
+    }
+
+    #[tokio::test]
+    async fn last_retryable_response_without_grid_returns_status() {
+        let transport = TestTransport::new(|_| {
+            let status = StatusCode::SERVICE_UNAVAILABLE;
+            HttpResponse::new(status, HeaderMap::new(), b"Unavailable".to_vec())
+        });
+        let client = unavailable_client(transport).await;
+
+        match client.about().await.unwrap_err().last_error() {
+            Error::Http { err } => assert_eq!(
+                err.status_code(),
+                Some(StatusCode::SERVICE_UNAVAILABLE)
+            ),
+            err => panic!("expected a status error, got {:?}", err),
+        }
+    }
+}
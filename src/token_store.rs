@@ -0,0 +1,412 @@
+//! Storage for auth tokens, so that auth tokens can be reused across
+//! processes instead of authenticating with the server every time a
+//! client is created.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use url::Url;
+
+/// Loads and saves auth tokens, keyed by project API URL and username.
+///
+/// A client with a token store loads an auth token from the store before
+/// authenticating with the server, and saves each new auth token it
+/// obtains to the store. A token store which returns an error is treated
+/// as if it contained no auth tokens, so that a broken token store never
+/// prevents a client from authenticating.
+///
+/// The client calls these methods using `tokio::task::spawn_blocking`, so
+/// they may block, for example when reading and writing files.
+pub trait TokenStore: Debug + Send + Sync {
+    /// Return the saved auth token for the given project API URL and
+    /// username, if there is one.
+    fn load(
+        &self,
+        project_api_url: &Url,
+        username: &str,
+    ) -> std::io::Result<Option<String>>;
+
+    /// Save the auth token for the given project API URL and username,
+    /// replacing any previously-saved auth token.
+    fn save(
+        &self,
+        project_api_url: &Url,
+        username: &str,
+        auth_token: &str,
+    ) -> std::io::Result<()>;
+
+    /// Remove the saved auth token for the given project API URL and
+    /// username, if there is one.
+    fn remove(
+        &self,
+        project_api_url: &Url,
+        username: &str,
+    ) -> std::io::Result<()>;
+}
+
+/// A `TokenStore` which keeps auth tokens in memory. The auth tokens are
+/// lost when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    auth_tokens: Mutex<HashMap<(String, String), String>>,
+}
+
+impl MemoryTokenStore {
+    /// Create a new, empty `MemoryTokenStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TokenStore for MemoryTokenStore {
+    fn load(
+        &self,
+        project_api_url: &Url,
+        username: &str,
+    ) -> std::io::Result<Option<String>> {
+        let auth_tokens = self.auth_tokens.lock().expect("lock not poisoned");
+        let key = (project_api_url.to_string(), username.to_owned());
+        Ok(auth_tokens.get(&key).cloned())
+    }
+
+    fn save(
+        &self,
+        project_api_url: &Url,
+        username: &str,
+        auth_token: &str,
+    ) -> std::io::Result<()> {
+        let mut auth_tokens =
+            self.auth_tokens.lock().expect("lock not poisoned");
+        let key = (project_api_url.to_string(), username.to_owned());
+        auth_tokens.insert(key, auth_token.to_owned());
+        Ok(())
+    }
+
+    fn remove(
+        &self,
+        project_api_url: &Url,
+        username: &str,
+    ) -> std::io::Result<()> {
+        let mut auth_tokens =
+            self.auth_tokens.lock().expect("lock not poisoned");
+        let key = (project_api_url.to_string(), username.to_owned());
+        auth_tokens.remove(&key);
+        Ok(())
+    }
+}
+
+/// A `TokenStore` which keeps auth tokens in a JSON file. On Unix
+/// platforms, the file can only be read and written by its owner.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use raystack::{FileTokenStore, SkySparkClientBuilder};
+/// use std::sync::Arc;
+/// use url::Url;
+///
+/// let token_store = FileTokenStore::new("/var/lib/my-job/auth_tokens.json");
+/// let url = Url::parse("https://skyspark.company.com/api/bigProject/").unwrap();
+/// let client = SkySparkClientBuilder::new(url, "username", "p4ssw0rd")
+///     .token_store(Arc::new(token_store))
+///     .build()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FileTokenStore {
+    path: PathBuf,
+    /// Prevents concurrent saves and removals through this `FileTokenStore`
+    /// from overwriting each other's changes. Other `FileTokenStore`s and
+    /// other processes using the same file are not prevented from doing so,
+    /// although the file is still never partially written.
+    lock: Mutex<()>,
+}
+
+impl FileTokenStore {
+    /// Create a new `FileTokenStore` which uses the file at the given path.
+    /// The file is created when the first auth token is saved.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Return the path of the file containing the auth tokens.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the file, which contains a JSON object mapping each project
+    /// API URL to an object mapping each username to its auth token.
+    fn read_auth_tokens(&self) -> std::io::Result<Map<String, Value>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Map::new())
+            }
+            Err(err) => return Err(err),
+        };
+
+        match serde_json::from_str(&contents)? {
+            Value::Object(auth_tokens) => Ok(auth_tokens),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "token store file does not contain a JSON object",
+            )),
+        }
+    }
+
+    /// Replace the file with the given auth tokens. The contents are first
+    /// written to a temporary file, so the file is never partially written.
+    /// Each write uses a different temporary file, so concurrent writes
+    /// never write to the same temporary file.
+    fn write_auth_tokens(
+        &self,
+        auth_tokens: &Map<String, Value>,
+    ) -> std::io::Result<()> {
+        static WRITE_COUNT: AtomicU64 = AtomicU64::new(0);
+        let write_number = WRITE_COUNT.fetch_add(1, Ordering::Relaxed);
+
+        let mut temp_file_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_owned())
+            .unwrap_or_default();
+        temp_file_name.push(format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            write_number
+        ));
+        let temp_path = self.path.with_file_name(temp_file_name);
+
+        let mut file = create_private_file(&temp_path)?;
+        file.write_all(
+            Value::Object(auth_tokens.clone()).to_string().as_bytes(),
+        )?;
+        file.sync_all()?;
+        drop(file);
+
+        if let Err(err) = std::fs::rename(&temp_path, &self.path) {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+impl TokenStore for FileTokenStore {
+    fn load(
+        &self,
+        project_api_url: &Url,
+        username: &str,
+    ) -> std::io::Result<Option<String>> {
+        let auth_tokens = self.read_auth_tokens()?;
+        let auth_token = auth_tokens
+            .get(project_api_url.as_str())
+            .and_then(|usernames| usernames.get(username))
+            .and_then(|auth_token| auth_token.as_str())
+            .map(|auth_token| auth_token.to_owned());
+        Ok(auth_token)
+    }
+
+    fn save(
+        &self,
+        project_api_url: &Url,
+        username: &str,
+        auth_token: &str,
+    ) -> std::io::Result<()> {
+        let _guard = self.lock.lock().expect("lock not poisoned");
+
+        let mut auth_tokens = self.read_auth_tokens()?;
+        let usernames = auth_tokens
+            .entry(project_api_url.as_str())
+            .or_insert_with(|| Value::Object(Map::new()));
+        if !usernames.is_object() {
+            *usernames = Value::Object(Map::new());
+        }
+        usernames
+            .as_object_mut()
+            .expect("usernames is a JSON object")
+            .insert(username.to_owned(), Value::String(auth_token.to_owned()));
+
+        self.write_auth_tokens(&auth_tokens)
+    }
+
+    fn remove(
+        &self,
+        project_api_url: &Url,
+        username: &str,
+    ) -> std::io::Result<()> {
+        let _guard = self.lock.lock().expect("lock not poisoned");
+
+        let mut auth_tokens = self.read_auth_tokens()?;
+        let url_str = project_api_url.as_str();
+        let removed = auth_tokens
+            .get_mut(url_str)
+            .and_then(|usernames| usernames.as_object_mut())
+            .and_then(|usernames| usernames.remove(username));
+
+        if removed.is_some() {
+            let has_no_usernames = matches!(
+                auth_tokens.get(url_str),
+                Some(Value::Object(usernames)) if usernames.is_empty()
+            );
+            if has_no_usernames {
+                auth_tokens.remove(url_str);
+            }
+            self.write_auth_tokens(&auth_tokens)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileTokenStore, MemoryTokenStore, TokenStore};
+    use url::Url;
+
+    fn url(project_name: &str) -> Url {
+        let url_str = format!("http://localhost:1/api/{}/", project_name);
+        Url::parse(&url_str).unwrap()
+    }
+
+    fn assert_store_works(store: &dyn TokenStore) {
+        assert_eq!(store.load(&url("proj"), "name").unwrap(), None);
+
+        store.save(&url("proj"), "name", "token1").unwrap();
+        store.save(&url("proj"), "other", "token2").unwrap();
+        store.save(&url("proj2"), "name", "token3").unwrap();
+        assert_eq!(
+            store.load(&url("proj"), "name").unwrap().as_deref(),
+            Some("token1")
+        );
+        assert_eq!(
+            store.load(&url("proj"), "other").unwrap().as_deref(),
+            Some("token2")
+        );
+        assert_eq!(
+            store.load(&url("proj2"), "name").unwrap().as_deref(),
+            Some("token3")
+        );
+
+        store.save(&url("proj"), "name", "token4").unwrap();
+        assert_eq!(
+            store.load(&url("proj"), "name").unwrap().as_deref(),
+            Some("token4")
+        );
+
+        store.remove(&url("proj2"), "name").unwrap();
+        assert_eq!(store.load(&url("proj2"), "name").unwrap(), None);
+        assert!(store.load(&url("proj"), "name").unwrap().is_some());
+
+        // Removing an auth token which does not exist does nothing:
+        store.remove(&url("proj2"), "name").unwrap();
+    }
+
+    #[test]
+    fn memory_token_store() {
+        assert_store_works(&MemoryTokenStore::new());
+    }
+
+    #[test]
+    fn file_token_store() {
+        let path = std::env::temp_dir().join(format!(
+            "raystack_file_token_store_{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let store = FileTokenStore::new(&path);
+        assert_store_works(&store);
+
+        // A new store using the same file sees the saved auth tokens:
+        let store2 = FileTokenStore::new(&path);
+        assert_eq!(
+            store2.load(&url("proj"), "name").unwrap().as_deref(),
+            Some("token4")
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_token_stores_sharing_a_file_can_save_concurrently() {
+        let path = std::env::temp_dir().join(format!(
+            "raystack_shared_token_store_{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let threads = (0..4)
+            .map(|thread_number| {
+                let store = FileTokenStore::new(&path);
+                std::thread::spawn(move || {
+                    let username = format!("name{}", thread_number);
+                    for _ in 0..20 {
+                        store.save(&url("proj"), &username, "token").unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Some saves may be overwritten by another store, but the file is
+        // always complete:
+        let store = FileTokenStore::new(&path);
+        assert!(store.load(&url("proj"), "name0").is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_token_store_with_invalid_file_fails() {
+        let path = std::env::temp_dir().join(format!(
+            "raystack_invalid_token_store_{}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, "[1, 2, 3]").unwrap();
+
+        let store = FileTokenStore::new(&path);
+        assert!(store.load(&url("proj"), "name").is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,375 @@
+//! Functions for reading and writing records in the Trio format
+//! (see <https://project-haystack.org/doc/Trio>).
+
+use crate::zinc::{parse_grid, parse_value, value_to_zinc};
+use crate::{is_tag_name, Grid, ValueExt};
+use raystack_core::{Hayson, Marker};
+use serde_json::map::Map;
+use serde_json::Value;
+use std::convert::TryInto;
+use thiserror::Error;
+
+type Result<T> = std::result::Result<T, ParseTrioError>;
+
+/// The tags which are written before all other tags in each Trio record.
+const LEADING_TAGS: [&str; 2] = ["id", "dis"];
+
+/// Return a string containing the grid's rows encoded as Trio records.
+pub(crate) fn to_trio_string(grid: &Grid) -> String {
+    grid.rows()
+        .iter()
+        .map(record_to_trio)
+        .collect::<Vec<_>>()
+        .join("---\n")
+}
+
+/// Parse a grid from a string containing Trio records. Each record becomes
+/// a row in the grid.
+pub(crate) fn parse_trio(trio: &str) -> Result<Grid> {
+    let lines = trio.lines().collect::<Vec<_>>();
+    let mut rows = Vec::new();
+    let mut row = Map::new();
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+        let line_num = index + 1;
+        index += 1;
+
+        if line.starts_with("---") {
+            if !row.is_empty() {
+                rows.push(Value::Object(std::mem::take(&mut row)));
+            }
+            continue;
+        }
+
+        if line.trim().is_empty() || line.starts_with("//") {
+            continue;
+        }
+
+        if line.starts_with(char::is_whitespace) {
+            return trio_error(line_num, "unexpected indented line");
+        }
+
+        let (name, text) = match line.find(':') {
+            Some(colon_index) => {
+                (&line[..colon_index], Some(line[colon_index + 1..].trim()))
+            }
+            None => (line.trim_end(), None),
+        };
+
+        if !is_tag_name(name) {
+            return trio_error(
+                line_num,
+                &format!("'{}' is not a valid tag name", name),
+            );
+        }
+
+        let value = match text {
+            None => Marker::new().to_hayson(),
+            Some("") => {
+                let (string, end_index) = read_indented_lines(&lines, index);
+                index = end_index;
+                Value::String(string)
+            }
+            Some("Zinc:") => {
+                let (zinc, end_index) = read_indented_lines(&lines, index);
+                index = end_index;
+                let grid = parse_grid(&zinc).or_else(|err| {
+                    trio_error(line_num, &format!("invalid Zinc grid, {}", err))
+                })?;
+                grid.into_json()
+            }
+            Some(text) => parse_trio_value(text).or_else(|msg| {
+                trio_error(
+                    line_num,
+                    &format!("invalid value for tag '{}', {}", name, msg),
+                )
+            })?,
+        };
+
+        if row.contains_key(name) {
+            return trio_error(
+                line_num,
+                &format!("duplicate tag '{}' in record", name),
+            );
+        }
+        row.insert(name.to_owned(), value);
+    }
+
+    if !row.is_empty() {
+        rows.push(Value::Object(row));
+    }
+
+    Grid::new(rows).map_err(|err| ParseTrioError::new(err.msg))
+}
+
+/// Read the indented lines starting at the given index, and return them
+/// joined by newlines with their indentation removed, along with the index
+/// of the first line after the indented lines.
+fn read_indented_lines(lines: &[&str], start_index: usize) -> (String, usize) {
+    let mut end_index = start_index;
+    let mut indented_lines = Vec::new();
+
+    for (index, line) in lines.iter().enumerate().skip(start_index) {
+        if line.is_empty() {
+            indented_lines.push("");
+        } else if line.starts_with(char::is_whitespace) {
+            let unindented = line
+                .strip_prefix("  ")
+                .or_else(|| line.strip_prefix('\t'))
+                .unwrap_or_else(|| line.trim_start());
+            indented_lines.push(unindented);
+            end_index = index + 1;
+        } else {
+            break;
+        }
+    }
+
+    // Empty lines after the last indented line are not part of the value:
+    indented_lines.truncate(end_index.saturating_sub(start_index));
+    (indented_lines.join("\n"), end_index)
+}
+
+/// Parse a single-line Trio value. Trio values use the Zinc encoding,
+/// except that strings may be unquoted, and booleans may be written as
+/// `true` or `false`.
+fn parse_trio_value(text: &str) -> std::result::Result<Value, String> {
+    match text {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => match parse_value(text) {
+            Ok(value) => Ok(value),
+            // These characters always begin a Zinc value, so the value is
+            // invalid Zinc rather than an unquoted string:
+            Err(err) if text.starts_with(|c| "\"`@^[{<".contains(c)) => {
+                Err(err.to_string())
+            }
+            Err(_) => Ok(Value::String(text.to_owned())),
+        },
+    }
+}
+
+fn record_to_trio(row: &Value) -> String {
+    let row = row.as_object().expect("row is a JSON object");
+    let mut names = row.keys().collect::<Vec<_>>();
+    names.sort_by_key(|name| {
+        let position = LEADING_TAGS.iter().position(|tag| tag == name);
+        (position.unwrap_or(LEADING_TAGS.len()), *name)
+    });
+
+    let mut trio = String::new();
+    for name in names {
+        let value = &row[name];
+        if value.is_null() {
+            continue;
+        }
+
+        trio.push_str(name);
+        if value.is_hs_marker() {
+            trio.push('\n');
+        } else if let Some(s) = value.as_str().filter(|s| s.contains('\n')) {
+            trio.push_str(":\n");
+            push_indented_lines(&mut trio, s);
+        } else if value["_kind"] == "grid" {
+            let grid: std::result::Result<Grid, _> = value.clone().try_into();
+            match grid {
+                Ok(grid) => {
+                    trio.push_str(": Zinc:\n");
+                    let zinc = grid.to_zinc_string();
+                    push_indented_lines(&mut trio, zinc.trim_end());
+                }
+                Err(_) => {
+                    trio.push_str(": ");
+                    trio.push_str(&value_to_zinc(value));
+                    trio.push('\n');
+                }
+            }
+        } else {
+            trio.push_str(": ");
+            match value.as_str() {
+                Some(s) => trio.push_str(&str_to_trio(s)),
+                None => trio.push_str(&value_to_zinc(value)),
+            }
+            trio.push('\n');
+        }
+    }
+    trio
+}
+
+fn push_indented_lines(trio: &mut String, s: &str) {
+    for line in s.split('\n') {
+        trio.push_str("  ");
+        trio.push_str(line);
+        trio.push('\n');
+    }
+}
+
+/// Strings are written without quotes when they can be read back
+/// unambiguously.
+fn str_to_trio(s: &str) -> String {
+    let is_safe = s.starts_with(char::is_alphabetic)
+        && !s.ends_with(' ')
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || " _-./()'&,#".contains(c))
+        && s != "true"
+        && s != "false"
+        && parse_value(s).is_err();
+
+    if is_safe {
+        s.to_owned()
+    } else {
+        value_to_zinc(&Value::String(s.to_owned()))
+    }
+}
+
+fn trio_error<T>(line_num: usize, msg: &str) -> Result<T> {
+    Err(ParseTrioError::new(format!("line {}: {}", line_num, msg)))
+}
+
+/// Error denoting that a string could not be parsed as Trio records.
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("{msg}")]
+pub struct ParseTrioError {
+    msg: String,
+}
+
+impl ParseTrioError {
+    fn new(msg: String) -> Self {
+        ParseTrioError { msg }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_trio;
+    use crate::{Grid, Marker, Number, Ref};
+    use raystack_core::Hayson;
+    use serde_json::json;
+    use std::convert::TryInto;
+
+    const SITE_TRIO: &str = "// Site templates
+id: @site1
+dis: Main Street Office
+site
+area: 1200ft²
+geoCity: \"Springfield\"
+---
+id: @equip1 \"AHU 1\"
+dis: \"true\"
+equip
+ahu
+siteRef: @site1
+active: true
+notes:
+  First line
+    indented line
+
+  last line
+---
+";
+
+    #[test]
+    fn parse_records() {
+        let grid = parse_trio(SITE_TRIO).unwrap();
+        assert_eq!(grid.size(), 2);
+
+        let site = &grid.rows()[0];
+        assert_eq!(
+            site["id"],
+            Ref::new("@site1".to_owned()).unwrap().to_hayson()
+        );
+        assert_eq!(site["dis"], "Main Street Office");
+        assert_eq!(site["site"], Marker::new().to_hayson());
+        assert_eq!(
+            site["area"],
+            Number::new(1200.0, Some("ft²".to_owned())).to_hayson()
+        );
+        assert_eq!(site["geoCity"], "Springfield");
+
+        let equip = &grid.rows()[1];
+        assert_eq!(equip["id"]["dis"], "AHU 1");
+        assert_eq!(equip["dis"], "true");
+        assert_eq!(equip["active"], true);
+        assert_eq!(equip["notes"], "First line\n  indented line\n\nlast line");
+        assert!(equip.get("site").is_none());
+    }
+
+    #[test]
+    fn parse_nested_zinc_grid() {
+        let trio = "name: Test\ndata: Zinc:\n  ver:\"3.0\"\n  a,b\n  1,\"x\"\n";
+        let grid = parse_trio(trio).unwrap();
+        let nested: Grid = grid.rows()[0]["data"].clone().try_into().unwrap();
+        assert_eq!(nested.col_name_strs(), vec!["a", "b"]);
+        assert_eq!(nested.rows()[0]["b"], "x");
+    }
+
+    #[test]
+    fn parse_invalid_trio_fails() {
+        assert!(parse_trio("  indented: 1\n").is_err());
+        assert!(parse_trio("Bad: 1\n").is_err());
+        assert!(parse_trio("a: 1\na: 2\n").is_err());
+        assert!(parse_trio("a: [1, 2\n").is_err());
+
+        let err = parse_trio("a: 1\n---\nb: \"x\nc\n").unwrap_err();
+        assert!(err.to_string().starts_with("line 3"));
+    }
+
+    #[test]
+    fn parse_empty_trio() {
+        assert!(parse_trio("").unwrap().is_empty());
+        assert!(parse_trio("// Nothing here\n---\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn write_records() {
+        let rows = vec![
+            json!({
+                "site": Marker::new().to_hayson(),
+                "id": Ref::new("@site1".to_owned()).unwrap().to_hayson(),
+                "dis": "Main Street Office",
+                "area": Number::new(1200.0, Some("ft²".to_owned())).to_hayson(),
+            }),
+            json!({
+                "dis": "T",
+                "notes": "Line 1\nLine 2",
+                "count": 3,
+            }),
+        ];
+        let grid = Grid::new(rows).unwrap();
+
+        assert_eq!(
+            grid.to_trio_string(),
+            "id: @site1\n\
+             dis: Main Street Office\n\
+             area: 1200ft²\n\
+             site\n\
+             ---\n\
+             dis: \"T\"\n\
+             count: 3\n\
+             notes:\n  Line 1\n  Line 2\n"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let nested = Grid::new(vec![json!({"x": "y"})]).unwrap();
+        let rows = vec![
+            json!({
+                "id": Ref::new("@a".to_owned()).unwrap().to_hayson(),
+                "dis": "Room 1 (North)",
+                "bool": false,
+                "str": "true",
+                "quoted": "  leading spaces",
+                "multi": "a\n\n  b\n",
+                "list": [Number::new_unitless(1.0).to_hayson(), "two"],
+                "dict": {"site": Marker::new().to_hayson()},
+                "grid": serde_json::from_str::<serde_json::Value>(&nested.to_json_string()).unwrap(),
+            }),
+            json!({"equip": Marker::new().to_hayson()}),
+        ];
+        let grid = Grid::new(rows).unwrap();
+        let parsed = parse_trio(&grid.to_trio_string()).unwrap();
+        assert_eq!(parsed, grid);
+    }
+}
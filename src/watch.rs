@@ -0,0 +1,511 @@
+use crate::{Error, Grid, HaystackClient, Number, Ref, ValueExt};
+use futures::stream::{self, Stream};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A handle to a watch which is open on the server. A `Watch` keeps
+/// track of the watch id, the lease and the ids of the records being
+/// watched.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use raystack::{Ref, SkySparkClient, Watch};
+/// use url::Url;
+///
+/// let url = Url::parse("https://www.example.com/api/projName/").unwrap();
+/// let client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
+/// let client = client.as_haystack_client();
+/// let ids = vec![Ref::new("@p:projName:r:2a3d29f9-c79fdd5e".to_owned()).unwrap()];
+///
+/// let (watch, current_records) = Watch::open(client, "My Watch", &ids, None).await.unwrap();
+/// // Later, get the records which have changed since the watch was opened:
+/// let changed_records = watch.poll(client).await.unwrap();
+/// watch.close(client).await.unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watch {
+    id: String,
+    lease: Option<Number>,
+    ids: Vec<Ref>,
+}
+
+impl Watch {
+    /// Open a new watch on the server, watching the records with the
+    /// given ids. Returns the new watch, and a grid containing the
+    /// current state of the watched records.
+    pub async fn open(
+        client: &HaystackClient,
+        watch_dis: &str,
+        ids: &[Ref],
+        lease: Option<&Number>,
+    ) -> Result<(Self, Grid)> {
+        let grid = client.watch_sub(watch_dis, ids, lease).await?;
+        let watch = Self::from_sub_grid(&grid, ids.to_vec())?;
+        Ok((watch, grid))
+    }
+
+    /// Create a `Watch` from the grid returned by a watchSub operation.
+    fn from_sub_grid(grid: &Grid, ids: Vec<Ref>) -> Result<Self> {
+        let id = grid
+            .meta()
+            .get("watchId")
+            .and_then(|id| id.as_hs_str())
+            .ok_or_else(|| {
+                Error::unexpected_grid("watchSub grid has no watchId in meta")
+            })?
+            .to_owned();
+        let lease = grid.meta().get("lease").and_then(|l| l.as_hs_number());
+
+        Ok(Self { id, lease, ids })
+    }
+
+    /// Return the id of this watch.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Return the lease of this watch, as reported by the server.
+    pub fn lease(&self) -> Option<&Number> {
+        self.lease.as_ref()
+    }
+
+    /// Return the ids of the records being watched.
+    pub fn ids(&self) -> &[Ref] {
+        &self.ids
+    }
+
+    /// Add the records with the given ids to this watch. Returns a grid
+    /// containing the current state of the added records.
+    pub async fn add(
+        &mut self,
+        client: &HaystackClient,
+        ids: &[Ref],
+    ) -> Result<Grid> {
+        let grid = client.watch_sub_existing(&self.id, ids, None).await?;
+        if let Some(lease) =
+            grid.meta().get("lease").and_then(|l| l.as_hs_number())
+        {
+            self.lease = Some(lease);
+        }
+        for id in ids {
+            if !self.ids.contains(id) {
+                self.ids.push(id.clone());
+            }
+        }
+        Ok(grid)
+    }
+
+    /// Remove the records with the given ids from this watch.
+    pub async fn remove(
+        &mut self,
+        client: &HaystackClient,
+        ids: &[Ref],
+    ) -> Result<Grid> {
+        let grid = client.watch_unsub(&self.id, ids).await?;
+        self.ids.retain(|id| !ids.contains(id));
+        Ok(grid)
+    }
+
+    /// Return a grid containing only the records which have changed since
+    /// the last poll.
+    pub async fn poll(&self, client: &HaystackClient) -> Result<Grid> {
+        client.watch_poll(&self.id, false).await
+    }
+
+    /// Return a grid containing the current state of all records in
+    /// this watch.
+    pub async fn refresh(&self, client: &HaystackClient) -> Result<Grid> {
+        client.watch_poll(&self.id, true).await
+    }
+
+    /// Close this watch on the server.
+    pub async fn close(self, client: &HaystackClient) -> Result<Grid> {
+        client.watch_close(&self.id).await
+    }
+}
+
+/// A `Stream` of records which have changed on the server, for a set of
+/// record ids. The first items in the stream are the current state of
+/// every watched record, followed by each record which changes.
+///
+/// The stream manages the underlying watch: it subscribes when first polled,
+/// polls the server at the given interval, and re-subscribes if the
+/// watch is lost (for example, if the lease expires or the server
+/// returns an error grid for the watch). When the stream is dropped, the
+/// watch is closed in a background task on the current Tokio runtime.
+///
+/// Errors which do not indicate a lost watch, such as HTTP errors, are
+/// yielded as items of the stream, and polling continues afterwards.
+///
+/// # Example
+/// ```rust,no_run
+/// # async fn run() {
+/// use futures::StreamExt;
+/// use raystack::{Ref, SkySparkClient, WatchStream};
+/// use std::time::Duration;
+/// use url::Url;
+///
+/// let url = Url::parse("https://www.example.com/api/projName/").unwrap();
+/// let client = SkySparkClient::new(url, "username", "p4ssw0rd").await.unwrap();
+/// let ids = vec![Ref::new("@p:projName:r:2a3d29f9-c79fdd5e".to_owned()).unwrap()];
+///
+/// let mut stream = WatchStream::new(client, "My Watch", ids, Duration::from_secs(5), None);
+/// while let Some(record) = stream.next().await {
+///     println!("{:?}", record);
+/// }
+/// # }
+/// ```
+pub struct WatchStream {
+    inner: Pin<Box<dyn Stream<Item = Result<Value>> + Send>>,
+}
+
+impl WatchStream {
+    /// Create a new `WatchStream` which watches the records with the
+    /// given ids. The server is polled every `poll_interval`. If the server
+    /// reports a lease which is shorter than twice the poll interval, the
+    /// server is polled more often so the lease does not expire.
+    /// `lease` must be a Number with a duration unit, such as `min` or `s`.
+    pub fn new<C: Into<HaystackClient>>(
+        client: C,
+        watch_dis: &str,
+        ids: Vec<Ref>,
+        poll_interval: Duration,
+        lease: Option<Number>,
+    ) -> Self {
+        let state = WatchStreamState {
+            client: Some(client.into()),
+            watch: None,
+            watch_dis: watch_dis.to_owned(),
+            ids,
+            lease,
+            poll_interval,
+            pending: VecDeque::new(),
+            should_wait: false,
+        };
+
+        let inner = stream::unfold(state, |mut state| async move {
+            let item = state.next_record().await;
+            Some((item, state))
+        });
+
+        Self {
+            inner: Box::pin(inner),
+        }
+    }
+}
+
+impl Stream for WatchStream {
+    type Item = Result<Value>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+impl std::fmt::Debug for WatchStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchStream").finish()
+    }
+}
+
+struct WatchStreamState {
+    /// Only `None` while the state is being dropped.
+    client: Option<HaystackClient>,
+    /// `None` if there is currently no watch open on the server.
+    watch: Option<Watch>,
+    watch_dis: String,
+    ids: Vec<Ref>,
+    lease: Option<Number>,
+    poll_interval: Duration,
+    /// Records which have been received but not yet yielded.
+    pending: VecDeque<Value>,
+    /// True if the next request to the server should wait for the
+    /// poll interval.
+    should_wait: bool,
+}
+
+impl WatchStreamState {
+    fn client(&self) -> &HaystackClient {
+        self.client
+            .as_ref()
+            .expect("client is only missing while being dropped")
+    }
+
+    /// Return the time to wait between polls, taking the watch lease into
+    /// account.
+    fn wait_duration(&self) -> Duration {
+        let lease_duration = self
+            .watch
+            .as_ref()
+            .and_then(|watch| watch.lease())
+            .and_then(lease_to_duration);
+
+        match lease_duration {
+            Some(lease_duration) => self.poll_interval.min(lease_duration / 2),
+            None => self.poll_interval,
+        }
+    }
+
+    async fn next_record(&mut self) -> Result<Value> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Ok(record);
+            }
+
+            if self.should_wait {
+                tokio::time::sleep(self.wait_duration()).await;
+            }
+            self.should_wait = true;
+
+            // The watch stays in `self.watch` while it is polled, so it is
+            // still closed if the stream is dropped during the poll.
+            match &self.watch {
+                None => {
+                    let (watch, grid) = Watch::open(
+                        self.client(),
+                        &self.watch_dis,
+                        &self.ids,
+                        self.lease.as_ref(),
+                    )
+                    .await?;
+                    self.watch = Some(watch);
+                    self.pending.extend(grid.to_rows());
+                }
+                Some(watch) => match watch.poll(self.client()).await {
+                    Ok(grid) => self.pending.extend(grid.to_rows()),
+                    Err(err) if is_lost_watch_error(&err) => {
+                        // Re-subscribe straight away, without waiting.
+                        self.watch = None;
+                        self.should_wait = false;
+                    }
+                    Err(err) => return Err(err),
+                },
+            }
+        }
+    }
+}
+
+impl Drop for WatchStreamState {
+    fn drop(&mut self) {
+        if let (Some(client), Some(watch)) =
+            (self.client.take(), self.watch.take())
+        {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                handle.spawn(async move {
+                    // The watch will eventually expire on the server if
+                    // closing it fails, so the error is ignored.
+                    let _ = watch.close(&client).await;
+                });
+            }
+        }
+    }
+}
+
+/// Return true if the error shows that the watch no longer exists on the
+/// server: either an error grid for an unknown watch, or a response with
+/// the 403 status. Other errors, such as a failure to re-authenticate, do
+/// not mean the watch has been lost.
+fn is_lost_watch_error(err: &Error) -> bool {
+    match err.last_error() {
+        Error::Grid { err_grid } => is_unknown_watch_grid(err_grid),
+        Error::Http { err } => err.status_code() == Some(StatusCode::FORBIDDEN),
+        _ => false,
+    }
+}
+
+/// Return true if the error grid reports that the watch is unknown, for
+/// example because its lease expired.
+fn is_unknown_watch_grid(err_grid: &Grid) -> bool {
+    ["dis", "errType"]
+        .iter()
+        .filter_map(|tag| err_grid.meta().get(*tag))
+        .filter_map(|value| value.as_str())
+        .map(|msg| msg.to_lowercase())
+        .any(|msg| {
+            msg.contains("watch")
+                && (msg.contains("unknown")
+                    || msg.contains("not found")
+                    || msg.contains("expired"))
+        })
+}
+
+/// Convert a lease Number (with a duration unit) into a `Duration`.
+fn lease_to_duration(lease: &Number) -> Option<Duration> {
+    let value = match lease {
+        Number::Basic(num) => num.value(),
+        Number::Scientific(num) => {
+            num.significand() * 10f64.powi(num.exponent())
+        }
+    };
+
+    let secs_per_unit = match lease.unit()? {
+        "ms" => 0.001,
+        "s" | "sec" => 1.0,
+        "min" => 60.0,
+        "h" | "hr" => 3600.0,
+        "d" | "day" => 86400.0,
+        _ => return None,
+    };
+
+    let secs = value * secs_per_unit;
+    if secs.is_finite() && secs > 0.0 {
+        Some(Duration::from_secs_f64(secs))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_lost_watch_error, lease_to_duration, Watch, WatchStream};
+    use crate::transport::TestTransport;
+    use crate::{
+        Error, Grid, HaystackClientBuilder, Number, Ref, TransportError,
+    };
+    use futures::StreamExt;
+    use raystack_core::Hayson;
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use url::Url;
+
+    fn ids() -> Vec<Ref> {
+        vec![Ref::new("@abc".to_owned()).unwrap()]
+    }
+
+    #[test]
+    fn from_sub_grid_works() {
+        let mut grid =
+            Grid::new(vec![json!({"id": ids()[0].to_hayson()})]).unwrap();
+        let lease = Number::new(60.0, Some("s".to_owned()));
+        grid.add_to_meta("watchId", json!("w-1234"));
+        grid.add_to_meta("lease", lease.to_hayson());
+
+        let watch = Watch::from_sub_grid(&grid, ids()).unwrap();
+        assert_eq!(watch.id(), "w-1234");
+        assert_eq!(watch.lease(), Some(&lease));
+        assert_eq!(watch.ids(), &ids()[..]);
+    }
+
+    #[test]
+    fn from_sub_grid_without_watch_id_fails() {
+        let grid = Grid::empty();
+        assert!(Watch::from_sub_grid(&grid, ids()).is_err());
+    }
+
+    #[test]
+    fn lease_to_duration_works() {
+        let lease = Number::new(2.0, Some("min".to_owned()));
+        assert_eq!(lease_to_duration(&lease), Some(Duration::from_secs(120)));
+
+        let lease = Number::new(500.0, Some("ms".to_owned()));
+        assert_eq!(lease_to_duration(&lease), Some(Duration::from_millis(500)));
+
+        let lease = Number::new(1.0, Some("d".to_owned()));
+        assert_eq!(lease_to_duration(&lease), Some(Duration::from_secs(86400)));
+    }
+
+    #[test]
+    fn lease_to_duration_without_duration_unit_fails() {
+        assert!(lease_to_duration(&Number::new_unitless(60.0)).is_none());
+        let lease = Number::new(60.0, Some("m".to_owned()));
+        assert!(lease_to_duration(&lease).is_none());
+    }
+
+    fn err_grid(dis: &str) -> Error {
+        let mut err_grid = Grid::empty();
+        err_grid.add_to_meta("err", json!({"_kind": "marker"}));
+        err_grid.add_to_meta("dis", json!(dis));
+        Error::Grid { err_grid }
+    }
+
+    #[test]
+    fn lost_watch_errors() {
+        assert!(is_lost_watch_error(&err_grid("Unknown watch: w-1234")));
+        assert!(is_lost_watch_error(&Error::Http {
+            err: TransportError::status(StatusCode::FORBIDDEN),
+        }));
+        let retried = Error::Retried {
+            attempts: 2,
+            err: Box::new(err_grid("Watch not found")),
+        };
+        assert!(is_lost_watch_error(&retried));
+
+        assert!(!is_lost_watch_error(&err_grid("sys::EvalErr")));
+        assert!(!is_lost_watch_error(&Error::Http {
+            err: TransportError::status(StatusCode::SERVICE_UNAVAILABLE),
+        }));
+        let auth_err = crate::auth::AuthError::UnsupportedScheme {
+            scheme: "NEGOTIATE".to_owned(),
+        };
+        assert!(!is_lost_watch_error(&Error::UpdateAuthToken(auth_err)));
+    }
+
+    #[tokio::test]
+    async fn watch_is_closed_when_stream_is_dropped_during_poll() {
+        let transport = TestTransport::new(|request| {
+            let grid = match request.url().path() {
+                "/haystack/watchSub" => json!({
+                    "_kind": "grid",
+                    "meta": {"ver": "3.0", "watchId": "w-1234"},
+                    "cols": [{"name": "id"}],
+                    "rows": [{"id": ids()[0].to_hayson()}],
+                }),
+                _ => json!({
+                    "_kind": "grid",
+                    "meta": {"ver": "3.0"},
+                    "cols": [{"name": "empty"}],
+                    "rows": [],
+                }),
+            };
+            TestTransport::json_response(StatusCode::OK, &grid)
+        });
+        let transport =
+            Arc::new(transport.with_delay(Duration::from_millis(50)));
+        let base_url = Url::parse("http://localhost:1/haystack/").unwrap();
+        let client = HaystackClientBuilder::new(base_url, "name", "password")
+            .auth_token("token")
+            .transport(transport.clone())
+            .build()
+            .await
+            .unwrap();
+
+        let poll_interval = Duration::from_millis(1);
+        let mut stream =
+            WatchStream::new(client, "watch", ids(), poll_interval, None);
+        assert!(stream.next().await.unwrap().is_ok());
+
+        // Stop waiting while the poll request is in progress:
+        let next = stream.next();
+        let timeout = Duration::from_millis(20);
+        assert!(tokio::time::timeout(timeout, next).await.is_err());
+        drop(stream);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let paths = transport
+            .requests()
+            .iter()
+            .map(|request| request.url().path().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                "/haystack/watchSub",
+                "/haystack/watchPoll",
+                "/haystack/watchUnsub"
+            ]
+        );
+    }
+}
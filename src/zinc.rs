@@ -0,0 +1,1175 @@
+//! Functions for encoding and decoding grids in the Zinc format
+//! (see <https://project-haystack.org/doc/Zinc>).
+
+use crate::{skyspark_tz_string_to_tz, DateTime, Grid, ValueExt};
+use chrono::{NaiveDate, NaiveTime, SecondsFormat};
+use raystack_core::{
+    Coord, Hayson, Marker, Na, Number, Ref, RemoveMarker, Symbol, Uri, Xstr,
+};
+use serde_json::map::Map;
+use serde_json::{json, Value};
+use std::convert::TryInto;
+use thiserror::Error;
+
+const KIND: &str = "_kind";
+
+type Result<T> = std::result::Result<T, ParseZincError>;
+
+/// Return a string containing the grid encoded in the Zinc format.
+pub(crate) fn to_zinc_string(grid: &Grid) -> String {
+    let mut zinc = String::new();
+    write_grid(&mut zinc, grid.meta(), grid.cols(), grid.rows());
+    zinc
+}
+
+/// Parse a grid from a string containing a grid encoded in the
+/// Zinc format.
+pub(crate) fn parse_grid(zinc: &str) -> Result<Grid> {
+    let mut reader = ZincReader::new(zinc);
+    let grid = reader.read_grid()?;
+    reader.skip_whitespace();
+
+    if reader.peek().is_some() {
+        return reader.error("unexpected content after the end of the grid");
+    }
+
+    grid.try_into()
+        .map_err(|err: crate::ParseJsonGridError| ParseZincError::new(err.msg))
+}
+
+/// Parse a single Hayson value from a string containing a value encoded
+/// in the Zinc format.
+pub(crate) fn parse_value(zinc: &str) -> Result<Value> {
+    let mut reader = ZincReader::new(zinc);
+    let value = reader.read_value()?;
+    reader.skip_whitespace();
+
+    if reader.peek().is_some() {
+        return reader.error("unexpected content after the end of the value");
+    }
+
+    Ok(value)
+}
+
+fn write_grid(
+    zinc: &mut String,
+    meta: &Map<String, Value>,
+    cols: &[Value],
+    rows: &[Value],
+) {
+    let ver = meta.get("ver").and_then(Value::as_str).unwrap_or("3.0");
+    zinc.push_str("ver:");
+    zinc.push_str(&str_to_zinc(ver));
+    for (name, value) in meta {
+        if name != "ver" {
+            write_tag(zinc, name, value);
+        }
+    }
+    zinc.push('\n');
+
+    // A grid with no columns is written with a single column named 'empty':
+    if cols.is_empty() {
+        zinc.push_str("empty\n");
+        return;
+    }
+
+    let col_names = cols
+        .iter()
+        .map(|col| col["name"].as_str().expect("col name is a JSON string"))
+        .collect::<Vec<_>>();
+
+    for (index, col) in cols.iter().enumerate() {
+        if index > 0 {
+            zinc.push(',');
+        }
+        zinc.push_str(col_names[index]);
+        if let Some(col_meta) = col.get("meta").and_then(Value::as_object) {
+            for (name, value) in col_meta {
+                write_tag(zinc, name, value);
+            }
+        }
+    }
+    zinc.push('\n');
+
+    for row in rows {
+        for (index, col_name) in col_names.iter().enumerate() {
+            if index > 0 {
+                zinc.push(',');
+            }
+            match row.get(col_name) {
+                Some(value) if !value.is_null() => {
+                    zinc.push_str(&value_to_zinc(value))
+                }
+                // An empty line ends the grid, so a null value is written
+                // explicitly when it is the only value in the row:
+                _ if col_names.len() == 1 => zinc.push('N'),
+                _ => (),
+            }
+        }
+        zinc.push('\n');
+    }
+}
+
+/// Write a tag to the Zinc string, preceded by a space. Markers are
+/// written as just the tag name, and null values are not written at all.
+fn write_tag(zinc: &mut String, name: &str, value: &Value) {
+    if value.is_null() {
+        return;
+    }
+
+    zinc.push(' ');
+    zinc.push_str(name);
+    if !value.is_hs_marker() {
+        zinc.push(':');
+        zinc.push_str(&value_to_zinc(value));
+    }
+}
+
+/// Return a string containing the Hayson value encoded in the Zinc format.
+pub(crate) fn value_to_zinc(value: &Value) -> String {
+    match value {
+        Value::Null => "N".to_owned(),
+        Value::Bool(true) => "T".to_owned(),
+        Value::Bool(false) => "F".to_owned(),
+        Value::Number(num) => num.to_string(),
+        Value::String(s) => str_to_zinc(s),
+        Value::Array(values) => {
+            let values = values.iter().map(value_to_zinc).collect::<Vec<_>>();
+            format!("[{}]", values.join(", "))
+        }
+        Value::Object(obj) => match obj.get(KIND).and_then(Value::as_str) {
+            Some("dict") | None => dict_to_zinc(obj),
+            Some("grid") => {
+                let empty_meta = Map::new();
+                let meta = obj
+                    .get("meta")
+                    .and_then(Value::as_object)
+                    .unwrap_or(&empty_meta);
+                let cols = obj
+                    .get("cols")
+                    .and_then(Value::as_array)
+                    .map(|cols| cols.as_slice())
+                    .unwrap_or(&[]);
+                let rows = obj
+                    .get("rows")
+                    .and_then(Value::as_array)
+                    .map(|rows| rows.as_slice())
+                    .unwrap_or(&[]);
+
+                let mut zinc = "<<\n".to_owned();
+                write_grid(&mut zinc, meta, cols, rows);
+                zinc.push_str(">>");
+                zinc
+            }
+            Some(kind) => {
+                kind_to_zinc(kind, value).unwrap_or_else(|| dict_to_zinc(obj))
+            }
+        },
+    }
+}
+
+/// Return the Zinc encoding of a Hayson value with the given kind, or
+/// `None` if the kind is unknown or the value could not be decoded.
+fn kind_to_zinc(kind: &str, value: &Value) -> Option<String> {
+    let zinc = match kind {
+        "coord" => {
+            let coord = value.as_hs_coord()?;
+            format!("C({},{})", coord.lat(), coord.lng())
+        }
+        "date" => value.as_hs_date()?.naive_date().to_string(),
+        "dateTime" => {
+            let date_time = value.as_hs_date_time()?;
+            format!(
+                "{} {}",
+                date_time
+                    .date_time()
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                date_time.short_time_zone()
+            )
+        }
+        "marker" => "M".to_owned(),
+        "na" => "NA".to_owned(),
+        "number" => number_to_zinc(&value.as_hs_number()?),
+        "ref" => {
+            let hs_ref = value.as_hs_ref()?;
+            match value.get("dis").and_then(Value::as_str) {
+                Some(dis) => format!("{} {}", hs_ref, str_to_zinc(dis)),
+                None => hs_ref.to_string(),
+            }
+        }
+        "remove" => "R".to_owned(),
+        "symbol" => value.as_hs_symbol()?.to_string(),
+        "time" => value.as_hs_time()?.naive_time().to_string(),
+        "uri" => uri_to_zinc(value.as_hs_uri()?.as_ref()),
+        "xstr" => {
+            let xstr = value.as_hs_xstr()?;
+            format!("{}({})", xstr.type_name(), str_to_zinc(xstr.value()))
+        }
+        _ => return None,
+    };
+    Some(zinc)
+}
+
+fn dict_to_zinc(obj: &Map<String, Value>) -> String {
+    let mut tags = String::new();
+    for (name, value) in obj {
+        if name != KIND {
+            write_tag(&mut tags, name, value);
+        }
+    }
+    format!("{{{}}}", tags.trim_start())
+}
+
+fn number_to_zinc(number: &Number) -> String {
+    let mut zinc = match number {
+        Number::Basic(number) => {
+            let value = number.value();
+            if value.is_nan() {
+                return "NaN".to_owned();
+            } else if value.is_infinite() && value.is_sign_positive() {
+                return "INF".to_owned();
+            } else if value.is_infinite() && value.is_sign_negative() {
+                return "-INF".to_owned();
+            } else if value != 0.0
+                && (value.abs() >= 1e16 || value.abs() < 1e-4)
+            {
+                format!("{:e}", value)
+            } else {
+                value.to_string()
+            }
+        }
+        Number::Scientific(number) => {
+            format!("{}e{}", number.significand(), number.exponent())
+        }
+    };
+
+    if let Some(unit) = number.unit() {
+        zinc.push_str(unit);
+    }
+    zinc
+}
+
+fn str_to_zinc(s: &str) -> String {
+    let mut zinc = String::with_capacity(s.len() + 2);
+    zinc.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => zinc.push_str("\\\""),
+            '\\' => zinc.push_str("\\\\"),
+            '$' => zinc.push_str("\\$"),
+            '\n' => zinc.push_str("\\n"),
+            '\r' => zinc.push_str("\\r"),
+            '\t' => zinc.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                zinc.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => zinc.push(c),
+        }
+    }
+    zinc.push('"');
+    zinc
+}
+
+fn uri_to_zinc(uri: &str) -> String {
+    let mut zinc = String::with_capacity(uri.len() + 2);
+    zinc.push('`');
+    for c in uri.chars() {
+        match c {
+            '`' => zinc.push_str("\\`"),
+            c if (c as u32) < 0x20 => {
+                zinc.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => zinc.push(c),
+        }
+    }
+    zinc.push('`');
+    zinc
+}
+
+struct ZincReader {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl ZincReader {
+    fn new(zinc: &str) -> Self {
+        Self {
+            chars: zinc.chars().collect(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T> {
+        Err(ParseZincError::new(format!("line {}: {}", self.line, msg)))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if let Some(c) = c {
+            if c == '\n' {
+                self.line += 1;
+            }
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => self
+                .error(&format!("expected '{}' but found '{}'", expected, c)),
+            None => self.error(&format!(
+                "expected '{}' but found the end of the input",
+                expected
+            )),
+        }
+    }
+
+    fn read_while<F>(&mut self, f: F) -> String
+    where
+        F: Fn(char) -> bool,
+    {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if f(c) {
+                s.push(c);
+                self.next();
+            } else {
+                break;
+            }
+        }
+        s
+    }
+
+    /// Skip spaces and tabs, but not newlines.
+    fn skip_spaces(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.next();
+        }
+    }
+
+    /// Skip spaces, tabs and newlines.
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn is_line_end(&self) -> bool {
+        matches!(self.peek(), None | Some('\n') | Some('\r'))
+    }
+
+    fn is_nested_grid_end(&self) -> bool {
+        self.peek() == Some('>') && self.peek_at(1) == Some('>')
+    }
+
+    /// Consume a newline, or do nothing at the end of the input.
+    fn read_line_end(&mut self) -> Result<()> {
+        if self.peek() == Some('\r') {
+            self.next();
+        }
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.next();
+                Ok(())
+            }
+            Some(c) => {
+                self.error(&format!("expected a newline but found '{}'", c))
+            }
+        }
+    }
+
+    fn read_grid(&mut self) -> Result<Value> {
+        self.skip_spaces();
+        let ver_name = self.read_tag_name()?;
+        if ver_name != "ver" {
+            return self.error("grid must start with the 'ver' tag");
+        }
+        self.expect(':')?;
+        let ver = self.read_value()?;
+        if !ver.is_string() {
+            return self.error("grid 'ver' tag must be a string");
+        }
+
+        let mut meta = Map::new();
+        meta.insert("ver".to_owned(), ver);
+        self.skip_spaces();
+        while !self.is_line_end() {
+            self.read_tag(&mut meta)?;
+            self.skip_spaces();
+        }
+        self.read_line_end()?;
+
+        let mut cols = Vec::new();
+        loop {
+            self.skip_spaces();
+            let name = self.read_tag_name()?;
+            self.skip_spaces();
+
+            let mut col_meta = Map::new();
+            while !self.is_line_end() && self.peek() != Some(',') {
+                self.read_tag(&mut col_meta)?;
+                self.skip_spaces();
+            }
+
+            if col_meta.is_empty() {
+                cols.push(json!({ "name": name }));
+            } else {
+                cols.push(json!({ "name": name, "meta": col_meta }));
+            }
+
+            if self.peek() == Some(',') {
+                self.next();
+            } else {
+                break;
+            }
+        }
+        self.read_line_end()?;
+
+        let col_names = cols
+            .iter()
+            .map(|col| col["name"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+
+        let mut rows = Vec::new();
+        // The rows end at the end of the input, at a blank line, or at
+        // the end of a nested grid:
+        while !self.is_line_end() && !self.is_nested_grid_end() {
+            let mut row = Map::new();
+            for (index, col_name) in col_names.iter().enumerate() {
+                if index > 0 {
+                    self.expect(',')?;
+                }
+                self.skip_spaces();
+                if !self.is_line_end() && self.peek() != Some(',') {
+                    let value = self.read_value()?;
+                    if !value.is_null() {
+                        row.insert(col_name.clone(), value);
+                    }
+                    self.skip_spaces();
+                }
+            }
+            rows.push(Value::Object(row));
+            self.read_line_end()?;
+        }
+
+        // By convention, a grid with no columns is written with a single
+        // column named 'empty':
+        if rows.is_empty() && col_names == ["empty"] {
+            cols.clear();
+        }
+
+        Ok(json!({
+            KIND: "grid",
+            "meta": meta,
+            "cols": cols,
+            "rows": rows,
+        }))
+    }
+
+    fn read_tag_name(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(c) if c.is_ascii_lowercase() => {
+                Ok(self.read_while(|c| c.is_ascii_alphanumeric() || c == '_'))
+            }
+            Some(c) => {
+                self.error(&format!("expected a tag name but found '{}'", c))
+            }
+            None => {
+                self.error("expected a tag name but found the end of the input")
+            }
+        }
+    }
+
+    /// Read a tag, which is either a name on its own (a marker tag),
+    /// or a name and a value separated by a colon.
+    fn read_tag(&mut self, map: &mut Map<String, Value>) -> Result<()> {
+        let name = self.read_tag_name()?;
+        let value = if self.peek() == Some(':') {
+            self.next();
+            self.skip_spaces();
+            self.read_value()?
+        } else {
+            Marker::new().to_hayson()
+        };
+        map.insert(name, value);
+        Ok(())
+    }
+
+    fn read_value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.read_str()?)),
+            Some('`') => Ok(Uri::new(self.read_uri()?).to_hayson()),
+            Some('@') => self.read_ref(),
+            Some('^') => self.read_symbol(),
+            Some('[') => self.read_list(),
+            Some('{') => self.read_dict(),
+            Some('<') if self.peek_at(1) == Some('<') => {
+                self.read_nested_grid()
+            }
+            Some('-') if self.peek_at(1) == Some('I') => {
+                self.next();
+                match self.read_while(|c| c.is_ascii_alphanumeric()).as_ref() {
+                    "INF" => {
+                        Ok(Number::new_unitless(f64::NEG_INFINITY).to_hayson())
+                    }
+                    _ => self.error("expected -INF"),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '-' => self.read_scalar(),
+            Some(c) if c.is_ascii_alphabetic() => self.read_keyword(),
+            Some(c) => self.error(&format!("unexpected character '{}'", c)),
+            None => {
+                self.error("expected a value but found the end of the input")
+            }
+        }
+    }
+
+    fn read_keyword(&mut self) -> Result<Value> {
+        let id = self.read_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if self.peek() == Some('(') {
+            if id == "C" {
+                return self.read_coord();
+            } else if id.starts_with(|c: char| c.is_ascii_uppercase()) {
+                self.expect('(')?;
+                self.skip_spaces();
+                let value = self.read_str()?;
+                self.skip_spaces();
+                self.expect(')')?;
+                return Ok(Xstr::new(id, value).to_hayson());
+            }
+        }
+
+        match id.as_ref() {
+            "N" => Ok(Value::Null),
+            "T" => Ok(Value::Bool(true)),
+            "F" => Ok(Value::Bool(false)),
+            "M" => Ok(Marker::new().to_hayson()),
+            "R" => Ok(RemoveMarker::new().to_hayson()),
+            "NA" => Ok(Na::new().to_hayson()),
+            "NaN" => Ok(Number::new_unitless(f64::NAN).to_hayson()),
+            "INF" => Ok(Number::new_unitless(f64::INFINITY).to_hayson()),
+            _ => self.error(&format!("unexpected identifier '{}'", id)),
+        }
+    }
+
+    fn read_coord(&mut self) -> Result<Value> {
+        self.expect('(')?;
+        self.skip_spaces();
+        let lat = self.read_coord_part()?;
+        self.skip_spaces();
+        self.expect(',')?;
+        self.skip_spaces();
+        let lng = self.read_coord_part()?;
+        self.skip_spaces();
+        self.expect(')')?;
+        Ok(Coord::new(lat, lng).to_hayson())
+    }
+
+    fn read_coord_part(&mut self) -> Result<f64> {
+        let part =
+            self.read_while(|c| c.is_ascii_digit() || c == '-' || c == '.');
+        part.parse()
+            .or_else(|_| self.error(&format!("invalid coord value '{}'", part)))
+    }
+
+    fn read_str(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => s.push(self.read_escape()?),
+                Some(c) => s.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    fn read_uri(&mut self) -> Result<String> {
+        self.expect('`')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('`') => return Ok(s),
+                Some('\\') => match self.peek() {
+                    // Escaped URI reserved characters keep their backslash:
+                    Some(c) if ":/?#[]@\\&=;".contains(c) => {
+                        self.next();
+                        s.push('\\');
+                        s.push(c);
+                    }
+                    _ => s.push(self.read_escape()?),
+                },
+                Some(c) => s.push(c),
+                None => return self.error("unterminated uri"),
+            }
+        }
+    }
+
+    /// Read the remainder of an escape sequence, after the backslash.
+    fn read_escape(&mut self) -> Result<char> {
+        match self.next() {
+            Some('b') => Ok('\u{8}'),
+            Some('f') => Ok('\u{c}'),
+            Some('n') => Ok('\n'),
+            Some('r') => Ok('\r'),
+            Some('t') => Ok('\t'),
+            Some('u') => {
+                let mut hex = String::new();
+                for _ in 0..4 {
+                    match self.next() {
+                        Some(c) => hex.push(c),
+                        None => return self.error("unterminated escape"),
+                    }
+                }
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                    .map(Ok)
+                    .unwrap_or_else(|| {
+                        self.error(&format!("invalid unicode escape '{}'", hex))
+                    })
+            }
+            Some(c) if "\"\\$`".contains(c) => Ok(c),
+            Some(c) => self.error(&format!("invalid escape '\\{}'", c)),
+            None => self.error("unterminated escape"),
+        }
+    }
+
+    fn read_ref(&mut self) -> Result<Value> {
+        self.expect('@')?;
+        let id = self.read_while(is_ref_char);
+        let hs_ref = Ref::new(format!("@{}", id))
+            .or_else(|_| self.error(&format!("invalid ref '@{}'", id)))?;
+        let mut value = hs_ref.to_hayson();
+
+        if self.peek() == Some(' ') && self.peek_at(1) == Some('"') {
+            self.next();
+            let dis = self.read_str()?;
+            value
+                .as_object_mut()
+                .expect("Hayson ref is a JSON object")
+                .insert("dis".to_owned(), Value::String(dis));
+        }
+        Ok(value)
+    }
+
+    fn read_symbol(&mut self) -> Result<Value> {
+        self.expect('^')?;
+        let id = self.read_while(is_ref_char);
+        let symbol = Symbol::new(format!("^{}", id))
+            .or_else(|_| self.error(&format!("invalid symbol '^{}'", id)))?;
+        Ok(symbol.to_hayson())
+    }
+
+    fn read_list(&mut self) -> Result<Value> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some(']') {
+                self.next();
+                return Ok(Value::Array(values));
+            }
+            values.push(self.read_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.next();
+                }
+                Some(']') => (),
+                _ => return self.error("expected ',' or ']' in list"),
+            }
+        }
+    }
+
+    fn read_dict(&mut self) -> Result<Value> {
+        self.expect('{')?;
+        let mut map = Map::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some('}') {
+                self.next();
+                return Ok(Value::Object(map));
+            }
+            self.read_tag(&mut map)?;
+            self.skip_whitespace();
+            if self.peek() == Some(',') {
+                self.next();
+            }
+        }
+    }
+
+    fn read_nested_grid(&mut self) -> Result<Value> {
+        self.expect('<')?;
+        self.expect('<')?;
+        self.skip_whitespace();
+        let grid = self.read_grid()?;
+        self.skip_whitespace();
+        self.expect('>')?;
+        self.expect('>')?;
+        Ok(grid)
+    }
+
+    /// Read a number, date, time or date time.
+    fn read_scalar(&mut self) -> Result<Value> {
+        let is_digit_at = |offset| matches!(self.peek_at(offset), Some(c) if c.is_ascii_digit());
+        let is_date = (0..4).all(is_digit_at) && self.peek_at(4) == Some('-');
+        let is_time = (0..2).all(is_digit_at) && self.peek_at(2) == Some(':');
+
+        let token = self.read_while(|c| {
+            c.is_alphanumeric() || "-+:._%/$".contains(c) || !c.is_ascii()
+        });
+
+        if is_date && token.contains('T') {
+            self.read_date_time(&token)
+        } else if is_date {
+            NaiveDate::parse_from_str(&token, "%Y-%m-%d")
+                .map(|date| crate::Date::new(date).to_hayson())
+                .or_else(|_| self.error(&format!("invalid date '{}'", token)))
+        } else if is_time {
+            token
+                .parse::<NaiveTime>()
+                .map(|time| crate::Time::new(time).to_hayson())
+                .or_else(|_| self.error(&format!("invalid time '{}'", token)))
+        } else {
+            self.parse_number(&token)
+        }
+    }
+
+    fn read_date_time(&mut self, token: &str) -> Result<Value> {
+        let date_time =
+            chrono::DateTime::parse_from_rfc3339(token).or_else(|_| {
+                self.error(&format!("invalid date time '{}'", token))
+            })?;
+
+        let tz_name = if self.peek() == Some(' ')
+            && matches!(self.peek_at(1), Some(c) if c.is_ascii_alphabetic())
+        {
+            self.next();
+            self.read_while(|c| c.is_ascii_alphanumeric() || "_-+/".contains(c))
+        } else if token.ends_with('Z') {
+            "UTC".to_owned()
+        } else {
+            return self.error(&format!(
+                "date time '{}' does not have a time zone",
+                token
+            ));
+        };
+
+        let tz = skyspark_tz_string_to_tz(&tz_name).ok_or_else(|| {
+            ParseZincError::new(format!(
+                "line {}: unknown time zone '{}'",
+                self.line, tz_name
+            ))
+        })?;
+        Ok(DateTime::new(date_time.with_timezone(&tz)).to_hayson())
+    }
+
+    fn parse_number(&self, token: &str) -> Result<Value> {
+        let chars = token.chars().collect::<Vec<_>>();
+        let mut index = 0;
+        let is_digit_at = |index: usize| matches!(chars.get(index), Some(c) if c.is_ascii_digit());
+
+        if chars.first() == Some(&'-') {
+            index += 1;
+        }
+        while is_digit_at(index) || chars.get(index) == Some(&'_') {
+            index += 1;
+        }
+        if chars.get(index) == Some(&'.') && is_digit_at(index + 1) {
+            index += 1;
+            while is_digit_at(index) || chars.get(index) == Some(&'_') {
+                index += 1;
+            }
+        }
+        if let Some('e') | Some('E') = chars.get(index) {
+            let exp_digit_index = match chars.get(index + 1) {
+                Some('+') | Some('-') => index + 2,
+                _ => index + 1,
+            };
+            if is_digit_at(exp_digit_index) {
+                index = exp_digit_index;
+                while is_digit_at(index) {
+                    index += 1;
+                }
+            }
+        }
+
+        let number_str = chars[..index]
+            .iter()
+            .filter(|c| **c != '_')
+            .collect::<String>();
+        let unit = chars[index..].iter().collect::<String>();
+        let unit = if unit.is_empty() { None } else { Some(unit) };
+
+        let value: f64 = number_str
+            .parse()
+            .or_else(|_| self.error(&format!("invalid number '{}'", token)))?;
+        Ok(Number::new(value, unit).to_hayson())
+    }
+}
+
+fn is_ref_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_:-.~".contains(c)
+}
+
+/// Error denoting that a string could not be parsed as a Zinc grid.
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("{msg}")]
+pub struct ParseZincError {
+    msg: String,
+}
+
+impl ParseZincError {
+    fn new(msg: String) -> Self {
+        ParseZincError { msg }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_grid;
+    use crate::{
+        Coord, Date, DateTime, Grid, Marker, Na, Number, Ref, RemoveMarker,
+        Symbol, Time, Uri, Xstr,
+    };
+    use chrono::{NaiveDate, NaiveTime};
+    use raystack_core::Hayson;
+    use serde_json::json;
+    use std::convert::TryInto;
+
+    fn round_trip(grid: &Grid) -> Grid {
+        let zinc = grid.to_zinc_string();
+        parse_grid(&zinc).unwrap()
+    }
+
+    #[test]
+    fn parse_simple_grid() {
+        let zinc = "ver:\"3.0\" database:\"test\" dis:\"Site Energy\"\n\
+                    firstName,bday\n\
+                    \"Jack\",1973-07-23\n\
+                    \"Jill\",\n";
+        let grid = parse_grid(zinc).unwrap();
+        assert_eq!(grid.meta()["ver"], "3.0");
+        assert_eq!(grid.meta()["database"], "test");
+        assert_eq!(grid.col_name_strs(), vec!["firstName", "bday"]);
+        assert_eq!(grid.size(), 2);
+        assert_eq!(grid.rows()[0]["firstName"], "Jack");
+        assert_eq!(
+            grid.rows()[0]["bday"],
+            Date::new(NaiveDate::from_ymd_opt(1973, 7, 23).unwrap())
+                .to_hayson()
+        );
+        assert!(grid.rows()[1].get("bday").is_none());
+    }
+
+    #[test]
+    fn parse_scalars() {
+        let zinc = "ver:\"3.0\"\n\
+                    val\n\
+                    N\n\
+                    T\n\
+                    F\n\
+                    M\n\
+                    R\n\
+                    NA\n\
+                    -12.5kW\n\
+                    1_000\n\
+                    5.4e-3\n\
+                    INF\n\
+                    -INF\n\
+                    \"a \\\"quoted\\\" \\u00e9\\n \\$\"\n\
+                    `http://host/a\\`b`\n\
+                    @p:demo:r:23a1 \"Site\"\n\
+                    ^hot-water\n\
+                    2021-02-03\n\
+                    04:05:06.5\n\
+                    2021-02-03T04:05:06+11:00 Sydney\n\
+                    2021-02-03T04:05:06Z\n\
+                    C(37.55,-77.45)\n\
+                    Color(\"red\")\n";
+        let grid = parse_grid(zinc).unwrap();
+        let vals = grid.col_to_vec("val");
+
+        assert!(vals[0].is_none());
+        assert_eq!(vals[1].unwrap(), true);
+        assert_eq!(vals[2].unwrap(), false);
+        assert_eq!(vals[3].unwrap(), &Marker::new().to_hayson());
+        assert_eq!(vals[4].unwrap(), &RemoveMarker::new().to_hayson());
+        assert_eq!(vals[5].unwrap(), &Na::new().to_hayson());
+        assert_eq!(
+            vals[6].unwrap(),
+            &Number::new(-12.5, Some("kW".to_owned())).to_hayson()
+        );
+        assert_eq!(vals[7].unwrap(), &Number::new_unitless(1000.0).to_hayson());
+        assert_eq!(vals[8].unwrap(), &Number::new_unitless(5.4e-3).to_hayson());
+        assert_eq!(
+            vals[9].unwrap(),
+            &Number::new_unitless(f64::INFINITY).to_hayson()
+        );
+        assert_eq!(
+            vals[10].unwrap(),
+            &Number::new_unitless(f64::NEG_INFINITY).to_hayson()
+        );
+        assert_eq!(vals[11].unwrap(), "a \"quoted\" \u{e9}\n $");
+        assert_eq!(
+            vals[12].unwrap(),
+            &Uri::new("http://host/a`b".to_owned()).to_hayson()
+        );
+
+        let hs_ref = vals[13].unwrap();
+        assert_eq!(
+            Ref::from_hayson(hs_ref).unwrap(),
+            Ref::new("@p:demo:r:23a1".to_owned()).unwrap()
+        );
+        assert_eq!(hs_ref["dis"], "Site");
+
+        assert_eq!(
+            vals[14].unwrap(),
+            &Symbol::new("^hot-water".to_owned()).unwrap().to_hayson()
+        );
+        assert_eq!(
+            vals[15].unwrap(),
+            &Date::new(NaiveDate::from_ymd_opt(2021, 2, 3).unwrap())
+                .to_hayson()
+        );
+        assert_eq!(
+            vals[16].unwrap(),
+            &Time::new(NaiveTime::from_hms_milli_opt(4, 5, 6, 500).unwrap())
+                .to_hayson()
+        );
+
+        let sydney = DateTime::from_hayson(vals[17].unwrap()).unwrap();
+        assert_eq!(sydney.time_zone(), "Australia/Sydney");
+        assert_eq!(
+            sydney.date_time().to_rfc3339(),
+            "2021-02-03T04:05:06+11:00"
+        );
+
+        let utc = DateTime::from_hayson(vals[18].unwrap()).unwrap();
+        assert_eq!(utc.short_time_zone(), "UTC");
+
+        assert_eq!(vals[19].unwrap(), &Coord::new(37.55, -77.45).to_hayson());
+        assert_eq!(
+            vals[20].unwrap(),
+            &Xstr::new("Color".to_owned(), "red".to_owned()).to_hayson()
+        );
+    }
+
+    #[test]
+    fn parse_col_meta_and_collections() {
+        let zinc = "ver:\"3.0\" hisStart:2021-01-01\n\
+                    id, v0 id:@a unit:\"kW\" summary\n\
+                    @b,[1, \"two\", {x:3 flag}]\n";
+        let grid = parse_grid(zinc).unwrap();
+        assert_eq!(grid.cols()[0], json!({"name": "id"}));
+        assert_eq!(grid.cols()[1]["meta"]["unit"], "kW");
+        assert_eq!(
+            grid.cols()[1]["meta"]["summary"],
+            Marker::new().to_hayson()
+        );
+
+        let list = grid.rows()[0]["v0"].as_array().unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[1], "two");
+        assert_eq!(list[2]["flag"], Marker::new().to_hayson());
+        assert_eq!(list[2]["x"], Number::new_unitless(3.0).to_hayson());
+    }
+
+    #[test]
+    fn parse_nested_grid() {
+        let zinc = "ver:\"3.0\"\n\
+                    type,val\n\
+                    \"grid\",<<\n\
+                    ver:\"3.0\"\n\
+                    a,b\n\
+                    1,2\n\
+                    >>\n\
+                    \"str\",\"hello\"\n";
+        let grid = parse_grid(zinc).unwrap();
+        assert_eq!(grid.size(), 2);
+        let nested: Grid = grid.rows()[0]["val"].clone().try_into().unwrap();
+        assert_eq!(nested.col_name_strs(), vec!["a", "b"]);
+        assert_eq!(
+            nested.rows()[0]["b"],
+            Number::new_unitless(2.0).to_hayson()
+        );
+        assert_eq!(grid.rows()[1]["val"], "hello");
+    }
+
+    #[test]
+    fn parse_empty_grid() {
+        let grid = parse_grid("ver:\"3.0\"\nempty\n").unwrap();
+        assert!(grid.cols().is_empty());
+        assert!(grid.is_empty());
+    }
+
+    #[test]
+    fn parse_with_windows_line_endings() {
+        let grid = parse_grid("ver:\"3.0\"\r\na,b\r\n1,2\r\n").unwrap();
+        assert_eq!(grid.size(), 1);
+    }
+
+    #[test]
+    fn parse_invalid_zinc_fails() {
+        assert!(parse_grid("").is_err());
+        assert!(parse_grid("a,b\n1,2\n").is_err());
+        assert!(parse_grid("ver:\"3.0\"\na,b\n1\n").is_err());
+        assert!(parse_grid("ver:\"3.0\"\na\n\"unterminated\n").is_err());
+        assert!(parse_grid("ver:\"3.0\"\na\nUnknown\n").is_err());
+        assert!(
+            parse_grid("ver:\"3.0\"\na\n2021-01-01T00:00:00+10:00\n").is_err()
+        );
+
+        let err = parse_grid("ver:\"3.0\"\na\n1\n?\n").unwrap_err();
+        assert!(err.to_string().starts_with("line 4"));
+    }
+
+    #[test]
+    fn write_grid() {
+        let rows = vec![
+            json!({"id": Ref::new("@abc".to_owned()).unwrap().to_hayson(), "site": Marker::new().to_hayson()}),
+            json!({"dis": "Line 1\nLine 2", "area": Number::new(100.0, Some("ft²".to_owned())).to_hayson()}),
+        ];
+        let mut grid = Grid::new(rows).unwrap();
+        grid.add_to_meta("dis", json!("Sites"));
+
+        let zinc = grid.to_zinc_string();
+        assert_eq!(
+            zinc,
+            "ver:\"3.0\" dis:\"Sites\"\n\
+             area,dis,id,site\n\
+             ,,@abc,M\n\
+             100ft²,\"Line 1\\nLine 2\",,\n"
+        );
+    }
+
+    #[test]
+    fn write_empty_grid() {
+        assert_eq!(Grid::empty().to_zinc_string(), "ver:\"3.0\"\nempty\n");
+        assert_eq!(round_trip(&Grid::empty()), Grid::empty());
+    }
+
+    #[test]
+    fn write_null_in_single_col_grid() {
+        let rows = vec![json!({"val": "a"}), json!({}), json!({"val": "b"})];
+        let grid = Grid::new(rows).unwrap();
+
+        assert_eq!(
+            grid.to_zinc_string(),
+            "ver:\"3.0\"\nval\n\"a\"\nN\n\"b\"\n"
+        );
+        assert_eq!(round_trip(&grid), grid);
+    }
+
+    #[test]
+    fn round_trip_all_types() {
+        let date_time: DateTime = chrono::DateTime::parse_from_rfc3339(
+            "2021-06-01T12:30:00.123+10:00",
+        )
+        .unwrap()
+        .with_timezone(&chrono_tz::Australia::Sydney)
+        .into();
+        let mut dis_ref =
+            Ref::new("@p:demo:r:1".to_owned()).unwrap().to_hayson();
+        dis_ref["dis"] = json!("Demo $ite");
+
+        let values = vec![
+            json!(true),
+            json!(false),
+            json!(12),
+            json!("str with \"quotes\" and \\ and \t"),
+            json!([1, "a", [Marker::new().to_hayson()]]),
+            json!({"a": 1, "b": Marker::new().to_hayson(), "c": {"d": "e"}}),
+            Coord::new(-33.5, 151.25).to_hayson(),
+            Date::new(NaiveDate::from_ymd_opt(2021, 6, 1).unwrap()).to_hayson(),
+            date_time.to_hayson(),
+            Marker::new().to_hayson(),
+            Na::new().to_hayson(),
+            Number::new(-1.5, Some("°C".to_owned())).to_hayson(),
+            Number::new(2.5e20, Some("kWh".to_owned())).to_hayson(),
+            Number::new_unitless(f64::NEG_INFINITY).to_hayson(),
+            Ref::new("@abc".to_owned()).unwrap().to_hayson(),
+            dis_ref,
+            RemoveMarker::new().to_hayson(),
+            Symbol::new("^elec-meter".to_owned()).unwrap().to_hayson(),
+            Time::new(NaiveTime::from_hms_opt(23, 59, 1).unwrap()).to_hayson(),
+            Uri::new("http://a.com/b?c=`d`".to_owned()).to_hayson(),
+            Xstr::new("Bin".to_owned(), "text/plain".to_owned()).to_hayson(),
+            serde_json::from_str(
+                &Grid::new(vec![json!({"x": 1})]).unwrap().to_json_string(),
+            )
+            .unwrap(),
+        ];
+        let rows = values.iter().map(|val| json!({ "val": val })).collect();
+        let grid = Grid::new(rows).unwrap();
+
+        let parsed = round_trip(&grid);
+        assert_eq!(parsed.size(), values.len());
+
+        for (index, value) in values.iter().enumerate() {
+            let parsed_value = &parsed.rows()[index]["val"];
+            match value["_kind"].as_str() {
+                // Plain JSON numbers are parsed as Hayson numbers:
+                None if value.is_number() => assert_eq!(
+                    parsed_value,
+                    &Number::new_unitless(12.0).to_hayson()
+                ),
+                Some("dict") | None if value.is_object() => {
+                    assert_eq!(parsed_value["c"], value["c"]);
+                    assert_eq!(parsed_value["b"], value["b"]);
+                }
+                Some("grid") => {
+                    let nested: Grid = parsed_value.clone().try_into().unwrap();
+                    assert_eq!(nested.col_name_strs(), vec!["x"]);
+                }
+                Some("dateTime") => assert_eq!(
+                    DateTime::from_hayson(parsed_value).unwrap(),
+                    date_time
+                ),
+                _ if value.is_array() => {
+                    assert_eq!(parsed_value[1], "a");
+                    assert_eq!(
+                        parsed_value[2],
+                        json!([Marker::new().to_hayson()])
+                    );
+                }
+                _ => assert_eq!(parsed_value, value),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_col_meta() {
+        let json = json!({
+            "_kind": "grid",
+            "meta": {"ver": "3.0", "view": "table", "hisStart": Date::new(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap()).to_hayson()},
+            "cols": [
+                {"name": "ts", "meta": {"tz": "Sydney"}},
+                {"name": "v0", "meta": {"id": Ref::new("@a".to_owned()).unwrap().to_hayson(), "kind": "Number"}},
+            ],
+            "rows": [],
+        });
+        let grid: Grid = json.try_into().unwrap();
+        assert_eq!(round_trip(&grid), grid);
+    }
+}